@@ -1,21 +1,24 @@
 use std::collections::{HashSet, hash_map};
 
+use itertools::Itertools;
 use js_sys::JsString;
-use screeps::{HasPosition, OwnedStructureProperties, RoomName, find, game};
+use screeps::{CircleStyle, HasPosition, OwnedStructureProperties, Room, RoomName, find, game};
 use log::{info, warn};
+use anyhow::anyhow;
 use tap::Tap;
 
-use crate::{colony::{ColonyView, plan::ColonyPlan, steps::ColonyStep}, commands::{Command, handle_commands, pop_command}, memory::Memory, statemachine::step, visuals::{RoomDrawerType, draw_in_room_replaced}};
+use crate::{colony::{Colony, ColonyView, DEFAULT_PERIMETER_STEP, PlannerStrategy, RoomDescription, steps::ColonyStep, plan::{ColonyPlan, PlanMigration}, planner::PLAN_VERSION, progress::ColonyProgress, remote::REMOTE_MINING_STEP}, commands::{Command, handle_commands, pop_command}, memory::Memory, movement::traffic::RoomTraffic, visuals::{RoomDrawerType, draw_in_room_replaced}};
+
+// Planning a room takes several ticks worth of CPU, which is paid from the bucket
+const MIN_PLANNING_BUCKET: i32 = 1000;
+
+// Ramparts and walls can't be built before RCL2, so a perimeter planned any earlier can't be placed
+const MIN_PERIMETER_LEVEL: u8 = 2;
 
 pub fn update_colonies(mem: &mut Memory) {
     info!("Updating rooms...");
 
-    handle_commands(|command| {
-        let Command::ResetColony { room: name } = command else { return false; };
-        let Ok(name) = RoomName::new(name) else { return true; };
-        mem.colonies.0.remove(&name);
-        true
-    });
+    handle_colony_commands(mem);
 
     let prev_colonies: HashSet<_> = mem.colonies.0.keys().copied().collect();
     let curr_colonies: HashSet<_> = game::rooms().entries()
@@ -42,23 +45,34 @@ pub fn update_colonies(mem: &mut Memory) {
     let lost_colonies = prev_colonies.difference(&curr_colonies);
     for room in lost_colonies {
         mem.colonies.0.remove(room);
+        mem.detached_remotes.remove(room);
         mem.truck_coordinators.remove(room);
         mem.fabricator_coordinators.remove(room);
         warn!("Lost colony {room}");
     }
 
+    let mut has_replanned = false;
     for name in curr_colonies {
         let room = game::rooms().get(name).unwrap();
+        let strategy = mem.planner_strategies.get(&name).copied().unwrap_or_default();
+        let perimeter_step = mem.perimeter_steps.get(&name).copied().unwrap_or(DEFAULT_PERIMETER_STEP);
 
         if let hash_map::Entry::Vacant(e) = mem.colonies.0.entry(name) {
-            let plan = ColonyPlan::create_for(&room);
+            if game::cpu::bucket() < MIN_PLANNING_BUCKET {
+                info!("Waiting for buckets {}/{MIN_PLANNING_BUCKET} to plan {name}", game::cpu::bucket());
+                continue;
+            }
+
+            let plan = RoomDescription::of_room(&room)
+                .and_then(|description| ColonyPlan::create_from(&description.with_traffic(mem.movement.traffic.room(name)), strategy, perimeter_step));
             let Ok(plan) = plan else {
                 let Err(err) = plan else { unreachable!() };
                 warn!("Unable to create plan for {name}: {err}");
                 continue;
             };
 
-            let diff = plan.diff_with(&room);
+            let remote_roads = mem.detached_remotes.get(&name).map(|remotes| remotes.home_roads(name)).unwrap_or_default();
+            let diff = plan.diff_with(&room, &remote_roads);
             if !diff.compatible() {
                 if pop_command(Command::MigrateColony { room: name.to_string() }) {
                     info!("Migrating {name}");
@@ -75,28 +89,180 @@ pub fn update_colonies(mem: &mut Memory) {
 
             let plan = plan.tap_mut(|plan| plan.adapt_build_times_to(&room));
 
-            e.insert((plan, ColonyStep::default()));
+            e.insert(Colony { remotes: mem.detached_remotes.remove(&name).unwrap_or_default(), ..Colony::new(plan) });
+        }
+
+        let colony = mem.colonies.0.get_mut(&name).unwrap();
+
+        if !has_replanned && !colony.pinned && colony.plan_version != PLAN_VERSION && game::cpu::bucket() >= MIN_PLANNING_BUCKET {
+            has_replanned = true;
+            replan(colony, &room, strategy, perimeter_step, mem.movement.traffic.room(name));
         }
 
         if pop_command(Command::ResetColonyStep { room: name.to_string() }) {
-            mem.colonies.0.get_mut(&name).unwrap().1 = ColonyStep::default();
+            colony.progress = ColonyProgress::default();
         }
 
         if pop_command(Command::VisualizePlan { room: name.to_string(), animate: false }) {
-            let plan_clone = mem.colonies.0.get(&name).unwrap().0.clone();
+            let plan_clone = colony.plan.clone();
             draw_in_room_replaced(name, RoomDrawerType::Plan, move |visuals| plan_clone.draw_until(visuals, None));
         }
 
         if pop_command(Command::VisualizePlan { room: name.to_string(), animate: true }) {
-            let plan_clone = mem.colonies.0.get(&name).unwrap().0.clone();
+            let plan_clone = colony.plan.clone();
             plan_clone.draw_progression(name);
         }
 
+        if pop_command(Command::ExportPlan { room: name.to_string() }) {
+            match RoomDescription::of_room(&room).and_then(|description| colony.plan.export(&description)) {
+                Ok(data) => info!("Plan for {name}: {data}"),
+                Err(err) => warn!("Unable to export plan for {name}: {err}")
+            }
+        }
+
+        if let Some(migration) = &mut colony.migration && migration.update(&colony.plan, &room, &colony.remotes.home_roads(name)) {
+            info!("Finished migrating {name} to plan version {}", colony.plan_version);
+            colony.migration = None;
+            colony.progress = ColonyProgress::default();
+        }
+
+        let progress = colony.progress.clone();
+        let view = ColonyView::new(room.clone(), colony);
+        let progress = progress.update(&room, &view);
 
-        let (plan, stp) = mem.colonies.0.get_mut(&name).unwrap();
-        let view = ColonyView::new(room.clone(), plan, *stp);
-        step(stp, |stp| stp.update(&room, &view));
+        info!("{name} is working on {:?}", progress.active_goals(view.controller.level()).collect_vec());
+        colony.progress = progress;
 
-        info!("{name} is at step {stp:?}");
+        if colony.progress.has_reached(REMOTE_MINING_STEP) {
+            colony.remotes.update(&room, &colony.plan);
+        }
     }
 }
+
+fn handle_colony_commands(mem: &mut Memory) {
+    handle_commands(|command| {
+        let Command::ResetColony { room: name } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+        if let Some(colony) = mem.colonies.0.remove(&name) {
+            mem.detached_remotes.insert(name, colony.remotes);
+        }
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::SetPlannerStrategy { room: name, strategy } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+
+        if let Some(strategy) = strategy {
+            mem.planner_strategies.insert(name, *strategy);
+        } else {
+            mem.planner_strategies.remove(&name);
+        }
+
+        if let Some(colony) = mem.colonies.0.get_mut(&name) {
+            colony.plan_version = 0;
+            colony.pinned = false;
+        }
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::SetPerimeterStep { room: name, step } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+
+        if let Some(step) = step {
+            if step.controller_level() < MIN_PERIMETER_LEVEL {
+                warn!("Unable to plan the perimeter of {name} at {step:?}, it needs RCL{MIN_PERIMETER_LEVEL}");
+                return true;
+            }
+
+            mem.perimeter_steps.insert(name, *step);
+        } else {
+            mem.perimeter_steps.remove(&name);
+        }
+
+        // Imported plans stay pinned, the new step is used once they are replanned
+        if let Some(colony) = mem.colonies.0.get_mut(&name) {
+            colony.plan_version = 0;
+        }
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::Replan { room: name } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+
+        if let Some(colony) = mem.colonies.0.get_mut(&name) {
+            colony.plan_version = 0;
+            colony.pinned = false;
+        }
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::VisualizeTraffic { room: name } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+        let Some(traffic) = mem.movement.traffic.room(name).cloned() else {
+            warn!("No traffic recorded in {name}");
+            return true;
+        };
+
+        let unused_roads = mem.colonies.0.get(&name).map(|colony| colony.plan.unused_roads(&traffic)).unwrap_or_default();
+        draw_in_room_replaced(name, RoomDrawerType::Traffic, move |visuals| {
+            traffic.draw(visuals);
+            for road in &unused_roads {
+                visuals.circle(f32::from(road.x.u8()), f32::from(road.y.u8()), Some(CircleStyle::default().radius(0.2).fill("#ff4747")));
+            }
+        });
+
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::ImportPlan { room: name, data } = command else { return false; };
+        let Ok(name) = RoomName::new(name) else { return true; };
+
+        if let Err(err) = import_plan(mem, name, data) {
+            warn!("Unable to import plan for {name}: {err}");
+        }
+
+        true
+    });
+}
+
+fn import_plan(mem: &mut Memory, name: RoomName, data: &str) -> anyhow::Result<()> {
+    let colony = mem.colonies.0.get_mut(&name).ok_or(anyhow!("{name} is not a colony"))?;
+    let room = game::rooms().get(name).ok_or(anyhow!("{name} is not visible"))?;
+    let plan = ColonyPlan::import(data, &RoomDescription::of_room(&room)?)?;
+
+    let migration = PlanMigration::between(&colony.plan, &plan, colony.migration.take());
+    info!("Imported plan for {name}, migrating with {} removals", migration.removals.len());
+
+    colony.plan = plan;
+    colony.progress = ColonyProgress::default();
+    colony.plan_version = PLAN_VERSION;
+    colony.pinned = true;
+    colony.migration = Some(migration);
+    Ok(())
+}
+
+fn replan(colony: &mut Colony, room: &Room, strategy: PlannerStrategy, perimeter_step: ColonyStep, traffic: Option<&RoomTraffic>) {
+    let name = room.name();
+    colony.plan_version = PLAN_VERSION;
+
+    let plan = RoomDescription::of_room(room).and_then(|description| ColonyPlan::create_from(&description.with_traffic(traffic), strategy, perimeter_step));
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(err) => {
+            warn!("Unable to replan {name}, keeping the current plan: {err}");
+            return;
+        }
+    };
+
+    let migration = PlanMigration::between(&colony.plan, &plan, colony.migration.take());
+    info!("Migrating {name} to plan version {PLAN_VERSION} with {} removals", migration.removals.len());
+
+    colony.plan = plan;
+    colony.progress = ColonyProgress::default();
+    colony.migration = Some(migration);
+}
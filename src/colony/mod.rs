@@ -1,24 +1,63 @@
 use std::{collections::HashMap, fmt::Display};
 
 use derive_where::derive_where;
-use screeps::{HasPosition, Position, Room, RoomName, Store, StructureContainer, StructureController, StructureStorage, game};
+use screeps::{HasPosition, Position, Room, RoomName, Source, Store, StructureContainer, StructureController, StructureStorage, game};
 use serde::{Deserialize, Serialize};
 
-use crate::{check::{Check, CheckFrom}, colony::{plan::ColonyPlan, steps::ColonyStep}, domain_traits::{HasId, HasStore, ObjectId, ResolvableId, Transferable, Withdrawable}, ids::{CheckState, Checked, Unchecked}};
+use crate::{check::{Check, CheckFrom}, colony::{plan::{ColonyPlan, PlanMigration, SourcePlan}, planner::PLAN_VERSION, progress::ColonyProgress, remote::Remotes}, domain_traits::{HasId, HasStore, ObjectId, ResolvableId, Transferable, Withdrawable}, ids::{CheckState, Checked, Unchecked}};
 
 mod lifecycle;
 pub mod plan;
 mod planner;
+pub mod progress;
+pub mod remote;
 pub mod steps;
 
 pub use lifecycle::update_colonies;
+pub use planner::{DEFAULT_PERIMETER_STEP, PlannerStrategy, room::RoomDescription};
 
 #[derive(Serialize, Deserialize, Default)]
-pub struct Colonies(HashMap<RoomName, (ColonyPlan, ColonyStep)>);
+pub struct Colonies(HashMap<RoomName, Colony>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "SerializedColony")]
+pub struct Colony {
+    pub plan: ColonyPlan,
+    pub progress: ColonyProgress,
+    pub plan_version: u32,
+    // Imported plans are kept through planner changes, until a replan is asked for
+    pub pinned: bool,
+    pub migration: Option<PlanMigration>,
+    pub remotes: Remotes
+}
+
+impl Colony {
+    pub fn new(plan: ColonyPlan) -> Self {
+        Self { plan, progress: ColonyProgress::default(), plan_version: PLAN_VERSION, pinned: false, migration: None, remotes: Remotes::default() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedColony {
+    Versioned { plan: ColonyPlan, #[serde(alias = "step")] progress: ColonyProgress, plan_version: u32, #[serde(default)] pinned: bool, migration: Option<PlanMigration>, #[serde(default)] remotes: Remotes },
+    Unversioned(ColonyPlan, ColonyProgress)
+}
+
+impl From<SerializedColony> for Colony {
+    fn from(colony: SerializedColony) -> Self {
+        match colony {
+            SerializedColony::Versioned { plan, progress, plan_version, pinned, migration, remotes } => Self { plan, progress, plan_version, pinned, migration, remotes },
+            SerializedColony::Unversioned(plan, progress) => Self { plan, progress, plan_version: 0, pinned: false, migration: None, remotes: Remotes::default() }
+        }
+    }
+}
 
 pub struct ColonyView<'mem> {
     pub plan: &'mem ColonyPlan,
-    pub step: ColonyStep,
+    pub progress: &'mem ColonyProgress,
+    pub migration: Option<&'mem PlanMigration>,
+    pub remotes: &'mem Remotes,
     pub name: RoomName,
     pub room: Room,
     pub controller: StructureController,
@@ -33,13 +72,16 @@ impl Display for ColonyView<'_> {
 }
 
 impl<'mem> ColonyView<'mem> {
-    pub fn new(room: Room, plan: &'mem ColonyPlan, step: ColonyStep) -> Self {
+    pub fn new(room: Room, colony: &'mem Colony) -> Self {
+        let plan = &colony.plan;
         let buffer = plan.center.storage.resolve().map(|x| x.id()).map(ColonyBuffer::Storage)
             .or_else(|| plan.center.container_storage.resolve().map(|x| x.id()).map(ColonyBuffer::Container));
 
         ColonyView {
             plan,
-            step,
+            progress: &colony.progress,
+            migration: colony.migration.as_ref(),
+            remotes: &colony.remotes,
             name: room.name(),
             controller: room.controller().expect("Every colony should have a controller"),
             room,
@@ -47,19 +89,22 @@ impl<'mem> ColonyView<'mem> {
             center: plan.center.pos
         }
     }
+
+    pub fn source_plan(&self, source: screeps::ObjectId<Source>) -> Option<&'mem SourcePlan> {
+        self.plan.sources.get(&source).or_else(|| self.remotes.source_plan(source))
+    }
 }
 
 impl Colonies {
     pub fn view(&self, name: RoomName) -> Option<ColonyView<'_>> {
-        let (plan, step) = self.0.get(&name)?;
-        Some(ColonyView::new(game::rooms().get(name)?, plan, *step))
+        let colony = self.0.get(&name)?;
+        Some(ColonyView::new(game::rooms().get(name)?, colony))
     }
 
     pub fn view_all(&self) -> impl Iterator<Item = ColonyView<'_>> {
         self.0.keys().filter_map(|name| self.view(*name))
     }
 
-    #[expect(unused)]
     pub fn rooms(&self) -> impl Iterator<Item = RoomName> {
         self.0.keys().copied()
     }
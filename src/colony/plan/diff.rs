@@ -6,13 +6,38 @@ use screeps::{Room, RoomXY, StructureType};
 use crate::colony::plan::{ColonyPlan, get_all_roads_in, get_all_structures_in};
 
 impl ColonyPlan {
-    pub fn diff_with(&self, room: &Room) -> ColonyPlanDiff {
-        let planned_roads: HashSet<_> = self.steps.values()
-            .flat_map(|step| step.new_roads.iter().copied())
+    // Roads which are kept for something outside the plan, like the way to the remotes, are left out of the diff
+    pub fn diff_with(&self, room: &Room, kept_roads: &HashSet<RoomXY>) -> ColonyPlanDiff {
+        let all_roads = get_all_roads_in(room).into_keys()
+            .filter(|pos| !kept_roads.contains(pos))
+            .collect();
+        let all_structures = get_all_structures_in(room).into_iter()
+            .map(|(pos, (ty, _))| (pos, ty))
             .collect();
-        let all_roads: HashSet<_> = get_all_roads_in(room).into_keys().collect();
 
-        let missing_roads = planned_roads.difference(&all_roads)
+        self.diff_against(&all_roads, &all_structures)
+    }
+
+    pub fn diff_with_plan(&self, old: &ColonyPlan) -> ColonyPlanDiff {
+        self.diff_against(&old.all_roads(), &old.all_structures())
+    }
+
+    pub fn all_roads(&self) -> HashSet<RoomXY> {
+        self.steps.values()
+            .flat_map(|step| step.new_roads.iter().copied())
+            .collect()
+    }
+
+    pub fn all_structures(&self) -> HashMap<RoomXY, StructureType> {
+        self.steps.values()
+            .flat_map(|step| step.new_structures.iter().map(|(a, b)| (*a, *b)))
+            .collect()
+    }
+
+    fn diff_against(&self, all_roads: &HashSet<RoomXY>, all_structures: &HashMap<RoomXY, StructureType>) -> ColonyPlanDiff {
+        let planned_roads = self.all_roads();
+
+        let missing_roads = planned_roads.difference(all_roads)
             .map(|pos| (*pos, RoadDiff::Missing));
 
         let extra_roads = all_roads.difference(&planned_roads)
@@ -20,10 +45,7 @@ impl ColonyPlan {
 
         let road_diff: HashMap<_, _> = missing_roads.chain(extra_roads).collect();
 
-        let planned_structures: HashMap<_, _> = self.steps.values()
-            .flat_map(|step| step.new_structures.iter().map(|(a, b)| (*a, *b)))
-            .collect();
-        let all_structures = get_all_structures_in(room);
+        let planned_structures = self.all_structures();
 
         let planned_structure_positions: HashSet<_> = planned_structures.keys().copied().collect();
         let all_structure_positions: HashSet<_> = all_structures.keys().copied().collect();
@@ -32,12 +54,12 @@ impl ColonyPlan {
             .map(|pos| (*pos, StructureDiff::Missing(planned_structures[pos])));
 
         let extra_structures = all_structure_positions.difference(&planned_structure_positions)
-            .map(|pos| (*pos, all_structures[pos].0))
+            .map(|pos| (*pos, all_structures[pos]))
             .filter(|(_, structure)| *structure != StructureType::Controller)
             .map(|(pos, structure)| (pos, StructureDiff::Extra(structure)));
 
         let different_structures = all_structure_positions.intersection(&planned_structure_positions)
-            .map(|pos| (*pos, planned_structures[pos], all_structures[pos].0))
+            .map(|pos| (*pos, planned_structures[pos], all_structures[pos]))
             .filter(|(_, expected, found)| *expected != *found)
             .map(|(pos, expected, found)| (pos, StructureDiff::Different { expected, found }));
 
@@ -6,7 +6,7 @@ use screeps::{Position, Room, RoomName, StructureType, look};
 use anyhow::anyhow;
 use strum::IntoEnumIterator;
 
-use crate::colony::{plan::{ColonyPlan, ColonyPlanDiff, ColonyPlanStep, RoadDiff, StructureDiff, get_all_roads_in, get_all_structures_in}, steps::ColonyStep};
+use crate::colony::{plan::{ColonyPlan, ColonyPlanDiff, ColonyPlanStep, PlanMigration, RoadDiff, StructureDiff, get_all_roads_in, get_all_structures_in, migration::remove_structure}, steps::ColonyStep};
 
 impl ColonyPlan {
     pub fn adapt_build_times_to(&mut self, room: &Room) {
@@ -40,12 +40,12 @@ impl ColonyPlan {
 }
 
 impl ColonyPlanStep {
-    pub fn build(&self, room: &Room) -> anyhow::Result<bool> {
+    pub fn build(&self, room: &Room, migration: Option<&PlanMigration>) -> anyhow::Result<bool> {
         let roads = get_all_roads_in(room);
         let roads_set: HashSet<_> = roads.keys().copied().collect();
         let missing_roads = self.new_roads.difference(&roads_set).copied().collect_vec();
 
-        for road in &missing_roads {
+        for road in missing_roads.iter().filter(|road| migration.is_none_or(|migration| !migration.removals.contains_key(*road))) {
             Position::new(road.x, road.y, room.name()).create_construction_site(StructureType::Road, None)?;
         }
 
@@ -64,19 +64,23 @@ impl ColonyPlanStep {
 
         let missing_structure_keys: HashSet<_> = missing_structures.keys().copied().collect();
         let all_structure_keys: HashSet<_> = all_structures.keys().copied().collect();
-        let overlap = all_structure_keys.intersection(&missing_structure_keys).collect_vec();
+        let overlap: HashSet<_> = all_structure_keys.intersection(&missing_structure_keys).copied().collect();
+        let unexpected_overlap = overlap.iter()
+            .filter(|pos| migration.is_none_or(|migration| !migration.is_pending(**pos)))
+            .collect_vec();
 
-        if !overlap.is_empty() {
+        if !unexpected_overlap.is_empty() {
             warn!("Found structure overlap in {}:", room.name());
-            for pos in overlap {
+            for pos in unexpected_overlap {
                 warn!("For {:?} at {pos}", missing_structures[pos]);
             }
 
             return Err(anyhow!("Structure overlap"))
         }
 
-        for (pos, ty) in &missing_structures {
+        for (pos, ty) in missing_structures.iter().filter(|(pos, _)| !overlap.contains(*pos)) {
             let pos = Position::new(pos.x, pos.y, room.name());
+            if migration.is_some_and(|migration| migration.road_removals.contains(&pos.xy())) { continue; }
             if pos.look_for(look::CONSTRUCTION_SITES).ok().is_none_or(|sites| sites.is_empty()) {
                 pos.create_construction_site(*ty, None).map_err(|e| anyhow!("Unable to create structure {ty} at {pos}: {e}"))?;
             }
@@ -104,15 +108,7 @@ impl ColonyPlanDiff {
             });
 
         for (pos, ty) in road_removals.chain(structure_removals) {
-            let pos = Position::new(pos.x, pos.y, room);
-
-            let structure = pos.look_for(look::STRUCTURES).unwrap().into_iter()
-                .find(|structure| structure.structure_type() == ty);
-            if let Some(structure) = structure { structure.as_structure().destroy().ok(); }
-
-            let site = pos.look_for(look::CONSTRUCTION_SITES).unwrap().into_iter()
-                .find(|site| site.structure_type() == ty);
-            if let Some(site) = site { site.remove().ok(); }
+            remove_structure(Position::new(pos.x, pos.y, room), ty);
         }
     }
 }
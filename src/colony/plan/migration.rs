@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use log::info;
+use screeps::{Position, Room, RoomXY, StructureType, look};
+use serde::{Deserialize, Serialize};
+use serde_json_any_key::any_key_map;
+
+use crate::colony::plan::{ColonyPlan, ColonyPlanDiff, RoadDiff, StructureDiff, get_all_roads_in, get_all_structures_in};
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PlanMigration {
+    #[serde(with = "any_key_map")]
+    pub removals: HashMap<RoomXY, StructureType>,
+    pub road_removals: HashSet<RoomXY>
+}
+
+impl PlanMigration {
+    pub fn between(old: &ColonyPlan, new: &ColonyPlan, pending: Option<PlanMigration>) -> Self {
+        let mut migration = Self::from_diff(&new.diff_with_plan(old));
+        let Some(pending) = pending else { return migration };
+
+        let planned_structures = new.all_structures();
+        let planned_roads = new.all_roads();
+
+        migration.removals.extend(pending.removals.into_iter().filter(|(pos, ty)| planned_structures.get(pos) != Some(ty)));
+        migration.road_removals.extend(pending.road_removals.into_iter().filter(|pos| !planned_roads.contains(pos)));
+        migration
+    }
+
+    fn from_diff(diff: &ColonyPlanDiff) -> Self {
+        let removals = diff.structures.iter()
+            .filter_map(|(pos, diff)| match diff {
+                StructureDiff::Missing(_) => None,
+                StructureDiff::Extra(found) |
+                StructureDiff::Different { expected: _, found } => Some((*pos, *found))
+            }).collect();
+
+        let road_removals = diff.roads.iter()
+            .filter(|(_, diff)| matches!(diff, RoadDiff::Extra))
+            .map(|(pos, _)| *pos)
+            .collect();
+
+        Self { removals, road_removals }
+    }
+
+    pub fn is_pending(&self, pos: RoomXY) -> bool {
+        self.removals.contains_key(&pos) || self.road_removals.contains(&pos)
+    }
+
+    pub fn update(&mut self, plan: &ColonyPlan, room: &Room, kept_roads: &HashSet<RoomXY>) -> bool {
+        let Some(controller) = room.controller() else { return false };
+        let level = controller.level();
+
+        let structures = get_all_structures_in(room);
+        let roads = get_all_roads_in(room);
+
+        self.removals.retain(|pos, ty| structures.get(pos).is_some_and(|(found, _)| found == ty));
+        self.road_removals.retain(|pos| roads.contains_key(pos) && !kept_roads.contains(pos));
+
+        let buildable: HashMap<_, _> = plan.steps.iter()
+            .filter(|(step, _)| step.controller_level() <= level)
+            .flat_map(|(_, step)| step.new_structures.iter().map(|(pos, ty)| (*pos, *ty)))
+            .collect();
+
+        let blocking_roads = self.road_removals.iter()
+            .filter(|pos| buildable.get(pos).is_some_and(|ty| !matches!(ty, StructureType::Rampart | StructureType::Container)))
+            .copied()
+            .collect_vec();
+
+        for pos in blocking_roads {
+            remove_structure(Position::new(pos.x, pos.y, room.name()), StructureType::Road);
+            self.road_removals.remove(&pos);
+        }
+
+        let removable = self.removable(&structures, &buildable, level);
+
+        for (pos, ty) in removable {
+            info!("Removing {ty} at {pos} in {} for the new plan", room.name());
+            remove_structure(Position::new(pos.x, pos.y, room.name()), ty);
+            self.removals.remove(&pos);
+        }
+
+        if !self.removals.is_empty() { return false }
+
+        for pos in self.road_removals.drain() {
+            remove_structure(Position::new(pos.x, pos.y, room.name()), StructureType::Road);
+        }
+
+        true
+    }
+
+    fn removable(&self, structures: &HashMap<RoomXY, (StructureType, bool)>, buildable: &HashMap<RoomXY, StructureType>, level: u8) -> Vec<(RoomXY, StructureType)> {
+        let mut removed_types = HashSet::new();
+        self.removals.iter()
+            .sorted_by_key(|(pos, _)| **pos)
+            .filter(|(pos, ty)| {
+                let is_site = structures.get(pos).is_some_and(|(_, built)| !*built);
+                if is_site { return true }
+
+                let replaced = buildable.iter()
+                    .filter(|(pos, planned)| planned == ty && !self.is_pending(**pos))
+                    .all(|(pos, planned)| structures.get(pos).is_some_and(|(found, built)| found == planned && *built));
+                if replaced { return true }
+
+                // Once capped, replacements can't get a construction site, so one structure at a time makes room for them.
+                // Spawns and extensions are kept until the cap rises instead, so the colony never loses spawn capacity
+                if matches!(ty, StructureType::Spawn | StructureType::Extension) { return false }
+
+                let built = structures.values().filter(|(found, built)| found == *ty && *built).count() as u32;
+                built >= ty.controller_structures(u32::from(level)) && built > 1 && removed_types.insert(**ty)
+            })
+            .map(|(pos, ty)| (*pos, *ty))
+            .collect()
+    }
+}
+
+pub fn remove_structure(pos: Position, ty: StructureType) {
+    let structure = pos.look_for(look::STRUCTURES).unwrap().into_iter()
+        .find(|structure| structure.structure_type() == ty);
+    if let Some(structure) = structure { structure.as_structure().destroy().ok(); }
+
+    let site = pos.look_for(look::CONSTRUCTION_SITES).unwrap().into_iter()
+        .find(|site| site.structure_type() == ty);
+    if let Some(site) = site { site.remove().ok(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(x: u8, y: u8) -> RoomXY {
+        RoomXY::try_from((x, y)).unwrap()
+    }
+
+    fn with_removals(removals: &[(RoomXY, StructureType)]) -> PlanMigration {
+        PlanMigration { removals: removals.iter().copied().collect(), road_removals: HashSet::new() }
+    }
+
+    #[test]
+    fn removes_once_the_replacement_is_built() {
+        let migration = with_removals(&[(xy(10, 10), StructureType::Extension)]);
+        let buildable = HashMap::from([(xy(20, 20), StructureType::Extension)]);
+
+        let mut structures = HashMap::from([(xy(10, 10), (StructureType::Extension, true)), (xy(20, 20), (StructureType::Extension, false))]);
+        assert!(migration.removable(&structures, &buildable, 2).is_empty());
+
+        structures.insert(xy(20, 20), (StructureType::Extension, true));
+        assert_eq!(migration.removable(&structures, &buildable, 2), vec![(xy(10, 10), StructureType::Extension)]);
+    }
+
+    #[test]
+    fn keeps_capped_spawns_and_extensions() {
+        let mut structures = HashMap::from([(xy(10, 10), (StructureType::Spawn, true)), (xy(11, 10), (StructureType::Spawn, true))]);
+        let buildable = HashMap::from([(xy(10, 10), StructureType::Spawn), (xy(20, 20), StructureType::Spawn)]);
+
+        let migration = with_removals(&[(xy(11, 10), StructureType::Spawn)]);
+        assert!(migration.removable(&structures, &buildable, 7).is_empty());
+
+        structures.insert(xy(20, 20), (StructureType::Spawn, true));
+        assert_eq!(migration.removable(&structures, &buildable, 8), vec![(xy(11, 10), StructureType::Spawn)]);
+
+        let structures = (0..10).map(|x| (xy(x, 10), (StructureType::Extension, true))).collect();
+        let buildable = (1..10).map(|x| (xy(x, 10), StructureType::Extension)).chain([(xy(20, 20), StructureType::Extension)]).collect();
+        let migration = with_removals(&[(xy(0, 10), StructureType::Extension)]);
+        assert!(migration.removable(&structures, &buildable, 3).is_empty());
+    }
+
+    #[test]
+    fn moves_other_capped_types_one_at_a_time() {
+        let structures = HashMap::from([
+            (xy(10, 10), (StructureType::Tower, true)),
+            (xy(11, 10), (StructureType::Tower, true)),
+            (xy(12, 10), (StructureType::Tower, true)),
+        ]);
+        let buildable = HashMap::from([(xy(12, 10), StructureType::Tower), (xy(20, 20), StructureType::Tower), (xy(21, 20), StructureType::Tower)]);
+
+        let migration = with_removals(&[(xy(10, 10), StructureType::Tower), (xy(11, 10), StructureType::Tower)]);
+        assert_eq!(migration.removable(&structures, &buildable, 7), vec![(xy(10, 10), StructureType::Tower)]);
+    }
+
+    #[test]
+    fn removes_construction_sites_right_away() {
+        let structures = HashMap::from([(xy(10, 10), (StructureType::Spawn, false))]);
+        let buildable = HashMap::from([(xy(20, 20), StructureType::Spawn)]);
+
+        let migration = with_removals(&[(xy(10, 10), StructureType::Spawn)]);
+        assert_eq!(migration.removable(&structures, &buildable, 1), vec![(xy(10, 10), StructureType::Spawn)]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use screeps::{Direction, HasPosition, ObjectId, OwnedStructureProperties, Position, Room, RoomXY, Source, StructureContainer, StructureController, StructureExtension, StructureExtractor, StructureLink, StructureObject, StructureObserver, StructureSpawn, StructureStorage, StructureTerminal, StructureTower, StructureType, find};
+use screeps::{Direction, HasPosition, ObjectId, OwnedStructureProperties, Position, Room, RoomXY, Source, StructureContainer, StructureController, StructureExtension, StructureExtractor, StructureFactory, StructureLab, StructureLink, StructureNuker, StructureObject, StructureObserver, StructurePowerSpawn, StructureRampart, StructureSpawn, StructureStorage, StructureTerminal, StructureTower, StructureType, StructureWall, find};
 use serde::{Deserialize, Serialize};
 use serde_json_any_key::any_key_map;
 
@@ -8,10 +8,12 @@ use crate::colony::{plan::refs::{OptionalPlannedStructureRef, PlannedStructureRe
 
 mod diff;
 mod execute;
+mod migration;
 pub mod refs;
 mod visuals;
 
 pub use diff::{ColonyPlanDiff, RoadDiff, StructureDiff};
+pub use migration::PlanMigration;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ColonyPlan {
@@ -22,7 +24,12 @@ pub struct ColonyPlan {
     pub sources: SourcesPlan,
     pub center: CenterPlan,
     pub mineral: MineralPlan,
-    pub controller: PlannedStructureRef<StructureController>
+    // Plans from before the perimeter was planned get one when they are replanned
+    #[serde(default)]
+    pub perimeter: PerimeterPlan,
+    pub controller: ControllerPlan,
+    #[serde(default)]
+    pub entry_links: PlannedStructureRefs<StructureLink>
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -30,13 +37,25 @@ pub struct CenterPlan {
     pub pos: Position,
 
     pub spawn: PlannedStructureRef<StructureSpawn>,
+    #[serde(default)]
+    pub extra_spawns: PlannedStructureRefs<StructureSpawn>,
     pub storage: OptionalPlannedStructureRef<StructureStorage>,
     pub container_storage: OptionalPlannedStructureRef<StructureContainer>,
     pub link: OptionalPlannedStructureRef<StructureLink>,
     pub terminal: OptionalPlannedStructureRef<StructureTerminal>,
     pub observer: OptionalPlannedStructureRef<StructureObserver>,
+    #[serde(default)]
+    pub factory: OptionalPlannedStructureRef<StructureFactory>,
+    #[serde(default)]
+    pub power_spawn: OptionalPlannedStructureRef<StructurePowerSpawn>,
+    #[serde(default)]
+    pub nuker: OptionalPlannedStructureRef<StructureNuker>,
     pub towers: PlannedStructureRefs<StructureTower>,
-    pub extensions: PlannedStructureRefs<StructureExtension>
+    pub extensions: PlannedStructureRefs<StructureExtension>,
+    #[serde(default)]
+    pub input_labs: PlannedStructureRefs<StructureLab>,
+    #[serde(default)]
+    pub output_labs: PlannedStructureRefs<StructureLab>
 }
 
 pub type SourcesPlan = HashMap<ObjectId<Source>, SourcePlan>;
@@ -48,8 +67,15 @@ pub struct SourcePlan {
     pub link: OptionalPlannedStructureRef<StructureLink>,
     pub extensions: PlannedStructureRefs<StructureExtension>,
 
-    pub distance: u32,
-    pub spawn_direction: Direction
+    pub distance: u32
+}
+
+impl SourcePlan {
+    pub fn spawn_direction(&self) -> Option<Direction> {
+        let spawn = self.spawn.as_ref()?;
+        let container = self.container.as_ref()?;
+        spawn.pos.get_direction_to(container.pos)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -60,6 +86,49 @@ pub struct MineralPlan {
     pub distance: u32
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "SerializedControllerPlan")]
+pub struct ControllerPlan {
+    pub controller: PlannedStructureRef<StructureController>,
+    pub container: OptionalPlannedStructureRef<StructureContainer>,
+    pub link: OptionalPlannedStructureRef<StructureLink>,
+    pub standing_tiles: Vec<Position>
+}
+
+// Plans used to only hold the controller itself, the upgrade station is planned when they are replanned
+#[derive(Deserialize)]
+#[serde(untagged)]
+#[expect(clippy::large_enum_variant, reason = "Only lives while deserializing")]
+enum SerializedControllerPlan {
+    Station {
+        controller: PlannedStructureRef<StructureController>,
+        container: OptionalPlannedStructureRef<StructureContainer>,
+        link: OptionalPlannedStructureRef<StructureLink>,
+        standing_tiles: Vec<Position>
+    },
+    Controller(PlannedStructureRef<StructureController>)
+}
+
+impl From<SerializedControllerPlan> for ControllerPlan {
+    fn from(plan: SerializedControllerPlan) -> Self {
+        match plan {
+            SerializedControllerPlan::Station { controller, container, link, standing_tiles } => Self { controller, container, link, standing_tiles },
+            SerializedControllerPlan::Controller(controller) => Self {
+                controller,
+                container: OptionalPlannedStructureRef::default(),
+                link: OptionalPlannedStructureRef::default(),
+                standing_tiles: Vec::new()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PerimeterPlan {
+    pub ramparts: PlannedStructureRefs<StructureRampart>,
+    pub walls: PlannedStructureRefs<StructureWall>
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct ColonyPlanStep {
     pub new_roads: HashSet<RoomXY>,
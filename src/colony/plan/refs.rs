@@ -8,7 +8,8 @@ use serde::{Deserialize, de::DeserializeOwned};
 
 use crate::{check::FilterCheck, domain_traits::{ConstructionSiteId, HasCheckableId, HasId, HasResolvableId, ResolvableId}, ids::{CheckState, Checked, Unchecked}};
 
-#[derive_where(Serialize, Deserialize, Default, Clone; PlannedStructureRef<T>)]
+#[derive_where(Serialize, Deserialize, Clone; PlannedStructureRef<T>)]
+#[derive_where(Default)]
 #[derive(Deref)]
 pub struct PlannedStructureRefs<T: HasId>(pub Vec<PlannedStructureRef<T>>);
 
@@ -30,7 +31,8 @@ impl<T: HasResolvableId + HasStructureType> PlannedStructureRefs<T>
     }
 }
 
-#[derive_where(Serialize, Deserialize, Clone, Default; PlannedStructureRef<T>)]
+#[derive_where(Serialize, Deserialize, Clone; PlannedStructureRef<T>)]
+#[derive_where(Default)]
 #[derive(Deref)]
 pub struct OptionalPlannedStructureRef<T: HasId>(pub Option<PlannedStructureRef<T>>);
 
@@ -120,7 +122,6 @@ impl<T : HasResolvableId + HasStructureType> PlannedStructureRef<T>
         }
     }
 
-    #[expect(unused)]
     pub fn is_being_built(&self) -> bool {
         self.resolve_site().is_some()
     }
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use itertools::Itertools;
-use screeps::{CircleStyle, LineStyle, RoomName, RoomVisual, RoomXY, StructureType, TextAlign, TextStyle};
+use screeps::{CircleStyle, LineStyle, RectStyle, RoomName, RoomVisual, RoomXY, StructureType, TextAlign, TextStyle};
 use strum::IntoEnumIterator;
 
 use crate::{colony::{plan::{ColonyPlan, ColonyPlanDiff}, steps::ColonyStep}, visuals::{RoomDrawerType, draw_in_room_replaced}};
@@ -89,6 +89,9 @@ impl ColonyPlanDiff {
 pub fn draw_structure(visuals: &RoomVisual, pos: RoomXY, structure: StructureType) {
     if structure == StructureType::Extension {
         visuals.circle(f32::from(pos.x.u8()), f32::from(pos.y.u8()), Some(CircleStyle::default().radius(0.3).opacity(0.75).fill("#b05836")));
+    } else if matches!(structure, StructureType::Rampart | StructureType::Wall) {
+        let color = if structure == StructureType::Rampart { "#3c8c3c" } else { "#1e1e1e" };
+        visuals.rect(f32::from(pos.x.u8()) - 0.45, f32::from(pos.y.u8()) - 0.45, 0.9, 0.9, Some(RectStyle::default().opacity(0.5).fill(color)));
     } else {
         visuals.circle(f32::from(pos.x.u8()), f32::from(pos.y.u8()), Some(CircleStyle::default().radius(0.45).opacity(0.75).fill("#b05836")));
         visuals.text(f32::from(pos.x.u8()), f32::from(pos.y.u8()), structure.to_string(), Some(TextStyle::default().custom_font("0.35 Consolas").opacity(0.75).align(screeps::TextAlign::Center)));
@@ -1,10 +1,10 @@
 use std::{cmp::Reverse, collections::{HashMap, HashSet, VecDeque}};
 
 use itertools::Itertools;
-use screeps::{Direction, HasPosition, Position, Room, RoomXY, Terrain, find};
+use screeps::{Direction, RoomXY, Terrain};
 use anyhow::bail;
 
-use crate::{colony::{planner::{floodfill::{FloodFill, OrthogonalWalkableNeighs, StarWalkableNeighs, WalkableNeighs}, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep}, pathfinding, visuals::{RoomDrawerType, draw_in_room}};
+use crate::{colony::{planner::{MAX_CANDIDATES, MIN_CANDIDATE_DIST, MIN_ENTRANCE_DIST, floodfill::{FloodFill, OrthogonalWalkableNeighs, StarWalkableNeighs, WalkableNeighs}, pathing::shortest_path, room::RoomDescription, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep}, visuals::{RoomDrawerType, draw_in_room}};
 
 pub struct CenterPlanner {
     road_iter: FloodFill<StarWalkableNeighs>,
@@ -21,7 +21,7 @@ impl CenterPlanner {
         let mut visited_structure_positions = HashSet::new();
         let mut structure_queue = VecDeque::new();
 
-        let mut floodfill = FloodFill::new(vec![center], OrthogonalWalkableNeighs::new(planner.room.get_terrain())).peekable();
+        let mut floodfill = FloodFill::new(vec![center], OrthogonalWalkableNeighs::new(planner.terrain.clone())).peekable();
 
         while let Some((_, pos)) = floodfill.next_if(|(dist, _)| *dist < 2) {
             visited_structure_positions.insert(pos);
@@ -35,16 +35,26 @@ impl CenterPlanner {
 
         Self {
             storage_buffer_pos: seed[0],
-            road_iter: FloodFill::new(seed, StarWalkableNeighs::new(planner.room.get_terrain(), center)),
+            road_iter: FloodFill::new(seed, StarWalkableNeighs::new(planner.terrain.clone(), center)),
             placed_structures: HashMap::new(),
             roads: HashSet::new(),
             structure_queue,
         }
     }
 
-    pub fn next_structure_pos(&mut self, planner: &ColonyPlanner, step: ColonyStep) -> anyhow::Result<RoomXY> {
+    pub fn container_buffer_pos(&self) -> RoomXY { self.storage_buffer_pos }
+
+    pub fn plan_roads(self, planner: &mut ColonyPlanner) {
+        plan_serving_roads(planner, &self.roads, &self.placed_structures);
+    }
+}
+
+impl StructureSlots for CenterPlanner {
+    fn next_structure_pos(&mut self, planner: &ColonyPlanner, step: ColonyStep) -> anyhow::Result<RoomXY> {
         loop {
             if let Some(pos) = self.structure_queue.pop_front() {
+                if !planner.is_buildable_at(pos) { continue; }
+
                 self.placed_structures.insert(pos, step);
                 return Ok(pos) 
             }
@@ -52,7 +62,7 @@ impl CenterPlanner {
             let Some((_, road)) = self.road_iter.next() else { bail!("Not enough roads"); };
             if planner.pos2structure.contains_key(&road) { continue; }
 
-            draw_in_room(planner.room.name(), RoomDrawerType::Plan, move |visuals| {
+            draw_in_room(planner.room.name, RoomDrawerType::Plan, move |visuals| {
                 visuals.circle(road.x.u8().into(), road.y.u8().into(), None);
             });
 
@@ -69,27 +79,29 @@ impl CenterPlanner {
             );
         }
     }
+}
 
-    pub fn plan_structure(&mut self, planner: &mut ColonyPlanner, step: ColonyStep, structure: PlannedStructure) -> anyhow::Result<()> {
+pub trait StructureSlots {
+    fn next_structure_pos(&mut self, planner: &ColonyPlanner, step: ColonyStep) -> anyhow::Result<RoomXY>;
+
+    fn plan_structure(&mut self, planner: &mut ColonyPlanner, step: ColonyStep, structure: PlannedStructure) -> anyhow::Result<()> {
         let pos = self.next_structure_pos(planner, step)?;
         planner.plan_structure(pos, step, structure)
     }
+}
 
-    pub fn container_buffer_pos(&self) -> RoomXY { self.storage_buffer_pos }
-
-    pub fn plan_roads(self, planner: &mut ColonyPlanner) {
-        for road in &self.roads {
-            let Some(plan_step) = Direction::iter()
-                .filter_map(|dir| road.checked_add_direction(*dir))
-                .filter_map(|pos| self.placed_structures.get(&pos))
-                .sorted().nth(2) else { continue; };
+pub fn plan_serving_roads(planner: &mut ColonyPlanner, roads: &HashSet<RoomXY>, placed_structures: &HashMap<RoomXY, ColonyStep>) {
+    for road in roads {
+        let Some(plan_step) = Direction::iter()
+            .filter_map(|dir| road.checked_add_direction(*dir))
+            .filter_map(|pos| placed_structures.get(&pos))
+            .sorted().nth(2) else { continue; };
 
-            planner.plan_road(*road, *plan_step);
-        }
+        planner.plan_road(*road, *plan_step);
     }
 }
 
-pub fn plan_extensions_towers_observer(planner: &mut ColonyPlanner, center_planner: &mut CenterPlanner) -> anyhow::Result<()> {
+pub fn plan_extensions_towers_observer(planner: &mut ColonyPlanner, center_planner: &mut impl StructureSlots) -> anyhow::Result<()> {
     for controller_level in 1_u8..=8 {
         if controller_level == 8 {
             center_planner.plan_structure(planner, ColonyStep::BuildLvl8, PlannedStructure::Observer)?;
@@ -127,44 +139,40 @@ pub fn plan_extensions_towers_observer(planner: &mut ColonyPlanner, center_plann
     Ok(())
 }
 
-const MIN_ENTRANCE_DIST: usize = 8;
-const MIN_CANDIDATE_DIST: u8 = 4;
-pub fn find_center(room: &Room) -> RoomXY {
-    let center_flag = room.find(find::FLAGS, None).into_iter()
-        .find(|flag| flag.name().to_lowercase().contains("center"));
-    if let Some(center_flag) = center_flag { return center_flag.pos().xy() }
-
-    let exits = room.find(find::EXIT, None).into_iter()
-        .map(|pos| Position::from(pos).xy());
+pub fn find_center_candidates(room: &RoomDescription) -> Vec<RoomXY> {
+    if let Some(center) = room.center { return vec![center] }
 
-    let entrance_blocks = FloodFill::new(exits, WalkableNeighs::new(room.get_terrain()))
-        .take_while(|(dist, _)| *dist <= MIN_ENTRANCE_DIST)
+    let entrance_blocks = FloodFill::new(room.exits(), WalkableNeighs::new(room.terrain.clone()))
+        .take_while(|(dist, _)| *dist <= usize::from(MIN_ENTRANCE_DIST))
         .map(|(_, pos)| pos);
-        //.inspect(|candidate| { let (x,y) = (candidate.x.u8(), candidate.y.u8()); draw_in_room(room.name(), move |visual| visual.circle(x as f32, y as f32, Some(CircleStyle::default().radius(0.2).fill("#f53636")))); });
 
     let wall_blocks = (0..50).cartesian_product(0..50)
-        .filter(|(x, y)| room.get_terrain().get(*x, *y) == Terrain::Wall)
-        .map(|(x, y)| RoomXY::try_from((x, y)).unwrap());
+        .map(|(x, y)| RoomXY::try_from((x, y)).unwrap())
+        .filter(|xy| room.is_wall(*xy));
 
-    let candidates = FloodFill::new(wall_blocks.chain(entrance_blocks), OrthogonalWalkableNeighs::new(room.get_terrain()))
+    let candidates = FloodFill::new(wall_blocks.chain(entrance_blocks), OrthogonalWalkableNeighs::new(room.terrain.clone()))
         .sorted_by_key(|(dist, pos)| (Reverse(*dist), *pos))
         .dedup_by(|(d1, p1), (d2, p2)| *d1 == *d2 && p1.get_range_to(*p2) <= MIN_CANDIDATE_DIST)
-        .take(5)
+        .take(MAX_CANDIDATES)
         .map(|(_, pos)| pos);
-        //.inspect(|candidate| { let (x,y) = (candidate.x.u8(), candidate.y.u8()); draw_in_room(room.name(), move |visual| visual.circle(x as f32, y as f32, Some(CircleStyle::default().radius(0.35).fill("#469ff2")))); });
-
-    candidates.min_by_key(|candidate| {
-        let candidate_pos = Position::new(candidate.x, candidate.y, room.name());
 
+    candidates.sorted_by_key(|candidate| {
         let mut points_of_interest = Vec::new();
 
-        points_of_interest.extend(room.find(find::SOURCES, None).into_iter().map(|source| source.pos()));
-        points_of_interest.extend(room.find(find::DEPOSITS, None).into_iter().map(|deposit| deposit.pos()));
-        points_of_interest.push(room.controller().unwrap().pos());
+        points_of_interest.extend(room.sources.iter().map(|source| source.pos));
+        points_of_interest.push(room.controller);
 
         points_of_interest.into_iter()
-            .map(|poi| pathfinding::search(candidate_pos, poi, 1).path().len())
-            .sum::<usize>()
-    })//.inspect(|best| { let (x,y) = (best.x.u8(), best.y.u8()); draw_in_room(room.name(), move |visual| visual.circle(x as f32, y as f32, Some(CircleStyle::default().radius(0.5).fill("#46f263")))); })
-    .unwrap()
+            .map(|poi| shortest_path(*candidate, poi, 1, |xy| terrain_cost(room, xy)).map(|path| path.len()))
+            .sum::<Option<usize>>()
+            .unwrap_or(usize::MAX)
+    }).collect()
+}
+
+fn terrain_cost(room: &RoomDescription, xy: RoomXY) -> Option<u32> {
+    match room.terrain.get_xy(xy) {
+        Terrain::Plain => Some(2),
+        Terrain::Swamp => Some(10),
+        Terrain::Wall => None
+    }
 }
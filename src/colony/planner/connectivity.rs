@@ -28,7 +28,7 @@ pub fn ensure_connectivity(planner: &mut ColonyPlanner, center: RoomXY) -> anyho
 
         for new_road in &new_roads {
             if network.find_shorten(new_road) != network.find_shorten(&center) {
-                planner.plan_road_between(center, *new_road, step);
+                planner.plan_road_between(center, *new_road, step)?;
                 network.union_by_rank(new_road, &center)?;
             }
         }
@@ -45,7 +45,7 @@ pub fn ensure_connectivity(planner: &mut ColonyPlanner, center: RoomXY) -> anyho
             if new_structure == center { continue; }
             if new_structure.neighbors().into_iter().any(|neigh| network.find_shorten(&neigh).is_some()) { continue; }
             debug!("Connecting {center} and {new_structure}");
-            planner.plan_road_between(center, new_structure, step);
+            planner.plan_road_between(center, new_structure, step)?;
         }
     }
 
@@ -0,0 +1,47 @@
+use itertools::Itertools;
+use screeps::RoomXY;
+use anyhow::{anyhow, bail};
+
+use crate::colony::{planner::state::{ColonyPlanner, PlannedStructure}, steps::ColonyStep};
+
+const CONTAINER_RANGE: u8 = 2;
+const UPGRADE_RANGE: u8 = 3;
+
+pub fn plan_controller(planner: &mut ColonyPlanner, center: RoomXY) -> anyhow::Result<()> {
+    use ColonyStep::*;
+
+    let controller = planner.room.controller;
+    let path = planner.find_path_between(center, controller, None).ok_or(anyhow!("Unable to find path to controller"))?;
+
+    let container_index = path.iter()
+        .position(|pos| pos.get_range_to(controller) <= CONTAINER_RANGE)
+        .ok_or(anyhow!("Controller path doesn't pass its container"))?;
+    let container_pos = path[container_index];
+    let approach_pos = container_index.checked_sub(1).map(|index| path[index]);
+
+    if !planner.is_free_at(container_pos) { bail!("Controller container is blocked") }
+
+    planner.plan_structure(container_pos, BuildLvl2, PlannedStructure::ControllerContainer)?;
+    planner.plan_road_between(center, container_pos, BuildArterialRoads)?;
+
+    let slots = container_pos.neighbors().into_iter()
+        .filter(|pos| Some(*pos) != approach_pos && pos.get_range_to(controller) <= UPGRADE_RANGE)
+        .filter(|pos| planner.is_free_at(*pos) && !planner.roads.contains_key(pos))
+        .collect_vec();
+
+    let link_pos = slots.iter().copied()
+        .max_by_key(|pos| (slots.iter().filter(|slot| slot.get_range_to(*pos) <= 1).count(), *pos));
+
+    let mut standing_tiles = vec![container_pos];
+    if let Some(link_pos) = link_pos {
+        planner.plan_structure(link_pos, BuildLvl5, PlannedStructure::ControllerLink)?;
+        standing_tiles.extend(slots.iter().filter(|slot| **slot != link_pos && slot.get_range_to(link_pos) <= 1));
+    }
+
+    for tile in standing_tiles.iter().skip(1) {
+        planner.reserve(*tile);
+    }
+
+    planner.standing_tiles = standing_tiles;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+use screeps::RoomXY;
+
+use crate::colony::{planner::{pathing::side_of, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep};
+
+// Links left over at RCL8 take the energy of remote trucks right where they enter the room
+const ENTRY_LINK_STEP: ColonyStep = ColonyStep::BuildLvl8;
+const MIN_EDGE_RANGE: u8 = 3;
+
+fn edge_range(xy: RoomXY) -> u8 {
+    let (x, y) = (xy.x.u8(), xy.y.u8());
+    x.min(y).min(49 - x).min(49 - y)
+}
+
+pub fn plan_entry_links(planner: &mut ColonyPlanner, center: RoomXY) -> anyhow::Result<()> {
+    // The farthest exits save the trucks the longest walks
+    let paths = planner.room.exits()
+        .into_group_map_by(|exit| side_of(*exit))
+        .into_values()
+        .filter_map(|exits| exits.into_iter().min_by_key(|exit| (exit.get_range_to(center), *exit)))
+        .filter_map(|exit| planner.find_path_between(center, exit, None))
+        .sorted_by_key(|path| (Reverse(path.len()), path.last().copied()))
+        .collect_vec();
+
+    for path in paths {
+        if planner.count_left_for(PlannedStructure::EntryLink, ENTRY_LINK_STEP) == 0 { break; }
+
+        let slot = path.iter().rev()
+            .filter(|pos| edge_range(**pos) >= MIN_EDGE_RANGE)
+            .find_map(|pos| pos.neighbors().into_iter()
+                .filter(|neigh| !path.contains(neigh) && edge_range(*neigh) >= MIN_EDGE_RANGE && planner.is_buildable_at(*neigh))
+                .min()
+                .map(|link_pos| (*pos, link_pos)));
+
+        let Some((road_end, link_pos)) = slot else { continue };
+        planner.plan_structure(link_pos, ENTRY_LINK_STEP, PlannedStructure::EntryLink)?;
+        planner.plan_road_between(center, road_end, ENTRY_LINK_STEP)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use screeps::StructureType;
+
+    use crate::colony::{plan::ColonyPlan, planner::{DEFAULT_PERIMETER_STEP, PlannerStrategy, room::RoomDescription}};
+
+    use super::*;
+
+    #[test]
+    fn entry_links_are_on_roads_away_from_the_exits() {
+        let room = RoomDescription::from_json(include_str!("fixtures/W33N17.json")).unwrap();
+        let plan = ColonyPlan::create_from(&room, PlannerStrategy::default(), DEFAULT_PERIMETER_STEP).unwrap();
+        let roads = plan.steps.values().flat_map(|step| step.new_roads.iter().copied()).collect_vec();
+
+        assert!(!plan.entry_links.is_empty());
+        for link in plan.entry_links.iter().map(|link| link.pos.xy()) {
+            assert!(edge_range(link) >= MIN_EDGE_RANGE, "Entry link at {link} is too close to an exit");
+            assert!(link.neighbors().iter().any(|neigh| roads.contains(neigh)), "Entry link at {link} isn't next to a road");
+            assert_eq!(plan.steps[&ENTRY_LINK_STEP].new_structures.get(&link), Some(&StructureType::Link));
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use itertools::Itertools;
+use screeps::{ObjectId, RoomXY, Source, Terrain};
+use anyhow::{anyhow, bail};
+use strum::IntoEnumIterator;
+
+use crate::{colony::{plan::{ColonyPlan, refs::PlannedStructureRef}, planner::{room::RoomDescription, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep}, domain_traits::HasId};
+
+const FORMAT_VERSION: u8 = 1;
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+impl ColonyPlan {
+    pub fn export(&self, room: &RoomDescription) -> anyhow::Result<String> {
+        let sources = sorted_sources(room);
+        let structures = self.planned_structures();
+
+        let mut writer = Writer(vec![FORMAT_VERSION]);
+        for step in ColonyStep::iter() {
+            let Some(plan_step) = self.steps.get(&step) else { continue; };
+            if plan_step.new_structures.is_empty() && plan_step.new_roads.is_empty() { continue; }
+
+            writer.u8(step as u8);
+
+            writer.u16(plan_step.new_structures.len());
+            for pos in plan_step.new_structures.keys().sorted() {
+                let structure = structures.iter()
+                    .find(|(structure_pos, _)| structure_pos == pos)
+                    .map(|(_, structure)| *structure)
+                    .ok_or(anyhow!("Unable to identify planned {} at {pos}", plan_step.new_structures[pos]))?;
+
+                writer.structure(structure, &sources)?;
+                writer.xy(*pos);
+            }
+
+            writer.u16(plan_step.new_roads.len());
+            for pos in plan_step.new_roads.iter().sorted() {
+                writer.xy(*pos);
+            }
+        }
+
+        writer.u8(u8::MAX);
+        writer.u16(self.controller.standing_tiles.len());
+        for tile in &self.controller.standing_tiles {
+            writer.xy(tile.xy());
+        }
+
+        Ok(encode(&writer.0))
+    }
+
+    pub fn import(data: &str, room: &RoomDescription) -> anyhow::Result<Self> {
+        let sources = sorted_sources(room);
+        let mut reader = Reader(decode(data)?.into_iter());
+
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION { bail!("Unsupported plan format version {version}") }
+
+        let mut planner = ColonyPlanner::new(room.clone());
+        loop {
+            let step = reader.u8()?;
+            if step == u8::MAX { break; }
+            let step = ColonyStep::from_repr(step).ok_or(anyhow!("Invalid step {step}"))?;
+
+            for _ in 0..reader.u16()? {
+                let structure = reader.structure(&sources)?;
+                let pos = reader.xy()?;
+                planner.plan_structure(pos, step, structure).map_err(|err| anyhow!("At {pos}: {err}"))?;
+            }
+
+            for _ in 0..reader.u16()? {
+                let pos = reader.xy()?;
+                if room.terrain.get_xy(pos) == Terrain::Wall { bail!("Can't plan road at {pos} due to wall") }
+                planner.plan_road(pos, step);
+            }
+        }
+
+        for _ in 0..reader.u16()? {
+            let tile = reader.xy()?;
+            if room.is_wall(tile) { bail!("Can't stand at {tile} due to wall") }
+            planner.standing_tiles.push(tile);
+        }
+
+        if reader.0.next().is_some() { bail!("Unexpected trailing data") }
+
+        planner.compile()
+    }
+
+    fn planned_structures(&self) -> Vec<(RoomXY, PlannedStructure)> {
+        use PlannedStructure::*;
+
+        fn all<'a, T: HasId + 'a>(refs: impl IntoIterator<Item = &'a PlannedStructureRef<T>>, structure: PlannedStructure) -> impl Iterator<Item = (RoomXY, PlannedStructure)> {
+            refs.into_iter().map(move |planned| (planned.pos.xy(), structure))
+        }
+
+        let center = &self.center;
+        let sources = self.sources.iter().flat_map(|(source, plan)| {
+            all(plan.spawn.iter(), SourceSpawn(*source))
+                .chain(all(plan.container.iter(), SourceContainer(*source)))
+                .chain(all(plan.link.iter(), SourceLink(*source)))
+                .chain(all(plan.extensions.iter(), SourceExtension(*source)))
+        });
+
+        all([&center.spawn], MainSpawn)
+            .chain(all(center.extra_spawns.iter(), ExtraSpawn))
+            .chain(all(center.storage.iter(), Storage))
+            .chain(all(center.container_storage.iter(), ContainerStorage))
+            .chain(all(center.link.iter(), CentralLink))
+            .chain(all(center.terminal.iter(), Terminal))
+            .chain(all(center.observer.iter(), Observer))
+            .chain(all(center.factory.iter(), Factory))
+            .chain(all(center.power_spawn.iter(), PowerSpawn))
+            .chain(all(center.nuker.iter(), Nuker))
+            .chain(all(center.towers.iter(), Tower))
+            .chain(all(center.extensions.iter(), Extension))
+            .chain(all(center.input_labs.iter(), InputLab))
+            .chain(all(center.output_labs.iter(), OutputLab))
+            .chain(all(self.mineral.container.iter(), MineralContainer))
+            .chain(all(self.mineral.extractor.iter(), Extractor))
+            .chain(all(self.controller.container.iter(), ControllerContainer))
+            .chain(all(self.controller.link.iter(), ControllerLink))
+            .chain(all(self.entry_links.iter(), EntryLink))
+            .chain(all(self.perimeter.ramparts.iter(), Rampart))
+            .chain(all(self.perimeter.walls.iter(), Wall))
+            .chain(sources)
+            .collect()
+    }
+}
+
+fn sorted_sources(room: &RoomDescription) -> Vec<ObjectId<Source>> {
+    room.sources.iter()
+        .sorted_by_key(|source| source.pos)
+        .map(|source| source.id)
+        .collect()
+}
+
+fn structure_code(structure: PlannedStructure) -> (u8, Option<ObjectId<Source>>) {
+    use PlannedStructure::*;
+
+    match structure {
+        MainSpawn => (0, None),
+        ExtraSpawn => (1, None),
+        SourceSpawn(source) => (2, Some(source)),
+        SourceContainer(source) => (3, Some(source)),
+        SourceLink(source) => (4, Some(source)),
+        SourceExtension(source) => (5, Some(source)),
+        Extension => (6, None),
+        Storage => (7, None),
+        Terminal => (8, None),
+        ContainerStorage => (9, None),
+        Tower => (10, None),
+        CentralLink => (11, None),
+        Extractor => (12, None),
+        MineralContainer => (13, None),
+        ControllerContainer => (14, None),
+        ControllerLink => (15, None),
+        Observer => (16, None),
+        InputLab => (17, None),
+        OutputLab => (18, None),
+        Factory => (19, None),
+        PowerSpawn => (20, None),
+        Nuker => (21, None),
+        Rampart => (22, None),
+        Wall => (23, None),
+        EntryLink => (24, None),
+    }
+}
+
+fn structure_from_code(code: u8, source: impl FnOnce() -> anyhow::Result<ObjectId<Source>>) -> anyhow::Result<PlannedStructure> {
+    use PlannedStructure::*;
+
+    Ok(match code {
+        0 => MainSpawn,
+        1 => ExtraSpawn,
+        2 => SourceSpawn(source()?),
+        3 => SourceContainer(source()?),
+        4 => SourceLink(source()?),
+        5 => SourceExtension(source()?),
+        6 => Extension,
+        7 => Storage,
+        8 => Terminal,
+        9 => ContainerStorage,
+        10 => Tower,
+        11 => CentralLink,
+        12 => Extractor,
+        13 => MineralContainer,
+        14 => ControllerContainer,
+        15 => ControllerLink,
+        16 => Observer,
+        17 => InputLab,
+        18 => OutputLab,
+        19 => Factory,
+        20 => PowerSpawn,
+        21 => Nuker,
+        22 => Rampart,
+        23 => Wall,
+        24 => EntryLink,
+        _ => bail!("Invalid structure code {code}")
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: usize) {
+        self.0.extend(u16::try_from(value).unwrap().to_be_bytes());
+    }
+
+    fn xy(&mut self, xy: RoomXY) {
+        self.0.extend([xy.x.u8(), xy.y.u8()]);
+    }
+
+    fn structure(&mut self, structure: PlannedStructure, sources: &[ObjectId<Source>]) -> anyhow::Result<()> {
+        let (code, source) = structure_code(structure);
+        self.u8(code);
+
+        if let Some(source) = source {
+            let index = sources.iter().position(|other| *other == source).ok_or(anyhow!("Unknown source {source}"))?;
+            self.u8(index as u8);
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader(std::vec::IntoIter<u8>);
+
+impl Reader {
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        self.0.next().ok_or(anyhow!("Unexpected end of plan"))
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn xy(&mut self) -> anyhow::Result<RoomXY> {
+        let (x, y) = (self.u8()?, self.u8()?);
+        RoomXY::checked_new(x, y).map_err(|_| anyhow!("Invalid position ({x}, {y})"))
+    }
+
+    fn structure(&mut self, sources: &[ObjectId<Source>]) -> anyhow::Result<PlannedStructure> {
+        let code = self.u8()?;
+        structure_from_code(code, || {
+            let index = self.u8()?;
+            sources.get(usize::from(index)).copied().ok_or(anyhow!("Invalid source index {index}"))
+        })
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.chunks(3).flat_map(|chunk| {
+        let bits = chunk.iter().enumerate().fold(0_u32, |acc, (i, byte)| acc | u32::from(*byte) << (16 - 8 * i));
+        (0..=chunk.len()).map(move |i| char::from(ALPHABET[(bits >> (18 - 6 * i) & 0b11_1111) as usize]))
+    }).collect()
+}
+
+fn decode(data: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u32> = data.trim().bytes()
+        .map(|c| ALPHABET.iter().position(|other| *other == c).map(|digit| digit as u32).ok_or(anyhow!("Invalid plan character {}", char::from(c))))
+        .collect::<anyhow::Result<_>>()?;
+
+    digits.chunks(4).map(|chunk| {
+        if chunk.len() < 2 { bail!("Truncated plan data") }
+
+        let bits = chunk.iter().enumerate().fold(0_u32, |acc, (i, digit)| acc | digit << (18 - 6 * i));
+        Ok((0..chunk.len() - 1).map(move |i| (bits >> (16 - 8 * i)) as u8))
+    }).flatten_ok().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colony::{DEFAULT_PERIMETER_STEP, PlannerStrategy};
+
+    #[test]
+    fn bytes_survive_encoding() {
+        for len in 0..=7 {
+            let bytes = (0..len).map(|i| (i * 97 + 13) as u8).collect_vec();
+            assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        assert!(decode("AB*D").is_err());
+        assert!(decode("ABCDE").is_err());
+    }
+
+    #[test]
+    fn plans_survive_export_and_import() {
+        let room = RoomDescription::from_json(include_str!("fixtures/W7N3.json")).unwrap();
+        let plan = ColonyPlan::create_from(&room, PlannerStrategy::FloodFill, DEFAULT_PERIMETER_STEP).unwrap();
+
+        let data = plan.export(&room).unwrap();
+        let imported = ColonyPlan::import(&data, &room).unwrap();
+
+        for step in ColonyStep::iter() {
+            let (expected, found) = (&plan.steps[&step], &imported.steps[&step]);
+            assert_eq!(expected.new_structures, found.new_structures, "Structures differ at {step:?}");
+            assert_eq!(expected.new_roads, found.new_roads, "Roads differ at {step:?}");
+        }
+
+        assert_eq!(plan.center.pos, imported.center.pos);
+        assert_eq!(plan.controller.standing_tiles, imported.controller.standing_tiles);
+        assert_eq!(imported.export(&room).unwrap(), data);
+    }
+
+    #[test]
+    fn malformed_plans_are_rejected() {
+        let room = RoomDescription::from_json(include_str!("fixtures/W7N3.json")).unwrap();
+        assert!(ColonyPlan::import(&encode(&[FORMAT_VERSION + 1, u8::MAX, 0, 0]), &room).is_err());
+        assert!(ColonyPlan::import(&encode(&[FORMAT_VERSION, u8::MAX, 0, 0, 0]), &room).is_err());
+    }
+}
@@ -1,29 +1,29 @@
 use std::collections::{HashSet, VecDeque};
-use screeps::{Direction, RoomTerrain, RoomXY, Terrain};
+use screeps::{Direction, LocalRoomTerrain, RoomXY, Terrain};
 
-pub struct WalkableNeighs(RoomTerrain);
+pub struct WalkableNeighs(LocalRoomTerrain);
 impl WalkableNeighs {
-    pub fn new(terrain: RoomTerrain) -> Self { Self(terrain) }
+    pub fn new(terrain: LocalRoomTerrain) -> Self { Self(terrain) }
 }
 
 impl Neigh for WalkableNeighs {
     fn neighbors_of(&self, pos: RoomXY) -> impl Iterator<Item = RoomXY> {
         Direction::iter()
             .filter_map(move |dir| pos.checked_add_direction(*dir))
-            .filter(|neigh| self.0.get(neigh.x.u8(), neigh.y.u8()) != Terrain::Wall)
+            .filter(|neigh| self.0.get_xy(*neigh) != Terrain::Wall)
     }
 }
 
 pub struct StarWalkableNeighs {
-    terrain: RoomTerrain,
+    terrain: LocalRoomTerrain,
     center: RoomXY
 }
 
 impl StarWalkableNeighs {
-    pub fn new(terrain: RoomTerrain, center: RoomXY) -> Self { Self { terrain, center } }
+    pub fn new(terrain: LocalRoomTerrain, center: RoomXY) -> Self { Self { terrain, center } }
 
     pub fn eligible(&self, pos: RoomXY) -> bool {
-        if self.terrain.get(pos.x.u8(), pos.y.u8()) == Terrain::Wall { return false }
+        if self.terrain.get_xy(pos) == Terrain::Wall { return false }
 
         let (dx, dy) = pos - self.center;
         let (dx, dy) = (dx.abs(), dy.abs());
@@ -41,9 +41,9 @@ impl Neigh for StarWalkableNeighs {
     }
 }
 
-pub struct OrthogonalWalkableNeighs(RoomTerrain);
+pub struct OrthogonalWalkableNeighs(LocalRoomTerrain);
 impl OrthogonalWalkableNeighs {
-    pub fn new(terrain: RoomTerrain) -> Self { Self(terrain) }
+    pub fn new(terrain: LocalRoomTerrain) -> Self { Self(terrain) }
 }
 
 impl Neigh for OrthogonalWalkableNeighs {
@@ -51,7 +51,7 @@ impl Neigh for OrthogonalWalkableNeighs {
         Direction::iter()
             .filter(|dir| dir.is_orthogonal())
             .filter_map(move |dir| pos.checked_add_direction(*dir))
-            .filter(|neigh| self.0.get(neigh.x.u8(), neigh.y.u8()) != Terrain::Wall)
+            .filter(|neigh| self.0.get_xy(*neigh) != Terrain::Wall)
     }
 }
 
@@ -0,0 +1,326 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, iter};
+
+use screeps::RoomXY;
+use anyhow::anyhow;
+
+use crate::colony::{planner::{pathing::{is_border, side_of}, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep};
+
+const INF: u32 = u32::MAX / 4;
+const STRUCTURE_MARGIN: u8 = 2;
+const TARGET_MARGIN: u8 = 1;
+const MIN_EXIT_DIST: u8 = 3;
+
+struct FlowGraph {
+    adjacent: Vec<Vec<usize>>,
+    to: Vec<usize>,
+    capacity: Vec<u32>
+}
+
+impl FlowGraph {
+    fn new(nodes: usize) -> Self {
+        Self { adjacent: vec![Vec::new(); nodes], to: Vec::new(), capacity: Vec::new() }
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, capacity: u32) {
+        self.adjacent[from].push(self.to.len());
+        self.to.push(to);
+        self.capacity.push(capacity);
+
+        self.adjacent[to].push(self.to.len());
+        self.to.push(from);
+        self.capacity.push(0);
+    }
+
+    fn levels(&self, source: usize) -> Vec<Option<u32>> {
+        let mut levels = vec![None; self.adjacent.len()];
+        let mut queue = VecDeque::from([source]);
+        levels[source] = Some(0);
+
+        while let Some(node) = queue.pop_front() {
+            for &edge in &self.adjacent[node] {
+                let next = self.to[edge];
+                if self.capacity[edge] == 0 || levels[next].is_some() { continue; }
+
+                levels[next] = Some(levels[node].unwrap() + 1);
+                queue.push_back(next);
+            }
+        }
+
+        levels
+    }
+
+    fn blocking_flow(&mut self, source: usize, sink: usize, levels: &mut [Option<u32>]) -> u32 {
+        let mut next_edge = vec![0; self.adjacent.len()];
+        let mut path: Vec<usize> = Vec::new();
+        let mut total = 0;
+        let mut node = source;
+
+        loop {
+            if node == sink {
+                let bottleneck = path.iter().map(|&edge| self.capacity[edge]).min().unwrap();
+                for &edge in &path {
+                    self.capacity[edge] -= bottleneck;
+                    self.capacity[edge ^ 1] += bottleneck;
+                }
+
+                total += bottleneck;
+                if total >= INF { return total; }
+
+                path.clear();
+                node = source;
+                continue;
+            }
+
+            let advance = self.adjacent[node][next_edge[node]..].iter()
+                .position(|&edge| self.capacity[edge] > 0 && levels[self.to[edge]].is_some_and(|level| Some(level) == levels[node].map(|l| l + 1)));
+
+            if let Some(offset) = advance {
+                next_edge[node] += offset;
+                let edge = self.adjacent[node][next_edge[node]];
+                path.push(edge);
+                node = self.to[edge];
+            } else {
+                next_edge[node] = self.adjacent[node].len();
+                if node == source { return total; }
+
+                levels[node] = None;
+                let edge = path.pop().unwrap();
+                node = self.to[edge ^ 1];
+                next_edge[node] += 1;
+            }
+        }
+    }
+
+    fn max_flow(&mut self, source: usize, sink: usize) -> u32 {
+        let mut flow = 0;
+
+        loop {
+            let mut levels = self.levels(source);
+            if levels[sink].is_none() { return flow; }
+
+            flow += self.blocking_flow(source, sink, &mut levels);
+            if flow >= INF { return flow; }
+        }
+    }
+}
+
+fn index(xy: RoomXY) -> usize {
+    usize::from(xy.y.u8()) * 50 + usize::from(xy.x.u8())
+}
+
+fn in_node(xy: RoomXY) -> usize { 2 * index(xy) }
+fn out_node(xy: RoomXY) -> usize { 2 * index(xy) + 1 }
+
+fn all_tiles() -> impl Iterator<Item = RoomXY> {
+    (0..50).flat_map(|y| (0..50).map(move |x| RoomXY::checked_new(x, y).unwrap()))
+}
+
+fn square_around(xy: RoomXY, radius: u8) -> impl Iterator<Item = RoomXY> {
+    let radius = i16::from(radius);
+    (-radius..=radius).flat_map(move |dy| (-radius..=radius).filter_map(move |dx| {
+        let x = u8::try_from(i16::from(xy.x.u8()) + dx).ok()?;
+        let y = u8::try_from(i16::from(xy.y.u8()) + dy).ok()?;
+        RoomXY::checked_new(x, y).ok()
+    }))
+}
+
+fn protected_tiles(planner: &ColonyPlanner) -> HashSet<RoomXY> {
+    let structures = planner.pos2structure.iter()
+        .filter(|(_, structure)| !matches!(structure, PlannedStructure::Extractor | PlannedStructure::MineralContainer | PlannedStructure::EntryLink))
+        .map(|(pos, _)| pos)
+        .chain(&planner.reserved)
+        .flat_map(|pos| square_around(*pos, STRUCTURE_MARGIN));
+
+    let sources = planner.room.sources.iter().map(|source| source.pos);
+    let targets = iter::once(planner.room.controller).chain(sources)
+        .flat_map(|pos| square_around(pos, TARGET_MARGIN));
+
+    structures.chain(targets).collect()
+}
+
+pub fn plan_perimeter(planner: &mut ColonyPlanner, center: RoomXY, step: ColonyStep) -> anyhow::Result<()> {
+    let walkable = |xy: RoomXY| !planner.room.is_wall(xy);
+
+    let exits: Vec<_> = planner.room.exits().collect();
+    let exit_dist: HashMap<_, _> = all_tiles()
+        .map(|xy| (xy, exits.iter().map(|exit| exit.get_range_to(xy)).min().unwrap_or(u8::MAX)))
+        .collect();
+
+    let protected: HashSet<_> = protected_tiles(planner).into_iter()
+        .filter(|xy| walkable(*xy) && exit_dist[xy] >= MIN_EXIT_DIST)
+        .collect();
+
+    let source = 2 * 50 * 50;
+    let sink = source + 1;
+    let mut graph = FlowGraph::new(sink + 1);
+
+    for xy in all_tiles().filter(|xy| walkable(*xy)) {
+        let unbuildable = exit_dist[&xy] <= 1 || protected.contains(&xy) || planner.pos2structure.contains_key(&xy) || planner.reserved.contains(&xy);
+        graph.add_edge(in_node(xy), out_node(xy), if unbuildable { INF } else { 1 });
+
+        for neigh in xy.neighbors().into_iter().filter(|neigh| walkable(*neigh)) {
+            graph.add_edge(out_node(xy), in_node(neigh), INF);
+        }
+
+        if protected.contains(&xy) { graph.add_edge(source, in_node(xy), INF); }
+        if is_border(xy) { graph.add_edge(out_node(xy), sink, INF); }
+    }
+
+    if graph.max_flow(source, sink) >= INF {
+        return Err(anyhow!("Unable to separate core from exits"));
+    }
+
+    let reachable: Vec<_> = graph.levels(source).into_iter().map(|level| level.is_some()).collect();
+    let cut: HashSet<_> = all_tiles()
+        .filter(|xy| walkable(*xy) && reachable[in_node(*xy)] && !reachable[out_node(*xy)])
+        .collect();
+
+    let gates = find_gates(planner, center, &cut);
+
+    for xy in cut {
+        let structure = if gates.contains(&xy) || planner.roads.contains_key(&xy) { PlannedStructure::Rampart } else { PlannedStructure::Wall };
+        planner.plan_structure(xy, step, structure)?;
+    }
+
+    Ok(())
+}
+
+fn find_gates(planner: &ColonyPlanner, center: RoomXY, cut: &HashSet<RoomXY>) -> HashSet<RoomXY> {
+    let passable = |xy: RoomXY| !planner.room.is_wall(xy) && planner.pos2structure.get(&xy).is_none_or(PlannedStructure::walkable);
+
+    let mut parents = HashMap::from([(center, center)]);
+    let mut queue = VecDeque::from([center]);
+    let mut side_exits = HashMap::new();
+
+    while let Some(pos) = queue.pop_front() {
+        if is_border(pos) {
+            side_exits.entry(side_of(pos)).or_insert(pos);
+            continue;
+        }
+
+        for neigh in pos.neighbors() {
+            if !passable(neigh) || parents.contains_key(&neigh) { continue; }
+
+            parents.insert(neigh, pos);
+            queue.push_back(neigh);
+        }
+    }
+
+    let mut gates = HashSet::new();
+    for mut pos in side_exits.into_values() {
+        while pos != center {
+            if cut.contains(&pos) { gates.insert(pos); }
+            pos = parents[&pos];
+        }
+    }
+
+    gates
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{LocalRoomTerrain, ROOM_AREA, RoomName};
+
+    use crate::colony::planner::room::RoomDescription;
+
+    use super::*;
+
+    fn xy(x: u8, y: u8) -> RoomXY {
+        RoomXY::checked_new(x, y).unwrap()
+    }
+
+    fn planner(controller: RoomXY, is_wall: impl Fn(RoomXY) -> bool) -> ColonyPlanner {
+        let bits: Box<[u8; ROOM_AREA]> = all_tiles().map(|xy| u8::from(is_wall(xy))).collect::<Vec<_>>().into_boxed_slice().try_into().unwrap();
+
+        ColonyPlanner::new(RoomDescription {
+            name: RoomName::new("W1N1").unwrap(),
+            terrain: LocalRoomTerrain::new_from_bits(bits),
+            sources: Vec::new(),
+            controller,
+            mineral: None,
+            center: None,
+            traffic: None
+        })
+    }
+
+    // A single row open to the left and right exits
+    fn corridor() -> ColonyPlanner {
+        planner(xy(24, 25), |xy| xy.y.u8() != 25)
+    }
+
+    fn ring(center: RoomXY, radius: u8) -> HashSet<RoomXY> {
+        square_around(center, radius).filter(|xy| xy.get_range_to(center) == radius).collect()
+    }
+
+    #[test]
+    fn structures_and_the_controller_are_protected_with_a_margin() {
+        let mut planner = planner(xy(10, 10), |_| false);
+        planner.plan_structure(xy(30, 30), ColonyStep::BuildLvl2, PlannedStructure::Extension).unwrap();
+        planner.plan_structure(xy(40, 40), ColonyStep::BuildLvl5, PlannedStructure::EntryLink).unwrap();
+
+        let protected = protected_tiles(&planner);
+        let expected: HashSet<_> = square_around(xy(30, 30), STRUCTURE_MARGIN).chain(square_around(xy(10, 10), TARGET_MARGIN)).collect();
+        assert_eq!(protected, expected);
+    }
+
+    #[test]
+    fn reserved_tiles_are_protected() {
+        let mut planner = planner(xy(10, 10), |_| false);
+        planner.reserve(xy(20, 20));
+
+        assert!(square_around(xy(20, 20), STRUCTURE_MARGIN).all(|xy| protected_tiles(&planner).contains(&xy)));
+    }
+
+    #[test]
+    fn corridor_gates_sit_on_the_way_to_both_exits() {
+        let planner = corridor();
+        let cut = HashSet::from([xy(10, 25), xy(40, 25)]);
+
+        assert_eq!(find_gates(&planner, xy(25, 25), &cut), cut);
+    }
+
+    #[test]
+    fn corridor_is_cut_with_one_rampart_per_side() {
+        let mut planner = corridor();
+        planner.plan_structure(xy(25, 25), ColonyStep::BuildLvl4, PlannedStructure::Storage).unwrap();
+        plan_perimeter(&mut planner, xy(25, 25), ColonyStep::BuildLvl4).unwrap();
+
+        let ramparts = planner.structures2pos.get(&PlannedStructure::Rampart).cloned().unwrap_or_default();
+        assert_eq!(ramparts.len(), 2);
+        assert!(ramparts.iter().any(|xy| xy.x.u8() < 25) && ramparts.iter().any(|xy| xy.x.u8() > 25));
+        assert!(!planner.structures2pos.contains_key(&PlannedStructure::Wall));
+    }
+
+    #[test]
+    fn open_room_gets_a_gate_towards_every_side() {
+        let planner = planner(xy(24, 25), |_| false);
+        let cut = ring(xy(25, 25), 10);
+
+        let gates = find_gates(&planner, xy(25, 25), &cut);
+        assert_eq!(gates.len(), 4);
+        assert!(gates.is_subset(&cut));
+    }
+
+    #[test]
+    fn open_room_is_walled_off_away_from_the_gates() {
+        let mut planner = planner(xy(24, 25), |_| false);
+        planner.plan_structure(xy(25, 25), ColonyStep::BuildLvl4, PlannedStructure::Storage).unwrap();
+        plan_perimeter(&mut planner, xy(25, 25), ColonyStep::BuildLvl8).unwrap();
+
+        let ramparts = planner.structures2pos.get(&PlannedStructure::Rampart).cloned().unwrap_or_default();
+        let walls = planner.structures2pos.get(&PlannedStructure::Wall).cloned().unwrap_or_default();
+        assert_eq!(ramparts.len(), 4);
+        assert!(!walls.is_empty());
+
+        // Nothing walkable connects the core to the exits without crossing the perimeter
+        let perimeter: HashSet<_> = ramparts.union(&walls).copied().collect();
+        let mut seen = HashSet::from([xy(25, 25)]);
+        let mut queue = VecDeque::from([xy(25, 25)]);
+        while let Some(pos) = queue.pop_front() {
+            assert!(!is_border(pos), "The core reaches the exit at {pos}");
+            for neigh in pos.neighbors() {
+                if !perimeter.contains(&neigh) && seen.insert(neigh) { queue.push_back(neigh); }
+            }
+        }
+    }
+}
@@ -1,55 +1,299 @@
-use screeps::{HasPosition, Room, find};
+use clap::ValueEnum;
+use itertools::Itertools;
+use log::warn;
+use screeps::{Room, RoomXY};
+use serde::{Deserialize, Serialize};
+use strum::{EnumIter, IntoEnumIterator};
 use anyhow::anyhow;
 
-use crate::colony::{plan::ColonyPlan, planner::{center::{CenterPlanner, find_center, plan_extensions_towers_observer}, connectivity::ensure_connectivity, sources::plan_sources, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep};
+use crate::colony::{plan::ColonyPlan, planner::{center::{CenterPlanner, StructureSlots, find_center_candidates, plan_extensions_towers_observer}, connectivity::ensure_connectivity, controller::plan_controller, entries::plan_entry_links, mincut::plan_perimeter, room::RoomDescription, score::PlanScore, sources::plan_sources, stamps::{StampPlanner, find_core_candidates, plan_lab_cluster}, state::{ColonyPlanner, PlannedStructure}, traffic::plan_traffic_roads}, steps::ColonyStep};
 
 mod center;
 mod connectivity;
+mod controller;
+mod entries;
+mod export;
 mod floodfill;
+mod mincut;
+mod pathing;
+pub mod room;
+mod score;
 mod sources;
+mod stamps;
 mod state;
+mod traffic;
+
+// Colonies are planned with a single strategy, as planning every candidate of both at once takes too long for a tick
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Default, EnumIter, ValueEnum)]
+pub enum PlannerStrategy {
+    #[default]
+    FloodFill,
+    Stamps
+}
+
+pub struct PlanCandidate {
+    pub strategy: PlannerStrategy,
+    pub center: RoomXY,
+    pub score: PlanScore,
+    pub plan: anyhow::Result<ColonyPlan>
+}
+
+// Bump whenever the planner output changes, so existing colonies migrate to the new plan
+pub const PLAN_VERSION: u32 = 5;
+
+// Every candidate is planned in full within one tick, including its perimeter
+const MAX_PLANNED_CANDIDATES: usize = 3;
+
+// Both strategies look for centers away from the exits and spread out from each other
+const MIN_ENTRANCE_DIST: u8 = 8;
+const MIN_CANDIDATE_DIST: u8 = 4;
+const MAX_CANDIDATES: usize = 5;
+
+// Colonies can have their perimeter built at another step with SetPerimeterStep
+pub const DEFAULT_PERIMETER_STEP: ColonyStep = ColonyStep::BuildLvl4;
 
 impl ColonyPlan {
-    pub fn create_for(room: &Room) -> anyhow::Result<Self> {
-        use ColonyStep::*;
+    pub fn create_for(room: &Room, strategy: PlannerStrategy, perimeter_step: ColonyStep) -> anyhow::Result<Self> {
+        Self::create_from(&RoomDescription::of_room(room)?, strategy, perimeter_step)
+    }
 
-        let mut planner = ColonyPlanner::new(room.clone());
-        let center = find_center(room);
-        
-        let mut center_planner = CenterPlanner::new(&planner, center);
-        planner.plan_structure(center_planner.container_buffer_pos(), BuildBufferAndSourceContainers, PlannedStructure::ContainerStorage)?;
+    pub fn create_from(room: &RoomDescription, strategy: PlannerStrategy, perimeter_step: ColonyStep) -> anyhow::Result<Self> {
+        let mut best: Option<(ColonyPlan, PlanScore)> = None;
+        let mut last_err = None;
+
+        for candidate in Self::plan_candidates(room, Some(strategy), perimeter_step) {
+            match candidate.plan {
+                Ok(plan) => if best.as_ref().is_none_or(|(_, best_score)| candidate.score.total() < best_score.total()) {
+                    best = Some((plan, candidate.score));
+                },
+                Err(err) => last_err = Some(err)
+            }
+        }
+
+        best.map(|(plan, _)| plan).ok_or_else(|| last_err.unwrap_or_else(|| anyhow!("No center candidates in {}", room.name)))
+    }
 
-        center_planner.plan_structure(&mut planner, BuildLvl4, PlannedStructure::Storage)?;
-        center_planner.plan_structure(&mut planner, BuildSpawn, PlannedStructure::MainSpawn)?;
-        center_planner.plan_structure(&mut planner, BuildLvl5, PlannedStructure::CentralLink)?;
-        center_planner.plan_structure(&mut planner, BuildLvl6, PlannedStructure::Terminal)?;
-        center_planner.plan_structure(&mut planner, BuildLvl3, PlannedStructure::Tower)?;
+    // Only the most promising centers of each strategy are planned, taking turns between the strategies
+    pub fn plan_candidates(room: &RoomDescription, strategy: Option<PlannerStrategy>, perimeter_step: ColonyStep) -> Vec<PlanCandidate> {
+        let centers = PlannerStrategy::iter()
+            .filter(|candidate_strategy| strategy.is_none_or(|strategy| strategy == *candidate_strategy))
+            .map(|strategy| {
+                let centers = match strategy {
+                    PlannerStrategy::FloodFill => find_center_candidates(room),
+                    PlannerStrategy::Stamps => find_core_candidates(room),
+                };
 
-        let excavator_positions = plan_sources(&mut planner, center)?;
+                centers.into_iter().map(|center| (strategy, center)).collect_vec()
+            })
+            .collect_vec();
 
-        plan_extensions_towers_observer(&mut planner, &mut center_planner)?;
+        let rounds = centers.iter().map(Vec::len).max().unwrap_or(0);
+        (0..rounds)
+            .flat_map(|round| centers.iter().filter_map(move |centers| centers.get(round).copied()))
+            .take(MAX_PLANNED_CANDIDATES)
+            .map(|(strategy, center)| match Self::create_with_center(room, strategy, center, perimeter_step) {
+                Ok((plan, score)) => PlanCandidate { strategy, center, score, plan: Ok(plan) },
+                Err(err) => PlanCandidate { strategy, center, score: PlanScore::failed(), plan: Err(err) }
+            })
+            .collect()
+    }
 
-        center_planner.plan_roads(&mut planner);
+    fn create_with_center(room: &RoomDescription, strategy: PlannerStrategy, center: RoomXY, perimeter_step: ColonyStep) -> anyhow::Result<(Self, PlanScore)> {
+        use ColonyStep::*;
 
-        let controller = room.controller().unwrap().pos().xy();
-        planner.plan_road_between(center, controller, BuildArterialRoads);
+        let mut planner = ColonyPlanner::new(room.clone());
 
-        for source in excavator_positions {
-            planner.plan_road_between(source, center, BuildArterialRoads);
+        match strategy {
+            PlannerStrategy::FloodFill => plan_flood_fill_layout(&mut planner, center)?,
+            PlannerStrategy::Stamps => plan_stamp_layout(&mut planner, center)?,
         }
 
-        for deposit in room.find(find::MINERALS, None) {
-            planner.plan_structure(deposit.pos().xy(), BuildLvl6, PlannedStructure::Extractor)?;
-            planner.plan_road_between(center, deposit.pos().xy(), BuildLvl6);
+        if let Some(deposit) = room.mineral {
+            planner.plan_structure(deposit, BuildLvl6, PlannedStructure::Extractor)?;
+            planner.plan_road_between(center, deposit, BuildLvl6)?;
 
-            let container_pos = deposit.pos().xy().neighbors().into_iter()
+            let container_pos = deposit.neighbors().into_iter()
                 .find(|neigh| planner.roads.contains_key(neigh))
                 .ok_or(anyhow!("Unable to find road around deposit"))?;
             planner.plan_structure(container_pos, BuildLvl6, PlannedStructure::MineralContainer)?;
         }
 
+        plan_entry_links(&mut planner, center)?;
+
+        plan_traffic_roads(&mut planner);
         ensure_connectivity(&mut planner, center)?;
 
-        planner.compile()
+        if let Err(err) = plan_perimeter(&mut planner, center, perimeter_step) {
+            warn!("Skipping perimeter of {}: {err}", room.name);
+        }
+
+        let score = PlanScore::of_planner(&planner, center);
+        Ok((planner.compile()?, score))
+    }
+}
+
+fn plan_flood_fill_layout(planner: &mut ColonyPlanner, center: RoomXY) -> anyhow::Result<()> {
+    use ColonyStep::*;
+
+    let mut center_planner = CenterPlanner::new(planner, center);
+    planner.plan_structure(center_planner.container_buffer_pos(), BuildBufferAndSourceContainers, PlannedStructure::ContainerStorage)?;
+
+    center_planner.plan_structure(planner, BuildLvl4, PlannedStructure::Storage)?;
+    center_planner.plan_structure(planner, BuildSpawn, PlannedStructure::MainSpawn)?;
+    center_planner.plan_structure(planner, BuildLvl5, PlannedStructure::CentralLink)?;
+    center_planner.plan_structure(planner, BuildLvl6, PlannedStructure::Terminal)?;
+    center_planner.plan_structure(planner, BuildLvl3, PlannedStructure::Tower)?;
+    center_planner.plan_structure(planner, BuildLvl7, PlannedStructure::Factory)?;
+    center_planner.plan_structure(planner, BuildLvl8, PlannedStructure::PowerSpawn)?;
+    center_planner.plan_structure(planner, BuildLvl8, PlannedStructure::Nuker)?;
+
+    plan_controller(planner, center)?;
+    plan_extra_spawns(planner, |planner, step| center_planner.plan_structure(planner, step, PlannedStructure::ExtraSpawn))?;
+    let excavator_positions = plan_sources(planner, center)?;
+
+    plan_extensions_towers_observer(planner, &mut center_planner)?;
+
+    center_planner.plan_roads(planner);
+    plan_lab_cluster(planner, center)?;
+
+    for source in excavator_positions {
+        planner.plan_road_between(source, center, BuildArterialRoads)?;
+    }
+
+    Ok(())
+}
+
+// The central spawns take the spawn slots at RCL7 and RCL8 first, source spawns only get whatever is left
+const CENTRAL_SPAWN_STEPS: [ColonyStep; 2] = [ColonyStep::BuildLvl7, ColonyStep::BuildLvl8];
+
+fn plan_extra_spawns(planner: &mut ColonyPlanner, mut plan_spawn: impl FnMut(&mut ColonyPlanner, ColonyStep) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    for step in CENTRAL_SPAWN_STEPS {
+        if planner.count_left_for(PlannedStructure::ExtraSpawn, step) > 0 {
+            plan_spawn(planner, step)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn plan_stamp_layout(planner: &mut ColonyPlanner, center: RoomXY) -> anyhow::Result<()> {
+    use ColonyStep::*;
+
+    let mut stamp_planner = StampPlanner::new(planner, center);
+    stamp_planner.plan_core(planner)?;
+    stamp_planner.plan_core_structure(planner, BuildLvl7, PlannedStructure::Factory)?;
+    stamp_planner.plan_core_structure(planner, BuildLvl8, PlannedStructure::PowerSpawn)?;
+    stamp_planner.plan_labs(planner)?;
+    stamp_planner.plan_structure(planner, BuildLvl8, PlannedStructure::Nuker)?;
+    stamp_planner.plan_structure(planner, BuildLvl3, PlannedStructure::Tower)?;
+
+    plan_controller(planner, center)?;
+    plan_extra_spawns(planner, |planner, step| stamp_planner.plan_core_structure(planner, step, PlannedStructure::ExtraSpawn))?;
+    let excavator_positions = plan_sources(planner, center)?;
+
+    plan_extensions_towers_observer(planner, &mut stamp_planner)?;
+
+    stamp_planner.plan_roads(planner);
+
+    for source in excavator_positions {
+        planner.plan_road_between(source, center, BuildArterialRoads)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use screeps::{StructureType, Terrain};
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    fn assert_valid(room: &RoomDescription, plan: &ColonyPlan) {
+        let mut structures = HashMap::new();
+        let mut roads = Vec::new();
+        let mut counts: HashMap<StructureType, u32> = HashMap::new();
+
+        for step in ColonyStep::iter() {
+            let plan_step = &plan.steps[&step];
+            roads.extend(plan_step.new_roads.iter().copied());
+
+            for (xy, ty) in &plan_step.new_structures {
+                assert!(structures.insert(*xy, *ty).is_none(), "{} has two structures planned at {xy}", room.name);
+                assert!(*ty == StructureType::Extractor || !room.is_wall(*xy), "{} has a {ty:?} planned on a wall at {xy}", room.name);
+                *counts.entry(*ty).or_default() += 1;
+            }
+
+            for (ty, count) in &counts {
+                let allowed = ty.controller_structures(u32::from(step.controller_level()));
+                assert!(*count <= allowed, "{} plans {count} {ty:?} by {step:?} where only {allowed} are allowed", room.name);
+            }
+        }
+
+        // Source spawns are planned on the road leaving their container
+        let source_spawns = plan.sources.values().filter_map(|source| source.spawn.as_ref()).map(|spawn| spawn.pos.xy()).collect_vec();
+
+        for xy in roads {
+            assert!(room.terrain.get_xy(xy) != Terrain::Wall, "{} has a road planned on a wall at {xy}", room.name);
+            assert!(
+                source_spawns.contains(&xy) || structures.get(&xy).is_none_or(|ty| matches!(ty, StructureType::Container | StructureType::Rampart)),
+                "{} has a road under a {:?} at {xy}", room.name, structures[&xy]
+            );
+        }
+
+        assert_eq!(plan.sources.len(), room.sources.len(), "{} is missing source plans", room.name);
+        assert_eq!(plan.center.extra_spawns.len(), CENTRAL_SPAWN_STEPS.len(), "{} is missing central spawns", room.name);
+        assert!(!plan.entry_links.is_empty(), "{} has no entry links", room.name);
+    }
+
+    fn assert_plans(json: &str) {
+        let room = RoomDescription::from_json(json).unwrap();
+
+        for strategy in PlannerStrategy::iter() {
+            let plan = ColonyPlan::create_from(&room, strategy, DEFAULT_PERIMETER_STEP)
+                .unwrap_or_else(|err| panic!("Unable to plan {} with {strategy:?}: {err}", room.name));
+            assert_valid(&room, &plan);
+        }
+    }
+
+    #[test]
+    fn plans_the_rcl7_and_rcl8_spawns_centrally() {
+        let room = RoomDescription::from_json(include_str!("fixtures/W7N3.json")).unwrap();
+
+        for strategy in PlannerStrategy::iter() {
+            let plan = ColonyPlan::create_from(&room, strategy, DEFAULT_PERIMETER_STEP).unwrap();
+            let spawn_steps = ColonyStep::iter()
+                .flat_map(|step| plan.steps[&step].new_structures.values().filter(|ty| **ty == StructureType::Spawn).map(move |_| step))
+                .collect_vec();
+            assert_eq!(spawn_steps, vec![ColonyStep::BuildSpawn, ColonyStep::BuildLvl7, ColonyStep::BuildLvl8], "Unexpected spawn steps with {strategy:?}");
+
+            assert_eq!(plan.center.extra_spawns.len(), 2, "Unexpected central spawns with {strategy:?}");
+        }
+    }
+
+    #[test]
+    fn plans_a_limited_number_of_candidates() {
+        let room = RoomDescription::from_json(include_str!("fixtures/W33N17.json")).unwrap();
+        let candidates = ColonyPlan::plan_candidates(&room, None, DEFAULT_PERIMETER_STEP);
+
+        assert_eq!(candidates.len(), MAX_PLANNED_CANDIDATES);
+        assert!(PlannerStrategy::iter().all(|strategy| candidates.iter().any(|candidate| candidate.strategy == strategy)));
+    }
+
+    #[test]
+    fn plans_room_with_two_sources() {
+        assert_plans(include_str!("fixtures/W7N3.json"));
+    }
+
+    #[test]
+    fn plans_room_with_one_source() {
+        assert_plans(include_str!("fixtures/E12S21.json"));
+    }
+
+    #[test]
+    fn plans_swampy_room_with_four_exits() {
+        assert_plans(include_str!("fixtures/W33N17.json"));
     }
 }
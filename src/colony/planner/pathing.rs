@@ -0,0 +1,69 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}};
+
+use screeps::{Direction, RoomXY};
+
+pub(super) fn is_border(xy: RoomXY) -> bool {
+    xy.x.u8() == 0 || xy.y.u8() == 0 || xy.x.u8() == 49 || xy.y.u8() == 49
+}
+
+pub(super) fn side_of(exit: RoomXY) -> Direction {
+    if exit.y.u8() == 0 { Direction::Top } else if exit.x.u8() == 49 { Direction::Right } else if exit.y.u8() == 49 { Direction::Bottom } else { Direction::Left }
+}
+
+// None when no tile in range of the target can be reached
+pub fn shortest_path(from: RoomXY, to: RoomXY, range: u8, cost: impl Fn(RoomXY) -> Option<u32>) -> Option<Vec<RoomXY>> {
+    let mut dists = HashMap::from([(from, 0)]);
+    let mut parents = HashMap::new();
+    let mut queue = BinaryHeap::from([(Reverse(0), from)]);
+    let mut end = None;
+
+    while let Some((Reverse(dist), pos)) = queue.pop() {
+        if dists[&pos] < dist { continue; }
+
+        if pos.get_range_to(to) <= range {
+            end = Some(pos);
+            break;
+        }
+
+        for neigh in pos.neighbors() {
+            let neigh_cost = if neigh == to { cost(neigh).unwrap_or(1) }
+                else if is_border(neigh) { continue }
+                else if let Some(cost) = cost(neigh) { cost }
+                else { continue };
+            let neigh_dist = dist + neigh_cost;
+
+            if dists.get(&neigh).is_some_and(|old_dist| *old_dist <= neigh_dist) { continue; }
+
+            dists.insert(neigh, neigh_dist);
+            parents.insert(neigh, pos);
+            queue.push((Reverse(neigh_dist), neigh));
+        }
+    }
+
+    let mut path = Vec::new();
+    let mut current = end?;
+    while current != from {
+        path.push(current);
+        current = parents[&current];
+    }
+
+    path.reverse();
+    Some(path)
+}
+
+// Steps from the start to every tile it can reach, walking only over passable tiles
+pub fn walking_distances(from: RoomXY, passable: impl Fn(RoomXY) -> bool) -> HashMap<RoomXY, u32> {
+    let mut dists = HashMap::from([(from, 0)]);
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(pos) = queue.pop_front() {
+        for neigh in pos.neighbors() {
+            if dists.contains_key(&neigh) || !passable(neigh) { continue; }
+
+            dists.insert(neigh, dists[&pos] + 1);
+            queue.push_back(neigh);
+        }
+    }
+
+    dists
+}
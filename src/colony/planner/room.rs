@@ -0,0 +1,88 @@
+use screeps::{HasId, HasPosition, LocalRoomTerrain, Room, RoomName, RoomXY, Source, Terrain, find};
+use serde::{Deserialize, Serialize};
+use anyhow::anyhow;
+
+use crate::movement::traffic::RoomTraffic;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomDescription {
+    pub name: RoomName,
+    #[serde(with = "terrain_string")]
+    pub terrain: LocalRoomTerrain,
+    pub sources: Vec<SourceDescription>,
+    pub controller: RoomXY,
+    pub mineral: Option<RoomXY>,
+    pub center: Option<RoomXY>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic: Option<RoomTraffic>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SourceDescription {
+    pub id: screeps::ObjectId<Source>,
+    pub pos: RoomXY
+}
+
+impl RoomDescription {
+    pub fn of_room(room: &Room) -> anyhow::Result<Self> {
+        let controller = room.controller().ok_or(anyhow!("No controller in {}", room.name()))?;
+
+        let center = room.find(find::FLAGS, None).into_iter()
+            .find(|flag| flag.name().to_lowercase().contains("center"))
+            .map(|flag| flag.pos().xy());
+
+        Ok(Self {
+            name: room.name(),
+            terrain: LocalRoomTerrain::from(room.get_terrain()),
+            sources: room.find(find::SOURCES, None).into_iter()
+                .map(|source| SourceDescription { id: source.id(), pos: source.pos().xy() })
+                .collect(),
+            controller: controller.pos().xy(),
+            mineral: room.find(find::MINERALS, None).first().map(|mineral| mineral.pos().xy()),
+            center,
+            traffic: None
+        })
+    }
+
+    pub fn with_traffic(mut self, traffic: Option<&RoomTraffic>) -> Self {
+        self.traffic = traffic.cloned();
+        self
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn is_wall(&self, xy: RoomXY) -> bool {
+        self.terrain.get_xy(xy) == Terrain::Wall
+    }
+
+    pub fn exits(&self) -> impl Iterator<Item = RoomXY> {
+        (0..50_u8).flat_map(|i| [(i, 0), (i, 49), (0, i), (49, i)])
+            .map(|(x, y)| RoomXY::try_from((x, y)).unwrap())
+            .filter(|xy| !self.is_wall(*xy))
+    }
+}
+
+mod terrain_string {
+    use screeps::{LocalRoomTerrain, ROOM_AREA};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(terrain: &LocalRoomTerrain, serializer: S) -> Result<S::Ok, S::Error> {
+        let encoded: String = terrain.get_bits().iter().map(|bits| char::from(b'0' + (bits & 0b11))).collect();
+        serializer.serialize_str(&encoded)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LocalRoomTerrain, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+
+        let bits: Vec<u8> = encoded.chars()
+            .map(|c| c.to_digit(4).map(|digit| digit as u8).ok_or(D::Error::custom(format!("Invalid terrain character {c}"))))
+            .collect::<Result<_, _>>()?;
+
+        let bits: Box<[u8; ROOM_AREA]> = bits.into_boxed_slice().try_into()
+            .map_err(|_| D::Error::custom(format!("Terrain should have {ROOM_AREA} tiles")))?;
+
+        Ok(LocalRoomTerrain::new_from_bits(bits))
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt::Display;
+
+use screeps::RoomXY;
+
+use crate::colony::planner::{pathing::walking_distances, state::{ColonyPlanner, PlannedStructure}};
+
+const SOURCE_DISTANCE_WEIGHT: f32 = 3.0;
+const CONTROLLER_DISTANCE_WEIGHT: f32 = 2.0;
+const ROAD_COUNT_WEIGHT: f32 = 0.5;
+const EXTENSION_DISTANCE_WEIGHT: f32 = 0.2;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PlanScore {
+    pub source_distance: u32,
+    pub controller_distance: u32,
+    pub road_count: u32,
+    pub extension_distance: u32,
+    pub failed: bool
+}
+
+impl PlanScore {
+    pub fn failed() -> Self {
+        Self { failed: true, ..Default::default() }
+    }
+
+    pub fn of_planner(planner: &ColonyPlanner, center: RoomXY) -> Self {
+        // Plans which can't reach their sources or controller are scored like failed ones
+        let source_distance = planner.room.sources.iter()
+            .filter_map(|source| planner.structures2pos.get(&PlannedStructure::SourceContainer(source.id))?.iter().next().copied())
+            .map(|container| planner.distance_at(center, container, planner.structures[&container]))
+            .sum::<Option<u32>>();
+        let controller_distance = planner.find_path_between(center, planner.room.controller, None).map(|path| path.len() as u32);
+        let (Some(source_distance), Some(controller_distance)) = (source_distance, controller_distance) else { return Self::failed() };
+
+        let passable = |xy: RoomXY| !planner.room.is_wall(xy) && planner.pos2structure.get(&xy).is_none_or(PlannedStructure::walkable);
+        let walking_dists = walking_distances(center, passable);
+        let extension_distance = planner.structures2pos.get(&PlannedStructure::Extension).into_iter().flatten()
+            .filter_map(|extension| extension.neighbors().into_iter().filter_map(|neigh| walking_dists.get(&neigh)).min())
+            .map(|dist| dist + 1)
+            .sum();
+
+        Self {
+            source_distance,
+            controller_distance,
+            road_count: planner.roads.len() as u32,
+            extension_distance,
+            failed: false
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        if self.failed { return f32::INFINITY }
+
+        self.source_distance as f32 * SOURCE_DISTANCE_WEIGHT
+            + self.controller_distance as f32 * CONTROLLER_DISTANCE_WEIGHT
+            + self.road_count as f32 * ROAD_COUNT_WEIGHT
+            + self.extension_distance as f32 * EXTENSION_DISTANCE_WEIGHT
+    }
+}
+
+impl Display for PlanScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.failed { return write!(f, "failed") }
+
+        write!(f, "sources {} (x{SOURCE_DISTANCE_WEIGHT}), controller {} (x{CONTROLLER_DISTANCE_WEIGHT}), roads {} (x{ROAD_COUNT_WEIGHT}), extensions {} (x{EXTENSION_DISTANCE_WEIGHT}) => {:.1}",
+            self.source_distance, self.controller_distance, self.road_count, self.extension_distance, self.total())
+    }
+}
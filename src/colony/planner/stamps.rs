@@ -0,0 +1,294 @@
+use std::{cmp::Reverse, collections::{HashMap, HashSet, VecDeque}};
+
+use itertools::Itertools;
+use screeps::{RoomXY, StructureType};
+use anyhow::{anyhow, bail};
+use strum::IntoEnumIterator;
+
+use crate::colony::{planner::{MAX_CANDIDATES, MIN_CANDIDATE_DIST, MIN_ENTRANCE_DIST, center::{StructureSlots, plan_serving_roads}, pathing::{shortest_path, walking_distances}, room::RoomDescription, state::{ColonyPlanner, PlannedStructure}}, steps::ColonyStep};
+
+const CORE_STAMP: [&str; 5] = [
+    "+c+++",
+    "+STL+",
+    "+s.?+",
+    "+???+",
+    "+++++",
+];
+const CORE_ORIGIN: (i8, i8) = (1, 1);
+
+const FLOWER_STAMP: [&str; 5] = [
+    "ee+ee",
+    "e+e+e",
+    "+eee+",
+    "e+e+e",
+    "ee+ee",
+];
+const FLOWER_ORIGIN: (i8, i8) = (2, 2);
+
+const LAB_STAMP: [&str; 4] = [
+    " ll+",
+    "ll+l",
+    "l+ll",
+    "+ll ",
+];
+const LAB_ORIGIN: (i8, i8) = (1, 1);
+const LAB_INPUTS: [(i8, i8); 2] = [(0, 0), (1, 1)];
+
+struct Stamp {
+    tiles: Vec<((i8, i8), char)>
+}
+
+impl Stamp {
+    fn new(rows: &[&str], origin: (i8, i8)) -> Self {
+        let tiles = rows.iter().enumerate()
+            .flat_map(|(y, row)| row.chars().enumerate().map(move |(x, c)| ((x as i8 - origin.0, y as i8 - origin.1), c)))
+            .filter(|(_, c)| *c != ' ')
+            .collect();
+
+        Self { tiles }
+    }
+
+    fn at(&self, anchor: RoomXY) -> Option<Vec<(RoomXY, char)>> {
+        self.tiles.iter()
+            .map(|((dx, dy), c)| Some((anchor.checked_add((*dx, *dy))?, *c)))
+            .collect()
+    }
+
+    fn fits(&self, planner: &ColonyPlanner, occupied: &HashSet<RoomXY>, blocked: &HashSet<RoomXY>, anchor: RoomXY) -> bool {
+        self.at(anchor).is_some_and(|tiles| tiles.into_iter().all(|(pos, c)| {
+            if blocked.contains(&pos) || occupied.contains(&pos) || !planner.is_free_at(pos) { return false }
+            c == '+' || !planner.roads.contains_key(&pos)
+        }))
+    }
+}
+
+fn blocked_tiles(room: &RoomDescription) -> HashSet<RoomXY> {
+    let exits: Vec<_> = room.exits().collect();
+
+    (0..50).cartesian_product(0..50)
+        .map(|(x, y)| RoomXY::try_from((x, y)).unwrap())
+        .filter(|xy| {
+            room.is_wall(*xy)
+                || xy.x.u8() == 0 || xy.y.u8() == 0 || xy.x.u8() == 49 || xy.y.u8() == 49
+                || exits.iter().any(|exit| exit.get_range_to(*xy) <= 1)
+        })
+        .collect()
+}
+
+fn distance_transform(blocked: &HashSet<RoomXY>) -> HashMap<RoomXY, u8> {
+    let mut dists: HashMap<_, _> = blocked.iter().map(|pos| (*pos, 0)).collect();
+    let mut queue: VecDeque<_> = blocked.iter().copied().collect();
+
+    while let Some(pos) = queue.pop_front() {
+        for neigh in pos.neighbors() {
+            if dists.contains_key(&neigh) { continue; }
+
+            dists.insert(neigh, dists[&pos] + 1);
+            queue.push_back(neigh);
+        }
+    }
+
+    dists
+}
+
+fn closest_fitting(planner: &ColonyPlanner, stamp: &Stamp, occupied: &HashSet<RoomXY>, blocked: &HashSet<RoomXY>, walking_dists: &HashMap<RoomXY, u32>) -> Option<RoomXY> {
+    walking_dists.iter()
+        .sorted_by_key(|(pos, dist)| (**dist, **pos))
+        .map(|(pos, _)| *pos)
+        .find(|anchor| stamp.fits(planner, occupied, blocked, *anchor))
+}
+
+fn first_step_allowing(ty: StructureType, index: usize) -> anyhow::Result<ColonyStep> {
+    ColonyStep::iter()
+        .find(|step| ty.controller_structures(u32::from(step.controller_level())) as usize > index)
+        .ok_or(anyhow!("Too many {ty:?} planned"))
+}
+
+fn plan_lab_structures(planner: &mut ColonyPlanner, tiles: &[(RoomXY, char)], anchor: RoomXY) -> anyhow::Result<HashMap<RoomXY, ColonyStep>> {
+    let inputs: Vec<_> = LAB_INPUTS.iter().filter_map(|offset| anchor.checked_add(*offset)).collect();
+    let outputs = tiles.iter()
+        .filter(|(pos, c)| *c == 'l' && !inputs.contains(pos))
+        .map(|(pos, _)| *pos)
+        .sorted_by_key(|pos| (inputs.iter().map(|input| input.get_range_to(*pos)).sum::<u8>(), *pos));
+
+    let labs = inputs.iter().map(|pos| (*pos, PlannedStructure::InputLab))
+        .chain(outputs.map(|pos| (pos, PlannedStructure::OutputLab)));
+
+    let mut placed_structures = HashMap::new();
+    for (i, (pos, structure)) in labs.enumerate() {
+        let step = first_step_allowing(StructureType::Lab, i)?;
+        planner.plan_structure(pos, step, structure)?;
+        placed_structures.insert(pos, step);
+    }
+
+    Ok(placed_structures)
+}
+
+pub fn plan_lab_cluster(planner: &mut ColonyPlanner, center: RoomXY) -> anyhow::Result<()> {
+    let labs = Stamp::new(&LAB_STAMP, LAB_ORIGIN);
+    let blocked = blocked_tiles(&planner.room);
+    let walking_dists = walking_distances(center, |xy| !planner.room.is_wall(xy));
+
+    let anchor = closest_fitting(planner, &labs, &HashSet::new(), &blocked, &walking_dists).ok_or(anyhow!("No space for labs"))?;
+    let tiles = labs.at(anchor).ok_or(anyhow!("Stamp is outside of room"))?;
+    let placed_structures = plan_lab_structures(planner, &tiles, anchor)?;
+
+    let first_step = placed_structures.values().min().copied().unwrap_or(ColonyStep::BuildLvl6);
+    for (pos, _) in tiles.into_iter().filter(|(_, c)| *c == '+') {
+        planner.plan_road(pos, first_step);
+    }
+
+    Ok(())
+}
+
+pub fn find_core_candidates(room: &RoomDescription) -> Vec<RoomXY> {
+    let blocked = blocked_tiles(room);
+    let space = distance_transform(&blocked);
+
+    let exits: Vec<_> = room.exits().collect();
+
+    let candidates = space.iter()
+        .filter_map(|(pos, space)| Some((pos.checked_add((CORE_ORIGIN.0 - 2, CORE_ORIGIN.1 - 2))?, *space)))
+        .filter(|(_, space)| *space >= 3)
+        .filter(|(pos, _)| exits.iter().all(|exit| exit.get_range_to(*pos) >= MIN_ENTRANCE_DIST))
+        .sorted_by_key(|(pos, space)| (Reverse(*space), *pos))
+        .dedup_by(|(p1, _), (p2, _)| p1.get_range_to(*p2) <= MIN_CANDIDATE_DIST)
+        .map(|(pos, _)| pos)
+        .take(MAX_CANDIDATES * 4);
+
+    candidates
+        .sorted_by_key(|candidate| {
+            room.sources.iter().map(|source| source.pos)
+                .chain([room.controller])
+                .map(|poi| shortest_path(*candidate, poi, 1, |xy| (!room.is_wall(xy)).then_some(1)).map(|path| path.len()))
+                .sum::<Option<usize>>()
+                .unwrap_or(usize::MAX)
+        })
+        .take(MAX_CANDIDATES)
+        .collect()
+}
+
+pub struct StampPlanner {
+    center: RoomXY,
+    blocked: HashSet<RoomXY>,
+    walking_dists: HashMap<RoomXY, u32>,
+
+    occupied: HashSet<RoomXY>,
+    roads: HashSet<RoomXY>,
+    slots: VecDeque<RoomXY>,
+    core_slots: VecDeque<RoomXY>,
+    placed_structures: HashMap<RoomXY, ColonyStep>
+}
+
+impl StampPlanner {
+    pub fn new(planner: &ColonyPlanner, center: RoomXY) -> Self {
+        Self {
+            center,
+            blocked: blocked_tiles(&planner.room),
+            walking_dists: walking_distances(center, |xy| !planner.room.is_wall(xy)),
+            occupied: HashSet::new(),
+            roads: HashSet::new(),
+            slots: VecDeque::new(),
+            core_slots: VecDeque::new(),
+            placed_structures: HashMap::new()
+        }
+    }
+
+    fn place(&mut self, planner: &ColonyPlanner, stamp: &Stamp, anchor: RoomXY) -> anyhow::Result<Vec<(RoomXY, char)>> {
+        let tiles = stamp.at(anchor).ok_or(anyhow!("Stamp is outside of room"))?;
+        if !stamp.fits(planner, &self.occupied, &self.blocked, anchor) { bail!("Stamp doesn't fit at {anchor}") }
+
+        for (pos, c) in &tiles {
+            self.occupied.insert(*pos);
+            if matches!(c, '+' | 'c') { self.roads.insert(*pos); }
+        }
+
+        Ok(tiles)
+    }
+
+    pub fn plan_core(&mut self, planner: &mut ColonyPlanner) -> anyhow::Result<()> {
+        use ColonyStep::*;
+
+        let core = Stamp::new(&CORE_STAMP, CORE_ORIGIN);
+        for (pos, c) in self.place(planner, &core, self.center)? {
+            let structure = match c {
+                'S' => (BuildLvl4, PlannedStructure::Storage),
+                's' => (BuildSpawn, PlannedStructure::MainSpawn),
+                'L' => (BuildLvl5, PlannedStructure::CentralLink),
+                'T' => (BuildLvl6, PlannedStructure::Terminal),
+                'c' => (BuildBufferAndSourceContainers, PlannedStructure::ContainerStorage),
+                '?' => {
+                    planner.reserve(pos);
+                    self.core_slots.push_back(pos);
+                    continue;
+                },
+                // The filler stands in the middle of the core, so nothing may be planned there
+                '.' => {
+                    planner.reserve(pos);
+                    continue;
+                },
+                _ => continue
+            };
+
+            planner.plan_structure(pos, structure.0, structure.1)?;
+            self.placed_structures.insert(pos, structure.0);
+        }
+
+        Ok(())
+    }
+
+    pub fn plan_core_structure(&mut self, planner: &mut ColonyPlanner, step: ColonyStep, structure: PlannedStructure) -> anyhow::Result<()> {
+        let pos = self.core_slots.pop_front().ok_or(anyhow!("No core slot left for {structure:?}"))?;
+        planner.plan_structure(pos, step, structure)?;
+        self.placed_structures.insert(pos, step);
+
+        Ok(())
+    }
+
+    fn closest_fitting(&self, planner: &ColonyPlanner, stamp: &Stamp) -> Option<RoomXY> {
+        closest_fitting(planner, stamp, &self.occupied, &self.blocked, &self.walking_dists)
+    }
+
+    pub fn plan_labs(&mut self, planner: &mut ColonyPlanner) -> anyhow::Result<()> {
+        let labs = Stamp::new(&LAB_STAMP, LAB_ORIGIN);
+        let anchor = self.closest_fitting(planner, &labs).ok_or(anyhow!("No space for labs"))?;
+
+        let tiles = self.place(planner, &labs, anchor)?;
+        let placed_structures = plan_lab_structures(planner, &tiles, anchor)?;
+        self.placed_structures.extend(placed_structures);
+
+        Ok(())
+    }
+
+    fn plan_flower(&mut self, planner: &ColonyPlanner) -> anyhow::Result<()> {
+        let flower = Stamp::new(&FLOWER_STAMP, FLOWER_ORIGIN);
+        let anchor = self.closest_fitting(planner, &flower).ok_or(anyhow!("No space for extensions"))?;
+
+        let slots = self.place(planner, &flower, anchor)?.into_iter()
+            .filter(|(_, c)| *c == 'e')
+            .map(|(pos, _)| pos)
+            .sorted_by_key(|pos| (self.center.get_range_to(*pos), *pos));
+
+        self.slots.extend(slots);
+        Ok(())
+    }
+
+    pub fn plan_roads(self, planner: &mut ColonyPlanner) {
+        plan_serving_roads(planner, &self.roads, &self.placed_structures);
+    }
+}
+
+impl StructureSlots for StampPlanner {
+    fn next_structure_pos(&mut self, planner: &ColonyPlanner, step: ColonyStep) -> anyhow::Result<RoomXY> {
+        loop {
+            if let Some(pos) = self.slots.pop_front() {
+                if !planner.is_buildable_at(pos) { continue; }
+
+                self.placed_structures.insert(pos, step);
+                return Ok(pos)
+            }
+
+            self.plan_flower(planner)?;
+        }
+    }
+}
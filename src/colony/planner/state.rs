@@ -1,16 +1,17 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 use itertools::Itertools;
-use screeps::{CostMatrix, CostMatrixSet, FindPathOptions, HasPosition, Path, Position, Room, RoomTerrain, RoomXY, Source, Step, StructureType, Terrain, find, pathfinder::SingleRoomCostResult};
+use screeps::{LocalCostMatrix, LocalRoomTerrain, Position, RoomXY, Source, StructureType, Terrain};
 use serde::{Deserialize, Serialize};
 use anyhow::anyhow;
 use strum::IntoEnumIterator;
 
-use crate::{colony::{plan::{CenterPlan, ColonyPlan, ColonyPlanStep, MineralPlan, SourcePlan, SourcesPlan, refs::{PlannedStructureRef, PlannedStructureRefs}}, steps::ColonyStep}, domain_traits::HasId};
+use crate::{colony::{planner::{pathing::shortest_path, room::RoomDescription}, plan::{CenterPlan, ColonyPlan, ColonyPlanStep, ControllerPlan, MineralPlan, PerimeterPlan, SourcePlan, SourcesPlan, refs::{OptionalPlannedStructureRef, PlannedStructureRef, PlannedStructureRefs}}, steps::ColonyStep}, domain_traits::HasId};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum PlannedStructure {
     MainSpawn,
+    ExtraSpawn,
     SourceSpawn(screeps::ObjectId<Source>),
     SourceContainer(screeps::ObjectId<Source>),
     SourceLink(screeps::ObjectId<Source>),
@@ -23,14 +24,24 @@ pub enum PlannedStructure {
     CentralLink,
     Extractor,
     MineralContainer,
+    ControllerContainer,
+    ControllerLink,
+    EntryLink,
     Observer,
+    InputLab,
+    OutputLab,
+    Factory,
+    PowerSpawn,
+    Nuker,
+    Rampart,
+    Wall,
 }
 
 impl PlannedStructure {
-    fn walkable(&self) -> bool {
+    pub fn walkable(&self) -> bool {
         use PlannedStructure::*;
 
-        matches!(self, SourceContainer(_) | ContainerStorage | MineralContainer)
+        matches!(self, SourceContainer(_) | ContainerStorage | MineralContainer | ControllerContainer | Rampart)
     }
 
     fn buildable_on_wall(&self) -> bool {
@@ -44,19 +55,30 @@ impl PlannedStructure {
 
         match self {
             PlannedStructure::MainSpawn
+            | PlannedStructure::ExtraSpawn
             | PlannedStructure::SourceSpawn(_) => Spawn,
             PlannedStructure::SourceContainer(_)
             | PlannedStructure::ContainerStorage
-            | PlannedStructure::MineralContainer => Container,
+            | PlannedStructure::MineralContainer
+            | PlannedStructure::ControllerContainer => Container,
             PlannedStructure::Extension
             | PlannedStructure::SourceExtension(_) => Extension,
             PlannedStructure::CentralLink
-            | PlannedStructure::SourceLink(_) => Link,
+            | PlannedStructure::SourceLink(_)
+            | PlannedStructure::ControllerLink
+            | PlannedStructure::EntryLink => Link,
             PlannedStructure::Storage => Storage,
             PlannedStructure::Tower => Tower,
             PlannedStructure::Terminal => Terminal,
             PlannedStructure::Extractor => Extractor,
             PlannedStructure::Observer => Observer,
+            PlannedStructure::InputLab
+            | PlannedStructure::OutputLab => Lab,
+            PlannedStructure::Factory => Factory,
+            PlannedStructure::PowerSpawn => PowerSpawn,
+            PlannedStructure::Nuker => Nuker,
+            PlannedStructure::Rampart => Rampart,
+            PlannedStructure::Wall => Wall,
         }
     }
 }
@@ -93,12 +115,14 @@ impl TilePathing {
 }
 
 pub struct ColonyPlanner {
-    pub cost_matrix: CostMatrix,
-    pub terrain: RoomTerrain,
-    pub room: Room,
+    pub cost_matrix: LocalCostMatrix,
+    pub terrain: LocalRoomTerrain,
+    pub room: RoomDescription,
 
     pub roads: HashMap<RoomXY, ColonyStep>,
     pub structures: HashMap<RoomXY, ColonyStep>,
+    pub reserved: HashSet<RoomXY>,
+    pub standing_tiles: Vec<RoomXY>,
 
     pub pos2structure: HashMap<RoomXY, PlannedStructure>,
     pub structures2pos: HashMap<PlannedStructure, HashSet<RoomXY>>,
@@ -106,13 +130,11 @@ pub struct ColonyPlanner {
 }
 
 impl ColonyPlanner {
-    pub fn new(room: Room) -> Self {
-        let terrain = room.get_terrain();
-        let cost_matrix = CostMatrix::new();
-        for x in 0..50 {
-            for y in 0..50 {
-                cost_matrix.set(x, y, TilePathing::from(terrain.get(x, y)).cost());
-            }
+    pub fn new(room: RoomDescription) -> Self {
+        let terrain = room.terrain.clone();
+        let mut cost_matrix = LocalCostMatrix::new();
+        for (xy, cost) in cost_matrix.iter_mut() {
+            *cost = TilePathing::from(terrain.get_xy(xy)).cost();
         }
 
         ColonyPlanner {
@@ -121,6 +143,8 @@ impl ColonyPlanner {
             room,
             roads: HashMap::new(),
             structures: HashMap::new(),
+            reserved: HashSet::new(),
+            standing_tiles: Vec::new(),
             pos2structure: HashMap::new(),
             structures2pos: HashMap::new(),
             structure_type_steps: HashMap::new()
@@ -153,7 +177,20 @@ impl ColonyPlanner {
             sources: self.compile_sources(center.pos.xy())?,
             mineral: self.compile_mineral(center.pos.xy())?,
             center,
-            controller: PlannedStructureRef::new(self.room.controller().ok_or(anyhow!("No controller"))?.pos())
+            perimeter: self.compile_perimeter(),
+            controller: self.compile_controller()?,
+            entry_links: self.get_structure_refs(PlannedStructure::EntryLink)
+        })
+    }
+
+    fn compile_controller(&self) -> anyhow::Result<ControllerPlan> {
+        use PlannedStructure::*;
+
+        Ok(ControllerPlan {
+            controller: PlannedStructureRef::new(Position::new(self.room.controller.x, self.room.controller.y, self.room.name)),
+            container: self.get_structure_ref(ControllerContainer)?.into(),
+            link: OptionalPlannedStructureRef(self.get_structure_ref(ControllerLink).ok()),
+            standing_tiles: self.standing_tiles.iter().map(|tile| Position::new(tile.x, tile.y, self.room.name)).collect()
         })
     }
 
@@ -165,29 +202,33 @@ impl ColonyPlanner {
         Ok(CenterPlan {
             pos: storage_ref.pos,
             spawn: self.get_structure_ref(MainSpawn)?,
+            extra_spawns: self.get_structure_refs(ExtraSpawn),
             storage: storage_ref.into(),
             container_storage: self.get_structure_ref(ContainerStorage)?.into(),
             link: self.get_structure_ref(CentralLink)?.into(),
             terminal: self.get_structure_ref(Terminal)?.into(),
             observer: self.get_structure_ref(Observer)?.into(),
+            factory: self.get_structure_ref(Factory)?.into(),
+            power_spawn: self.get_structure_ref(PowerSpawn)?.into(),
+            nuker: self.get_structure_ref(Nuker)?.into(),
             towers: self.get_structure_refs(Tower),
-            extensions: self.get_structure_refs(Extension)
+            extensions: self.get_structure_refs(Extension),
+            input_labs: self.get_structure_refs(InputLab),
+            output_labs: self.get_structure_refs(OutputLab)
         })
     }
 
     fn compile_sources(&self, center: RoomXY) -> anyhow::Result<SourcesPlan> {
         use PlannedStructure::*;
 
-        self.room.find(find::SOURCES, None).into_iter()
-            .map(|source| screeps::HasId::id(&source))
+        self.room.sources.iter()
+            .map(|source| source.id)
             .map(|source| {
                 let container = self.get_structure_ref(SourceContainer(source))?;
-                let spawn = self.get_structure_ref(SourceSpawn(source))?;
 
                 let plan = SourcePlan {
-                    spawn_direction: spawn.pos.get_direction_to(container.pos).unwrap(),
-                    distance: self.find_path_between(center, container.pos.xy(), None).len() as u32,
-                    spawn: spawn.into(),
+                    distance: self.distance_at(center, container.pos.xy(), self.structures[&container.pos.xy()]).ok_or(anyhow!("No path to {source} container"))?,
+                    spawn: OptionalPlannedStructureRef(self.get_structure_ref(SourceSpawn(source)).ok()),
                     container: container.into(),
                     link: self.get_structure_ref(SourceLink(source))?.into(),
                     extensions: self.get_structure_refs(SourceExtension(source)),
@@ -203,12 +244,19 @@ impl ColonyPlanner {
         let container = self.get_structure_ref(MineralContainer)?;
 
         Ok(MineralPlan {
-            distance: self.find_path_between(center, container.pos.xy(), None).len() as u32,
+            distance: self.find_path_between(center, container.pos.xy(), None).ok_or(anyhow!("No path to mineral container"))?.len() as u32,
             container: container.into(),
             extractor: self.get_structure_ref(Extractor)?.into(),
         })
     }
 
+    fn compile_perimeter(&self) -> PerimeterPlan {
+        PerimeterPlan {
+            ramparts: self.get_structure_refs(PlannedStructure::Rampart),
+            walls: self.get_structure_refs(PlannedStructure::Wall)
+        }
+    }
+
     pub fn get_structure_ref<T: HasId>(&self, structure: PlannedStructure) -> anyhow::Result<PlannedStructureRef<T>> {
         self.structures2pos.get(&structure)
             .ok_or(anyhow!("No {structure:?} was found"))
@@ -217,7 +265,7 @@ impl ColonyPlanner {
                 else if positions.len() > 1 { Err(anyhow!("Unable to determine unique {structure:?}")) }
                 else { Ok(*positions.iter().next().unwrap()) }
             })
-            .map(|pos| PlannedStructureRef::new(Position::new(pos.x, pos.y, self.room.name())))
+            .map(|pos| PlannedStructureRef::new(Position::new(pos.x, pos.y, self.room.name)))
     }
 
     pub fn get_structure_refs<T: HasId>(&self, structure: PlannedStructure) -> PlannedStructureRefs<T> {
@@ -225,7 +273,7 @@ impl ColonyPlanner {
 
         PlannedStructureRefs(positions.iter()
             .copied()
-            .map(|pos| PlannedStructureRef::new(Position::new(pos.x, pos.y, self.room.name())))
+            .map(|pos| PlannedStructureRef::new(Position::new(pos.x, pos.y, self.room.name)))
             .collect())
     }
 
@@ -238,7 +286,12 @@ impl ColonyPlanner {
     }
 
     pub fn is_free_at(&self, pos: RoomXY) -> bool {
-        self.terrain.get(pos.x.u8(), pos.y.u8()) != Terrain::Wall && !self.pos2structure.contains_key(&pos)
+        self.terrain.get_xy(pos) != Terrain::Wall && !self.pos2structure.contains_key(&pos) && !self.reserved.contains(&pos)
+    }
+
+    // Roads planned so far, like the one to the controller, are kept clear of structures
+    pub fn is_buildable_at(&self, pos: RoomXY) -> bool {
+        self.is_free_at(pos) && !self.roads.contains_key(&pos)
     }
 
     pub fn num_placed_by(&self, ty: StructureType, step: ColonyStep) -> u32 {
@@ -252,7 +305,12 @@ impl ColonyPlanner {
     }
 
     fn update_tile_pathing(&mut self, xy: RoomXY, ty: TilePathing) {
-        self.cost_matrix.set_xy(xy, ty.cost());
+        self.cost_matrix.set(xy, ty.cost());
+    }
+
+    pub fn reserve(&mut self, xy: RoomXY) {
+        self.reserved.insert(xy);
+        self.update_tile_pathing(xy, TilePathing::Impassable);
     }
 
     pub fn plan_road(&mut self, xy: RoomXY, step: ColonyStep) {
@@ -291,6 +349,7 @@ impl ColonyPlanner {
         if self.pos2structure.get(&xy).is_some_and(|other| structure != *other) { return Err(anyhow!("Can't plan {structure:?} due to overlap")) }
         if self.structures.get(&xy).is_some_and(|old_step| step >= *old_step) { return Ok(()) }
 
+        self.reserved.remove(&xy);
         self.structures2pos.entry(structure).or_default().insert(xy);
         self.pos2structure.insert(xy, structure);
         self.structures.insert(xy, step);
@@ -303,9 +362,25 @@ impl ColonyPlanner {
         Ok(())
     }
 
-    pub fn find_path_between(&self, point1: RoomXY, point2: RoomXY, step: Option<ColonyStep>) -> Vec<Step> {
-        let step = step.unwrap_or(ColonyStep::last());
+    pub fn find_path_between(&self, point1: RoomXY, point2: RoomXY, step: Option<ColonyStep>) -> Option<Vec<RoomXY>> {
+        let cost_matrix = self.cost_matrix_at(step.unwrap_or(ColonyStep::last()));
+        let cost = |xy| Some(cost_matrix.get(xy)).filter(|cost| *cost < TilePathing::Impassable.cost()).map(u32::from);
+        shortest_path(point1, point2, 0, cost)
+    }
+
+    // Source containers end up closed in by the link and extensions around them, so distances are measured
+    // without the structures built after the step
+    pub fn distance_at(&self, point1: RoomXY, point2: RoomXY, step: ColonyStep) -> Option<u32> {
+        let mut cost_matrix = self.cost_matrix_at(step);
+        for pos in self.structures.iter().filter(|(_, structure_step)| **structure_step > step).map(|(pos, _)| pos) {
+            cost_matrix.set(*pos, TilePathing::from(self.terrain.get_xy(*pos)).cost());
+        }
+
+        let cost = |xy| Some(cost_matrix.get(xy)).filter(|cost| *cost < TilePathing::Impassable.cost()).map(u32::from);
+        Some(shortest_path(point1, point2, 0, cost)?.len() as u32)
+    }
 
+    fn cost_matrix_at(&self, step: ColonyStep) -> LocalCostMatrix {
         let mut cost_matrix = self.cost_matrix.clone();
 
         let built_roads = self.roads.iter()
@@ -314,31 +389,21 @@ impl ColonyPlanner {
             .map(|(pos, _)| pos);
 
         for pos in built_roads {
-            cost_matrix.set_xy(*pos, TilePathing::BuiltRoad.cost());
+            cost_matrix.set(*pos, TilePathing::BuiltRoad.cost());
         }
 
-        let options = FindPathOptions::<fn(_, CostMatrix) -> SingleRoomCostResult, SingleRoomCostResult>::default()
-            .cost_callback(|_, _| SingleRoomCostResult::CostMatrix(cost_matrix.clone()));
-
-        let point1 = Position::new(point1.x, point1.y, self.room.name());
-        let point2 = Position::new(point2.x, point2.y, self.room.name());
-
-        let path = point1.find_path_to(&point2, Some(options));
-
-        let Path::Vectorized(path) = path else { unreachable!() };
-        path
+        cost_matrix
     }
 
-    pub fn plan_road_between(&mut self, point1: RoomXY, point2: RoomXY, step: ColonyStep) {
-        let path = self.find_path_between(point1, point2, Some(step));
+    pub fn plan_road_between(&mut self, point1: RoomXY, point2: RoomXY, step: ColonyStep) -> anyhow::Result<()> {
+        let path = self.find_path_between(point1, point2, Some(step)).ok_or(anyhow!("No path for a road between {point1} and {point2}"))?;
 
-        let mut pos = Position::new(point1.x, point1.y, self.room.name());
-        for path_step in &path {
-            pos.offset(path_step.dx, path_step.dy);
-
-            if self.pos2structure.get(&pos.xy()).is_none_or(PlannedStructure::walkable) && self.terrain.get(pos.x().u8(), pos.y().u8()) != Terrain::Wall {
-                self.plan_road(pos.xy(), step);
+        for pos in path {
+            if self.pos2structure.get(&pos).is_none_or(PlannedStructure::walkable) && self.terrain.get_xy(pos) != Terrain::Wall {
+                self.plan_road(pos, step);
             }
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,47 @@
+use itertools::Itertools;
+use log::info;
+use screeps::{RoomXY, Terrain};
+
+use crate::{colony::{plan::ColonyPlan, planner::state::ColonyPlanner, steps::ColonyStep}, movement::traffic::RoomTraffic};
+
+const PLAIN_ROAD_TRAFFIC: u32 = 200;
+const SWAMP_ROAD_TRAFFIC: u32 = 100;
+const MIN_JUDGED_TRAFFIC: u32 = 5000;
+const TRAFFIC_ROAD_STEP: ColonyStep = ColonyStep::BuildLvl3;
+
+pub fn plan_traffic_roads(planner: &mut ColonyPlanner) {
+    let Some(traffic) = planner.room.traffic.clone() else { return };
+
+    for (xy, count) in traffic.iter().sorted() {
+        let threshold = match planner.terrain.get_xy(xy) {
+            Terrain::Plain => PLAIN_ROAD_TRAFFIC,
+            Terrain::Swamp => SWAMP_ROAD_TRAFFIC,
+            Terrain::Wall => continue
+        };
+
+        if count < threshold || is_edge(xy) || planner.reserved.contains(&xy) { continue; }
+        if planner.pos2structure.get(&xy).is_some_and(|structure| !structure.walkable()) { continue; }
+
+        planner.plan_road(xy, TRAFFIC_ROAD_STEP);
+    }
+
+    let unused = unused_roads(planner.roads.keys().copied(), &traffic);
+    if !unused.is_empty() {
+        info!("{} planned roads in {} see no traffic", unused.len(), planner.room.name);
+    }
+}
+
+impl ColonyPlan {
+    pub fn unused_roads(&self, traffic: &RoomTraffic) -> Vec<RoomXY> {
+        unused_roads(self.steps.values().flat_map(|step| step.new_roads.iter().copied()), traffic)
+    }
+}
+
+fn unused_roads(roads: impl Iterator<Item = RoomXY>, traffic: &RoomTraffic) -> Vec<RoomXY> {
+    if traffic.total() < MIN_JUDGED_TRAFFIC { return Vec::new() }
+    roads.filter(|road| traffic.get(*road) == 0).sorted().collect()
+}
+
+fn is_edge(xy: RoomXY) -> bool {
+    [xy.x.u8(), xy.y.u8()].iter().any(|coord| *coord == 0 || *coord == 49)
+}
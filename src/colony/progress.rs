@@ -0,0 +1,107 @@
+use std::collections::{BTreeSet, HashSet};
+
+use itertools::Itertools;
+use log::warn;
+use screeps::{Room, find, game};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::colony::{ColonyView, steps::ColonyStep};
+
+// Goals are only checked again once something was built or destroyed, with the odd recheck for failed construction
+const RECHECK_INTERVAL: u32 = 100;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(from = "SerializedProgress")]
+pub struct ColonyProgress {
+    completed: BTreeSet<ColonyStep>,
+    checked: Option<RoomSnapshot>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct RoomSnapshot {
+    controller_level: u8,
+    structures: usize,
+    sites: usize,
+    time: u32
+}
+
+impl RoomSnapshot {
+    fn of_room(room: &Room, controller_level: u8) -> Self {
+        Self {
+            controller_level,
+            structures: room.find(find::STRUCTURES, None).len(),
+            sites: room.find(find::MY_CONSTRUCTION_SITES, None).len(),
+            time: game::time()
+        }
+    }
+
+    fn is_unchanged_since(&self, earlier: &Self) -> bool {
+        self.controller_level == earlier.controller_level
+            && self.structures == earlier.structures
+            && self.sites == earlier.sites
+            && self.time < earlier.time + RECHECK_INTERVAL
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedProgress {
+    Goals { completed: BTreeSet<ColonyStep>, #[serde(default)] checked: Option<RoomSnapshot> },
+    Step(ColonyStep)
+}
+
+impl From<SerializedProgress> for ColonyProgress {
+    fn from(progress: SerializedProgress) -> Self {
+        match progress {
+            SerializedProgress::Goals { completed, checked } => Self { completed, checked },
+            SerializedProgress::Step(step) => Self { completed: ColonyStep::iter().take_while(|goal| *goal < step).collect(), checked: None }
+        }
+    }
+}
+
+impl ColonyProgress {
+    pub fn is_complete(&self, goal: ColonyStep) -> bool {
+        self.completed.contains(&goal)
+    }
+
+    pub fn has_reached(&self, goal: ColonyStep) -> bool {
+        goal.prerequisites().iter().all(|prerequisite| self.is_complete(*prerequisite))
+    }
+
+    pub fn active_goals(&self, controller_level: u8) -> impl Iterator<Item = ColonyStep> {
+        ColonyStep::iter()
+            .filter(move |goal| goal.controller_level() <= controller_level)
+            .filter(|goal| !self.is_complete(*goal) && self.has_reached(*goal))
+    }
+
+    pub fn update(mut self, room: &Room, colony_data: &ColonyView<'_>) -> Self {
+        let controller_level = colony_data.controller.level();
+        self.completed.retain(|goal| goal.controller_level() <= controller_level && !(goal.is_upgrade() && goal.controller_level() == controller_level));
+
+        let snapshot = RoomSnapshot::of_room(room, controller_level);
+        if self.checked.is_some_and(|checked| snapshot.is_unchanged_since(&checked)) { return self }
+        self.checked = Some(snapshot);
+
+        let mut attempted = HashSet::new();
+        loop {
+            let goals = self.active_goals(controller_level).filter(|goal| attempted.insert(*goal)).collect_vec();
+            if goals.is_empty() { return self }
+
+            for goal in goals {
+                match is_achieved(goal, room, colony_data) {
+                    Ok(true) => { self.completed.insert(goal); },
+                    Ok(false) => (),
+                    Err(err) => warn!("Unable to progress {goal} in {}: {err}", colony_data.name)
+                }
+            }
+        }
+    }
+}
+
+fn is_achieved(goal: ColonyStep, room: &Room, colony_data: &ColonyView<'_>) -> anyhow::Result<bool> {
+    if goal == ColonyStep::EndlesslyUpgrade { return Ok(false) }
+    if goal.is_upgrade() { return Ok(colony_data.controller.level() > goal.controller_level()) }
+
+    colony_data.plan.steps.get(&goal).map_or(Ok(true), |step| step.build(room, colony_data.migration))
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use log::{info, warn};
+use screeps::{CONTAINER_DECAY, CONTAINER_DECAY_TIME, CREEP_CLAIM_LIFE_TIME, CREEP_LIFE_TIME, ENERGY_REGEN_TIME, HasId, HasPosition, ObjectId, OwnedStructureProperties, Part, Position, REPAIR_COST, ROAD_DECAY_AMOUNT, ROAD_DECAY_TIME, Room, RoomName, RoomXY, SOURCE_ENERGY_CAPACITY, Source, StructureType, find, game, look};
+use serde::{Deserialize, Serialize};
+use serde_json_any_key::any_key_map;
+
+use crate::{colony::{plan::{ColonyPlan, SourcePlan, SourcesPlan, refs::{OptionalPlannedStructureRef, PlannedStructureRef, PlannedStructureRefs}}, steps::ColonyStep}, pathfinding};
+
+pub const REMOTE_MINING_STEP: ColonyStep = ColonyStep::UpgradeToLevel5;
+const REMOTE_BUILD_INTERVAL: u32 = 50;
+
+const MAX_REMOTES: usize = 2;
+const MAX_REMOTE_SOURCE_DISTANCE: usize = 120;
+const MAX_ROAD_SITES_PER_ROOM: usize = 5;
+
+// Rooms which are reserved, owned or not worth mining are scouted again once a reservation could have run out
+const RESCOUT_INTERVAL: u32 = CREEP_CLAIM_LIFE_TIME;
+
+// Hauling a source over distance x needs 0.4x carry parts, and each pair costs 150 energy per creep lifetime
+const TRUCK_CARRY_PER_DIST: f32 = 0.4;
+const TRUCK_COST_PER_CARRY: f32 = 75.0 / CREEP_LIFE_TIME as f32;
+const RESERVER_COST: f32 = 2.0 * (Part::Claim.cost() + Part::Move.cost()) as f32 / CREEP_CLAIM_LIFE_TIME as f32;
+const CONTAINER_UPKEEP: f32 = CONTAINER_DECAY as f32 * REPAIR_COST / CONTAINER_DECAY_TIME as f32;
+const ROAD_UPKEEP: f32 = ROAD_DECAY_AMOUNT as f32 * REPAIR_COST / ROAD_DECAY_TIME as f32;
+const EXCAVATOR_COST: f32 = (5 * Part::Work.cost() + Part::Carry.cost() + 3 * Part::Move.cost()) as f32 / CREEP_LIFE_TIME as f32;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(from = "SerializedRemotes")]
+pub struct Remotes {
+    #[serde(with = "any_key_map")]
+    plans: HashMap<RoomName, RemotePlan>,
+    #[serde(with = "any_key_map")]
+    rescout_at: HashMap<RoomName, u32>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedRemotes {
+    Scouted {
+        #[serde(with = "any_key_map")]
+        plans: HashMap<RoomName, RemotePlan>,
+        #[serde(with = "any_key_map")]
+        rescout_at: HashMap<RoomName, u32>
+    },
+    Unscheduled(#[serde(with = "any_key_map")] HashMap<RoomName, Option<RemotePlan>>)
+}
+
+impl From<SerializedRemotes> for Remotes {
+    fn from(remotes: SerializedRemotes) -> Self {
+        match remotes {
+            SerializedRemotes::Scouted { plans, rescout_at } => Self { plans, rescout_at },
+            SerializedRemotes::Unscheduled(remotes) => Self {
+                rescout_at: remotes.iter().filter(|(_, plan)| plan.is_none()).map(|(room, _)| (*room, 0)).collect(),
+                plans: remotes.into_iter().filter_map(|(room, plan)| Some((room, plan?))).collect()
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RemotePlan {
+    pub room: RoomName,
+    pub controller: Position,
+    #[serde(with = "any_key_map")]
+    pub sources: SourcesPlan,
+    pub roads: Vec<Position>,
+    pub profit: f32
+}
+
+impl Remotes {
+    pub fn update(&mut self, home: &Room, home_plan: &ColonyPlan) {
+        let username = home.controller().and_then(|controller| controller.owner()).map(|owner| owner.username());
+
+        for neighbour in game::map::describe_exits(home.name()).values() {
+            let Some(room) = game::rooms().get(neighbour) else { continue; };
+
+            if !is_available(&room, username.as_deref()) {
+                if self.plans.remove(&neighbour).is_some() {
+                    warn!("Remote {neighbour} of {} is no longer available", home.name());
+                }
+
+                self.rescout_at.insert(neighbour, game::time() + RESCOUT_INTERVAL);
+                continue;
+            }
+
+            if !self.is_due(neighbour) { continue; }
+
+            let Some(plan) = RemotePlan::of_room(&room, home_plan, home.name()).filter(|plan| plan.profit > 0.0) else {
+                info!("Scouted {neighbour} for {}, but it isn't worth mining", home.name());
+                self.rescout_at.insert(neighbour, game::time() + RESCOUT_INTERVAL);
+                continue;
+            };
+
+            info!("Scouted remote {neighbour} for {} with an estimated profit of {:.2} energy per tick", home.name(), plan.profit);
+            self.rescout_at.remove(&neighbour);
+            self.plans.insert(neighbour, plan);
+        }
+
+        if game::time().is_multiple_of(REMOTE_BUILD_INTERVAL) {
+            self.active().for_each(RemotePlan::build);
+        }
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = &RemotePlan> {
+        self.plans.values()
+            .sorted_by(|a, b| b.profit.total_cmp(&a.profit))
+            .take(MAX_REMOTES)
+    }
+
+    fn is_due(&self, room: RoomName) -> bool {
+        !self.plans.contains_key(&room) && self.rescout_at.get(&room).is_none_or(|tick| game::time() >= *tick)
+    }
+
+    pub fn unscouted(&self, home: RoomName) -> Vec<RoomName> {
+        game::map::describe_exits(home).values()
+            .filter(|neighbour| self.is_due(*neighbour))
+            .collect()
+    }
+
+    // Remote roads run through the home room, where the colony plan doesn't know about them
+    pub fn home_roads(&self, home: RoomName) -> HashSet<RoomXY> {
+        self.plans.values()
+            .flat_map(|remote| remote.roads.iter())
+            .filter(|pos| pos.room_name() == home)
+            .map(|pos| pos.xy())
+            .collect()
+    }
+
+    pub fn source_plan(&self, source: ObjectId<Source>) -> Option<&SourcePlan> {
+        self.active().find_map(|remote| remote.sources.get(&source))
+    }
+}
+
+fn is_available(room: &Room, username: Option<&str>) -> bool {
+    let Some(controller) = room.controller() else { return false };
+    if controller.owner().is_some() { return false }
+    if controller.reservation().is_some_and(|reservation| Some(reservation.username().as_str()) != username) { return false }
+
+    room.find(find::HOSTILE_STRUCTURES, None).is_empty()
+}
+
+impl RemotePlan {
+    fn of_room(room: &Room, home_plan: &ColonyPlan, home: RoomName) -> Option<Self> {
+        let center = home_plan.center.pos;
+        let home_structures = home_plan.all_structures();
+
+        let mut sources = HashMap::new();
+        let mut roads = HashSet::new();
+
+        for source in room.find(find::SOURCES, None) {
+            let path = pathfinding::search(center, source.pos(), 1);
+            if path.incomplete() { return None }
+
+            let path = path.path();
+            if path.len() > MAX_REMOTE_SOURCE_DISTANCE { return None }
+            let container = *path.last()?;
+
+            // The part of the road inside the home room is built with the remote as well, except where the colony builds
+            roads.extend(path.iter()
+                .filter(|pos| **pos != container)
+                .filter(|pos| pos.room_name() != home || home_structures.get(&pos.xy()).is_none_or(|ty| matches!(ty, StructureType::Container | StructureType::Rampart)))
+                .filter(|pos| !matches!(pos.x().u8(), 0 | 49) && !matches!(pos.y().u8(), 0 | 49))
+                .copied());
+
+            sources.insert(source.id(), SourcePlan {
+                spawn: OptionalPlannedStructureRef::default(),
+                container: PlannedStructureRef::new(container).into(),
+                link: OptionalPlannedStructureRef::default(),
+                extensions: PlannedStructureRefs::default(),
+                distance: path.len() as u32
+            });
+        }
+
+        if sources.is_empty() { return None }
+
+        let mut plan = RemotePlan {
+            room: room.name(),
+            controller: room.controller()?.pos(),
+            sources,
+            roads: roads.into_iter().sorted().collect(),
+            profit: 0.0
+        };
+
+        plan.profit = plan.estimate_profit();
+        Some(plan)
+    }
+
+    // Energy per tick left over after paying for excavators, trucks, the reserver and upkeep
+    fn estimate_profit(&self) -> f32 {
+        let source_income = SOURCE_ENERGY_CAPACITY as f32 / ENERGY_REGEN_TIME as f32;
+
+        let sources_profit = self.sources.values()
+            .map(|source| {
+                let truck_cost = source.distance as f32 * TRUCK_CARRY_PER_DIST * TRUCK_COST_PER_CARRY;
+                source_income - EXCAVATOR_COST - truck_cost - CONTAINER_UPKEEP
+            }).sum::<f32>();
+
+        sources_profit - RESERVER_COST - self.roads.len() as f32 * ROAD_UPKEEP
+    }
+
+    fn build(&self) {
+        if game::rooms().get(self.room).is_none() { return }
+
+        let mut containers_complete = true;
+        for source in self.sources.values() {
+            let Some(container) = source.container.as_ref() else { continue; };
+            if container.is_complete() { continue; }
+
+            containers_complete = false;
+            if !container.is_being_built() {
+                container.pos.create_construction_site(StructureType::Container, None).ok();
+            }
+        }
+
+        if !containers_complete { return }
+
+        let missing_roads = self.roads.iter()
+            .filter(|pos| {
+                pos.look_for(look::STRUCTURES).is_ok_and(|structures| structures.iter().all(|structure| structure.structure_type() != StructureType::Road))
+                    && pos.look_for(look::CONSTRUCTION_SITES).is_ok_and(|sites| sites.is_empty())
+            });
+
+        for pos in missing_roads.take(MAX_ROAD_SITES_PER_ROOM) {
+            pos.create_construction_site(StructureType::Road, None).ok();
+        }
+    }
+}
@@ -1,34 +1,11 @@
 use std::{fmt::Debug, mem};
 
-use anyhow::Ok;
+use clap::ValueEnum;
 use enum_display::EnumDisplay;
-use screeps::Room;
 use serde::{Deserialize, Serialize};
 use strum::{EnumIter, FromRepr, IntoEnumIterator};
 
-use crate::{colony::ColonyView, statemachine::Transition};
-
-impl ColonyStep {
-    pub fn update(self, room: &Room, colony_data: &ColonyView<'_>) -> anyhow::Result<Transition<Self>> {
-        use Transition::*;
-
-        let controller_level = colony_data.controller.level();
-        if self.controller_level() > controller_level { return Ok(Next(Self::first_at_level(controller_level))) }
-
-        let controller_is_upgraded = controller_level > self.controller_level();
-        let built_step = colony_data.plan.steps.get(&self).map_or(Ok(true), |step| step.build(room))?;
-
-        let Some(promotion) = self.promotion() else { return Ok(Done(self)) };
-        let promotion_is_upgrade = promotion.controller_level() > self.controller_level();
-        if built_step && (!promotion_is_upgrade || controller_is_upgraded) {
-            Ok(Next(promotion))
-        } else {
-            Ok(Done(self))
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default, Clone, Debug, Hash, Copy, EnumIter, EnumDisplay, FromRepr)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default, Clone, Debug, Hash, Copy, EnumIter, EnumDisplay, FromRepr, ValueEnum)]
 #[repr(u8)]
 pub enum ColonyStep {
     #[default]
@@ -76,6 +53,35 @@ impl ColonyStep {
         }
     }
 
+    pub fn is_upgrade(self) -> bool {
+        matches!(self, Self::UpgradeToLevel2 | Self::UpgradeToLevel3 | Self::UpgradeToLevel4 | Self::UpgradeToLevel5 | Self::UpgradeToLevel6 | Self::UpgradeToLevel7 | Self::UpgradeToLevel8)
+    }
+
+    pub fn prerequisites(self) -> &'static [Self] {
+        use ColonyStep::*;
+
+        match self {
+            BuildSpawn => &[],
+            BuildBufferAndSourceContainers
+            | BuildArterialRoads => &[BuildSpawn],
+            UpgradeToLevel2 => &[BuildBufferAndSourceContainers, BuildArterialRoads],
+            BuildLvl2
+            | BuildLvl3
+            | BuildLvl4
+            | BuildLvl5
+            | BuildLvl6
+            | BuildLvl7
+            | BuildLvl8 => &[BuildBufferAndSourceContainers],
+            UpgradeToLevel3 => &[BuildLvl2],
+            UpgradeToLevel4 => &[BuildLvl3],
+            UpgradeToLevel5 => &[BuildLvl4],
+            UpgradeToLevel6 => &[BuildLvl5],
+            UpgradeToLevel7 => &[BuildLvl6],
+            UpgradeToLevel8 => &[BuildLvl7],
+            EndlesslyUpgrade => &[BuildLvl8]
+        }
+    }
+
     pub fn first_at_level(level: u8) -> Self {
         assert!(level <= 8);
 
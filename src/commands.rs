@@ -3,10 +3,11 @@ use std::{cell::RefCell, collections::HashSet, iter, mem};
 use anyhow::anyhow;
 use clap::Parser;
 use log::info;
-use screeps::{RoomName, StructureProperties, find, game};
+use ordered_float::OrderedFloat;
+use screeps::{ResourceType, RoomName, StructureProperties, find, game};
 use wasm_bindgen::prelude::wasm_bindgen;
 
-use crate::{colony::plan::ColonyPlan, visuals};
+use crate::{colony::{DEFAULT_PERIMETER_STEP, PlannerStrategy, RoomDescription, plan::ColonyPlan, steps::ColonyStep}, spawn::queue::ManualRole, visuals};
 
 thread_local! {
     static COMMANDS: RefCell<HashSet<Command>> = RefCell::new(HashSet::new());
@@ -25,9 +26,28 @@ fn do_command(command: &str) -> anyhow::Result<()> {
 
     match command {
         Command::ClearVisuals => visuals::clear_visuals(),
-        Command::VisualizeNewPlan { room } => {
+        Command::VisualizeNewPlan { room, strategy } => {
             let room = RoomName::new(&room).unwrap();
-            ColonyPlan::create_for(&game::rooms().get(room).unwrap()).unwrap().draw_progression(room);
+            ColonyPlan::create_for(&game::rooms().get(room).unwrap(), strategy.unwrap_or_default(), DEFAULT_PERIMETER_STEP).unwrap().draw_progression(room);
+        },
+        Command::ScorePlan { room, strategy } => {
+            let room = game::rooms().get(RoomName::new(&room)?).ok_or(anyhow!("Room {room} is not visible"))?;
+            let candidates = ColonyPlan::plan_candidates(&RoomDescription::of_room(&room)?, strategy, DEFAULT_PERIMETER_STEP);
+            let best = candidates.iter().map(|candidate| OrderedFloat(candidate.score.total())).filter(|total| total.is_finite()).min();
+
+            for candidate in &candidates {
+                let marker = if Some(OrderedFloat(candidate.score.total())) == best { "*" } else { " " };
+                let reason = candidate.plan.as_ref().err().map(|err| format!(": {err}")).unwrap_or_default();
+                info!("{marker} {:?} {}: {}{reason}", candidate.strategy, candidate.center, candidate.score);
+            }
+        },
+        Command::DescribeRoom { room } => {
+            let room = game::rooms().get(RoomName::new(&room)?).ok_or(anyhow!("Room {room} is not visible"))?;
+            info!("{}", serde_json::to_string(&RoomDescription::of_room(&room)?)?);
+        },
+        Command::VisualizeDescribedPlan { description, strategy } => {
+            let room = RoomDescription::from_json(&description)?;
+            ColonyPlan::create_from(&room, strategy.unwrap_or_default(), DEFAULT_PERIMETER_STEP)?.draw_progression(room.name);
         },
         Command::CleanRoomStructures { room } => {
             game::rooms().get(RoomName::new(&room).unwrap())
@@ -70,14 +90,30 @@ pub fn handle_commands<F>(f: F) where F : FnMut(&Command) -> bool {
 #[derive(Parser, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum Command {
     ClearVisuals,
-    VisualizeNewPlan { room: String },
+    VisualizeNewPlan { room: String, #[clap(long, short)] strategy: Option<PlannerStrategy> },
+    ScorePlan { room: String, #[clap(long, short)] strategy: Option<PlannerStrategy> },
+    DescribeRoom { room: String },
+    VisualizeDescribedPlan { description: String, #[clap(long, short)] strategy: Option<PlannerStrategy> },
+    SetPlannerStrategy { room: String, strategy: Option<PlannerStrategy> },
+    SetPerimeterStep { room: String, step: Option<ColonyStep> },
     VisualizePlan { room: String, #[clap(long, short)] animate: bool },
+    ExportPlan { room: String },
+    Replan { room: String },
+    VisualizeTraffic { room: String },
+    ImportPlan { room: String, data: String },
     CleanRoomStructures { room: String },
     CleanRoomSites { room: String },
     ResetColonyStep { room: String },
     ResetColony { room: String },
     MigrateColony { room: String },
     DebugSpawn,
+    ListSpawnQueue { room: String },
+    BumpSpawnRequest { room: String, id: u32 },
+    RequestSpawn { room: String, role: ManualRole, body: String, #[clap(long, short)] priority: Option<i32> },
+    VisualizeSpawnQueue { room: String },
+    SetStockpile { room: String, resource: ResourceType, amount: u32 },
+    ListStockpile { room: String },
+    PrintLedger { #[clap(long, short)] count: Option<usize> },
     VisualizeMovement { creep: String },
     Claim { room: String },
     ResetMemory
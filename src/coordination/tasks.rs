@@ -38,7 +38,6 @@ impl<Task: Hash + Eq + Clone, TaskData: UpdateableTaskData> Tasks<Task, TaskData
 }
 
 impl<Task: Hash + Eq, TaskData> Tasks<Task, TaskData> {
-    #[expect(unused)]
     pub fn get(&self, task: &Task) -> Option<&TaskData> {
         self.tasks.get(task)
     }
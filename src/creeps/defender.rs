@@ -0,0 +1,64 @@
+use enum_display::EnumDisplay;
+use screeps::{HasPosition, Position, RoomName, find};
+use serde::{Deserialize, Serialize};
+
+use crate::{creeps::virtual_creep::VirtualCreep, defer, done, movement::requests::MovementRequests, next, statemachine::Transition};
+
+const RANGED_ATTACK_RANGE: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DefenderKind {
+    Melee,
+    Ranged
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone, EnumDisplay)]
+pub enum DefenderCreep {
+    #[default]
+    Going,
+    Fighting
+}
+
+// Ranged defenders carry heal parts to outlast their targets
+fn heal_if_damaged(creep: &mut VirtualCreep, kind: DefenderKind) -> anyhow::Result<()> {
+    if kind == DefenderKind::Ranged && creep.is_damaged() { creep.heal_self()?; }
+    Ok(())
+}
+
+impl DefenderCreep {
+    pub fn update(self, creep: &mut VirtualCreep, kind: DefenderKind, room: RoomName, movement: &mut MovementRequests) -> anyhow::Result<Transition<Self>> {
+        use DefenderCreep::*;
+        use Transition::*;
+
+        match self {
+            Going => {
+                if creep.pos().room_name() == room { next!(Fighting) }
+                heal_if_damaged(creep, kind)?;
+
+                defer!(movement.move_vcreep_to(creep, Position::new(25.try_into().unwrap(), 25.try_into().unwrap(), room), 20), self)?;
+                Ok(Done(self))
+            },
+            Fighting => {
+                if creep.pos().room_name() != room { next!(Going) }
+                heal_if_damaged(creep, kind)?;
+
+                let Some(target) = creep.pos().find_closest_by_range(find::HOSTILE_CREEPS) else { done!(self) };
+
+                match kind {
+                    DefenderKind::Melee => {
+                        let in_range = creep.pos().is_near_to(target.pos());
+                        movement.move_vcreep_to(creep, target.pos(), 1)?;
+                        if in_range { creep.attack(target)?; }
+                    },
+                    DefenderKind::Ranged => {
+                        let in_range = creep.pos().get_range_to(target.pos()) <= RANGED_ATTACK_RANGE;
+                        movement.move_vcreep_to(creep, target.pos(), RANGED_ATTACK_RANGE)?;
+                        if in_range { creep.ranged_attack(target)?; }
+                    }
+                }
+
+                Ok(Done(self))
+            }
+        }
+    }
+}
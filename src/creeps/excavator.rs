@@ -1,7 +1,7 @@
 use anyhow::{anyhow};
 use enum_display::EnumDisplay;
 use log::warn;
-use screeps::{ConstructionSite, HasId, Part, ResourceType, Source, StructureContainer, StructureExtension, StructureLink, StructureSpawn};
+use screeps::{ConstructionSite, ObjectId, Part, ResourceType, Source, StructureContainer, StructureExtension, StructureLink, StructureSpawn};
 use serde::{Deserialize, Serialize};
 
 use crate::{colony::{ColonyView, plan::{SourcePlan, refs::PlannedStructureRef}}, creeps::virtual_creep::{IntentError, IntentType, VirtualCreep}, defer, domain_traits::EnergyStoreAccessors, movement::requests::MovementRequests, statemachine::Transition};
@@ -68,21 +68,26 @@ impl EnergyDestination {
 }
 
 impl ExcavatorCreep {
-    pub fn update(self, creep: &mut VirtualCreep, source: &Source, home: &ColonyView<'_>, movement: &mut MovementRequests) -> anyhow::Result<Transition<Self>> {
+    pub fn update(self, creep: &mut VirtualCreep, source: ObjectId<Source>, home: &ColonyView<'_>, movement: &mut MovementRequests) -> anyhow::Result<Transition<Self>> {
         use ExcavatorCreep::*;
         use Transition::*;
 
-        let plan = home.plan.sources.get(&source.id()).ok_or(anyhow!("Plan doesn't exist"))?;
+        let plan = home.source_plan(source).ok_or(anyhow!("Plan doesn't exist"))?;
 
         match self {
             Going => {
                 let harvest_pos = plan.container.as_ref().ok_or(anyhow!("No container"))?.pos;
-                defer!(movement.move_vtugged_to(creep, harvest_pos, 0), self)?;
+                if creep.body().part_count(Part::Move) > 0 {
+                    defer!(movement.move_vcreep_to(creep, harvest_pos, 0), self)?;
+                } else {
+                    defer!(movement.move_vtugged_to(creep, harvest_pos, 0), self)?;
+                }
 
                 Ok(Next(Mining))
             },
             Mining => {
-                creep.harvest_source(source.clone())?;
+                let source = source.resolve().ok_or(anyhow!("Source isn't visible"))?;
+                creep.harvest_source(source)?;
 
                 let harvest_energy = (creep.body().part_count(Part::Work) * 2) as u32;
                 let target_energy = creep.capacity() - harvest_energy;
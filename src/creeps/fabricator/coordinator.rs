@@ -1,5 +1,7 @@
+use std::iter;
+
 use ordered_float::OrderedFloat;
-use screeps::{BUILD_POWER, CONTROLLER_MAX_UPGRADE_PER_TICK, HasPosition, Part, REPAIR_POWER, Room, StructureController, UPGRADE_CONTROLLER_POWER, controller_downgrade, find};
+use screeps::{BUILD_POWER, CONTROLLER_MAX_UPGRADE_PER_TICK, HasPosition, Part, REPAIR_POWER, Room, StructureController, StructureType, UPGRADE_CONTROLLER_POWER, controller_downgrade, find};
 use serde::{Serialize, Deserialize};
 
 use crate::{check::{Expiration, Filtered, deserialize_filter_check}, colony::{ColonyBuffer, ColonyView}, coordination::{allocations::{CreepAllocationHandle, CreepAllocations, ResourceAmount}, tasks::{AddedToCollab, Tasks}}, creeps::{fabricator::{TaskExpiration, task::{BuildTask, FabricatorTask, RepairTask, StructureTask}}, virtual_creep::VirtualCreep}, domain_traits::{EnergyStoreAccessors, HasHits, ObjectId, ResolvableId}, structure::RepairableStructure};
@@ -54,19 +56,25 @@ fn storage_fill_percentage(buffer: Option<&ColonyBuffer>) -> Option<f32> {
 }
 
 impl FabricatorCoordinator {
-    pub fn update(&mut self, room: &Room) {
+    pub fn update(&mut self, room: &Room, remote_rooms: &[Room]) {
+        let remote_structures = remote_rooms.iter()
+            .flat_map(|room| room.find(find::STRUCTURES, None))
+            .filter(|structure| matches!(structure.structure_type(), StructureType::Road | StructureType::Container));
+
         self.repairs.set_tasks(
             room.find(find::STRUCTURES, None).into_iter()
+                .chain(remote_structures)
                 .filter_map(|structure| {
                     let repairable = RepairableStructure::try_from(structure).ok()?;
-                    let damage = repairable.hits_max() - repairable.hits();
+                    let damage = repairable.hits_max().saturating_sub(repairable.hits());
 
                     Some((repairable, ResourceAmount(damage)))
                 })
         );
 
         self.builds.set_tasks(
-            room.find(find::MY_CONSTRUCTION_SITES, None).into_iter()
+            iter::once(room).chain(remote_rooms)
+                .flat_map(|room| room.find(find::MY_CONSTRUCTION_SITES, None))
                 .filter_map(|site| Some((
                     ObjectId::try_new(&site)?,
                     ResourceAmount(site.progress_total() - site.progress())
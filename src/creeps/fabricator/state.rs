@@ -1,10 +1,10 @@
 use anyhow::Result;
 use derive_where::derive_where;
 use enum_display::EnumDisplay;
-use screeps::{HasPosition, ResourceType};
+use screeps::{HasPosition, Position, ResourceType, look};
 use serde::Deserialize;
 
-use crate::{check::Check, colony::ColonyView, creeps::{fabricator::{coordinator::FabricatorCoordinator, task::FabricatorTask}, virtual_creep::VirtualCreep}, defer, defer_err, domain_traits::EnergyStoreAccessors, done, done_if, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition};
+use crate::{check::Check, colony::ColonyView, creeps::{fabricator::{coordinator::FabricatorCoordinator, task::FabricatorTask}, virtual_creep::VirtualCreep}, defer, defer_err, domain_traits::{EnergyStoreAccessors, Withdrawable}, done, done_if, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition};
 
 #[derive(Debug, Default, EnumDisplay)]
 #[derive_where(Serialize, Deserialize, Clone; FabricatorTask<S>, S)]
@@ -48,14 +48,19 @@ impl FabricatorCreep {
                 next_if!(coordinator.refresh(creep, task).is_none(), Self::Idle);
                 next_if!(creep.next_used_energy_capacity() > 0, Self::Performing(task.clone()));
 
-                let Some(buffer) = &home.buffer else { done!(self) };
-                defer!(movement.move_vcreep_to(creep, buffer.pos(), 1), self)?;
+                if matches!(task, FabricatorTask::Upgrading) {
+                    let controller = &home.plan.controller;
+                    if let Some(link) = controller.link.resolve().filter(|link| link.used_energy_capacity() > 0) {
+                        return self.collect_from(creep, movement, link)
+                    }
 
-                done_if!(buffer.used_energy_capacity() == 0, self);
-                done_if!(creep.outgoing() > 0, self);
-                defer_err!(creep.withdraw(*buffer, ResourceType::Energy, None), self)?;
+                    if let Some(container) = controller.container.resolve().filter(|container| container.used_energy_capacity() > 0) {
+                        return self.collect_from(creep, movement, container)
+                    }
+                }
 
-                Ok(Next(Self::Performing(task.clone())))
+                let Some(buffer) = home.buffer else { done!(self) };
+                self.collect_from(creep, movement, buffer)
             },
             Self::Performing(ref task) => {
                 next_if!(creep.next_used_energy_capacity() == 0, Self::CollectingFor(task.clone()));
@@ -77,7 +82,8 @@ impl FabricatorCreep {
                     FabricatorTask::Upgrading => {
                         next_if!(coordinator.upgrade.refresh(creep.handle()).is_none(), Self::Idle);
 
-                        defer!(movement.move_vcreep_to(creep, home.controller.pos(), 3), self)?;
+                        let (target, range) = upgrade_position(creep, home);
+                        defer!(movement.move_vcreep_to(creep, target, range), self)?;
 
                         done_if!(creep.curr_used_energy_capacity() == 0, self);
                         defer_err!(creep.upgrade_controller(home.controller.clone()), self)?;
@@ -88,4 +94,26 @@ impl FabricatorCreep {
             }
         }
     }
-}
\ No newline at end of file
+
+    fn collect_from(self, creep: &mut VirtualCreep, movement: &mut MovementRequests, target: impl Withdrawable + HasPosition + 'static) -> anyhow::Result<Transition<Self>> {
+        use Transition::*;
+
+        let Self::CollectingFor(task) = &self else { unreachable!() };
+        let task = task.clone();
+
+        defer!(movement.move_vcreep_to(creep, target.pos(), 1), self)?;
+
+        done_if!(target.used_energy_capacity() == 0, self);
+        done_if!(creep.outgoing() > 0, self);
+        defer_err!(creep.withdraw(target, ResourceType::Energy, None), self)?;
+
+        Ok(Next(Self::Performing(task)))
+    }
+}
+
+fn upgrade_position(creep: &VirtualCreep, home: &ColonyView<'_>) -> (Position, u32) {
+    home.plan.controller.standing_tiles.iter()
+        .filter(|tile| **tile == creep.pos() || tile.look_for(look::CREEPS).is_ok_and(|creeps| creeps.is_empty()))
+        .min_by_key(|tile| tile.get_range_to(creep.pos()))
+        .map_or((home.controller.pos(), 3), |tile| (*tile, 0))
+}
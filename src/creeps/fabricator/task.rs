@@ -40,7 +40,7 @@ impl CheckFrom for StructureTask {
                 Self::Building(id.check()?),
             StructureTask::Repairing(id) => {
                 let structure: RepairableStructure = id.check()?;
-                if structure.hits() == structure.hits_max() { bail!("Structure no longer needs repair") }
+                if structure.hits() >= structure.hits_max() { bail!("Structure no longer needs repair") }
 
                 Self::Repairing(structure)
             }
@@ -0,0 +1,53 @@
+use anyhow::anyhow;
+use enum_display::EnumDisplay;
+use screeps::{HARVEST_MINERAL_POWER, Mineral, Part, look};
+use serde::{Deserialize, Serialize};
+
+use crate::{colony::{ColonyView, plan::MineralPlan}, creeps::virtual_creep::VirtualCreep, defer, domain_traits::HasStoreExt, done_if, movement::requests::MovementRequests, statemachine::Transition};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, EnumDisplay, Default)]
+pub enum MineralExcavatorCreep {
+    #[default]
+    Going,
+    Mining
+}
+
+impl MineralPlan {
+    pub fn resolve_mineral(&self) -> Option<Mineral> {
+        let extractor = self.extractor.as_ref()?;
+        extractor.pos.look_for(look::MINERALS).ok()?.into_iter().next()
+    }
+}
+
+impl MineralExcavatorCreep {
+    pub fn update(self, creep: &mut VirtualCreep, home: &ColonyView<'_>, movement: &mut MovementRequests) -> anyhow::Result<Transition<Self>> {
+        use MineralExcavatorCreep::*;
+        use Transition::*;
+
+        let plan = &home.plan.mineral;
+
+        match self {
+            Going => {
+                let harvest_pos = plan.container.as_ref().ok_or(anyhow!("No mineral container"))?.pos;
+                defer!(movement.move_vtugged_to(creep, harvest_pos, 0), self)?;
+
+                Ok(Next(Mining))
+            },
+            Mining => {
+                let mineral = plan.resolve_mineral().ok_or(anyhow!("No mineral"))?;
+                done_if!(mineral.mineral_amount() == 0, self);
+
+                let extractor = plan.extractor.resolve().ok_or(anyhow!("No extractor"))?;
+                done_if!(extractor.cooldown() > 0, self);
+
+                // Harvested minerals overflow into the container we stand on
+                let container = plan.container.resolve().ok_or(anyhow!("No mineral container"))?;
+                let harvest_amount = creep.body().part_count(Part::Work) as u32 * HARVEST_MINERAL_POWER;
+                done_if!(container.free_capacity(None) < harvest_amount, self);
+
+                creep.harvest_mineral(mineral)?;
+                Ok(Done(self))
+            }
+        }
+    }
+}
@@ -1,23 +1,34 @@
 use std::fmt::Debug;
 
 use derive_where::derive_where;
-use log::{error, warn};
-use screeps::{Creep, RoomName, Source, StructureSpawn, find, game, look, prelude::*};
+use itertools::Itertools;
+use log::{error, info, warn};
+use screeps::{CREEP_CLAIM_LIFE_TIME, CREEP_LIFE_TIME, Creep, Part, ResourceType, RoomName, Source, StructureSpawn, find, game, look, prelude::*};
 use anyhow::Result;
 
-use crate::{check::{Check, CheckFrom}, colony::ColonyView, creeps::{excavator::ExcavatorCreep, fabricator::FabricatorCreep, flagship::FlagshipCreep, truck::{CreepStops, ImportTruckState, TruckCreep}, virtual_creep::VirtualCreep}, domain_traits::{CreepId, EnergyStoreAccessors, HasId, ObjectId, ResolvableId}, ids::{CheckState, Checked, Unchecked}, memory::Memory, movement::requests::{MovementRequests, TugboatRequests}, statemachine::step, utils::adjacent_positions};
+use crate::{check::{Check, CheckFrom, FilterCheck}, colony::ColonyView, creeps::{defender::{DefenderCreep, DefenderKind}, excavator::ExcavatorCreep, fabricator::FabricatorCreep, flagship::FlagshipCreep, mineral_excavator::MineralExcavatorCreep, reserver::ReserverCreep, truck::{CreepStops, ImportTruckState, TruckCreep}, virtual_creep::VirtualCreep}, domain_traits::{CreepId, EnergyStoreAccessors, HasId, ObjectId, ResolvableId}, ids::{CheckState, Checked, Unchecked}, labs::boosts::do_boosts, memory::Memory, movement::requests::{MovementRequests, TugboatRequests}, statemachine::step, utils::adjacent_positions};
 
+pub mod defender;
 pub mod flagship;
 pub mod excavator;
 pub mod fabricator;
+pub mod mineral_excavator;
+pub mod reserver;
 pub mod truck;
 pub mod virtual_creep;
 
 #[derive(Debug)]
-#[derive_where(Deserialize, Serialize, Clone; CreepRole<S>)]
+#[derive_where(Deserialize, Serialize, Clone; CreepRole<S>, CreepId<S>)]
 pub struct CreepData<S: CheckState = Checked> {
     pub role: CreepRole<S>,
-    pub home: RoomName
+    pub home: RoomName,
+    #[serde(default)]
+    pub travel_time: Option<u32>,
+    #[serde(default)]
+    pub predecessor: Option<CreepId<S>>,
+    // Compounds the creep still has to be boosted with before starting its role
+    #[serde(default)]
+    pub boosts: Vec<ResourceType>
 }
 
 impl CheckFrom for CreepData {
@@ -27,14 +38,17 @@ impl CheckFrom for CreepData {
     fn check_from(us: Self::Unchecked) -> Result<Self> {
         Ok(Self {
             role: us.role.check()?,
-            home: us.home
+            home: us.home,
+            travel_time: us.travel_time,
+            predecessor: us.predecessor.filter_check().0,
+            boosts: us.boosts
         })
     }
 }
 
 impl CreepData {
     pub fn new(home: RoomName, role: CreepRole) -> Self {
-        CreepData { role, home }
+        CreepData { role, home, travel_time: None, predecessor: None, boosts: Vec::new() }
     }
 
     pub fn try_recover_from(creep: &Creep, mem: &Memory) -> Option<Self> {
@@ -60,6 +74,18 @@ impl CreepData {
 
                 CreepRole::Excavator(ExcavatorCreep::default(), source.id()) 
             },
+            "RemoteExcavator" => adjacent_positions(creep.pos())
+                .flat_map(|pos| pos.look_for(look::SOURCES))
+                .flatten()
+                .next()
+                .map_or_else(
+                    || CreepRole::Scrap(get_recycle_spawn(creep, &home).id()),
+                    |source| CreepRole::RemoteExcavator(ExcavatorCreep::default(), source.id().screeps_id())
+                ),
+            "MineralExcavator" => CreepRole::MineralExcavator(MineralExcavatorCreep::default()),
+            "Reserver" => CreepRole::Reserver(ReserverCreep::default(), creep.pos().room_name()),
+            "MeleeDefender" => CreepRole::MeleeDefender(DefenderCreep::default(), creep.pos().room_name()),
+            "RangedDefender" => CreepRole::RangedDefender(DefenderCreep::default(), creep.pos().room_name()),
             _ => CreepRole::Scrap(get_recycle_spawn(creep, &home).id())
         };
         
@@ -71,10 +97,15 @@ impl CreepData {
 #[derive_where(Serialize, Deserialize, Clone; ObjectId<Source, S>, CreepId<S>, ObjectId<StructureSpawn, S>)]
 pub enum CreepRole<S: CheckState = Checked> {
     Excavator(ExcavatorCreep, ObjectId<Source, S>),
+    RemoteExcavator(ExcavatorCreep, screeps::ObjectId<Source>),
+    MineralExcavator(MineralExcavatorCreep),
+    Reserver(ReserverCreep, RoomName),
     Flagship(FlagshipCreep),
     Truck(TruckCreep),
     ImportTruck(ImportTruckState),
     Fabricator(FabricatorCreep),
+    MeleeDefender(DefenderCreep, RoomName),
+    RangedDefender(DefenderCreep, RoomName),
     Tugboat(CreepId<S>, ObjectId<StructureSpawn, S>),
     Scrap(ObjectId<StructureSpawn, S>),
 }
@@ -86,10 +117,15 @@ impl CheckFrom for CreepRole {
     fn check_from(us: Self::Unchecked) -> Result<Self> {
         Ok(match us {
             Self::Unchecked::Excavator(state, source) => Self::Excavator(state, source.check()?),
+            Self::Unchecked::RemoteExcavator(state, source) => Self::RemoteExcavator(state, source),
+            Self::Unchecked::MineralExcavator(state) => Self::MineralExcavator(state),
+            Self::Unchecked::Reserver(state, room) => Self::Reserver(state, room),
             Self::Unchecked::Flagship(state) => Self::Flagship(state),
             Self::Unchecked::Truck(state) => Self::Truck(state),
             Self::Unchecked::ImportTruck(state) => Self::ImportTruck(state),
             Self::Unchecked::Fabricator(state) => Self::Fabricator(state),
+            Self::Unchecked::MeleeDefender(state, room) => Self::MeleeDefender(state, room),
+            Self::Unchecked::RangedDefender(state, room) => Self::RangedDefender(state, room),
             Self::Unchecked::Tugboat(tugged, spawn) => Self::Tugboat(tugged.check()?, spawn.check()?),
             Self::Unchecked::Scrap(state) => Self::Scrap(state.check()?),
         })
@@ -101,11 +137,79 @@ impl CreepRole {
         match self {
             CreepRole::Flagship(_) => "Flagship",
             CreepRole::Excavator(_, _) => "Excavator",
+            CreepRole::RemoteExcavator(_, _) => "RemoteExcavator",
+            CreepRole::MineralExcavator(_) => "MineralExcavator",
+            CreepRole::Reserver(_, _) => "Reserver",
             CreepRole::Tugboat(_, _) => "Tugboat",
             CreepRole::Scrap(_) => "Scrap",
             CreepRole::Truck(_) => "Truck",
             CreepRole::ImportTruck(_) => "ImportTruck",
             CreepRole::Fabricator(_) => "Fabricator",
+            CreepRole::MeleeDefender(_, _) => "MeleeDefender",
+            CreepRole::RangedDefender(_, _) => "RangedDefender",
+        }
+    }
+
+    pub fn defended_room(&self) -> Option<RoomName> {
+        match self {
+            CreepRole::MeleeDefender(_, room) | CreepRole::RangedDefender(_, room) => Some(*room),
+            _ => None
+        }
+    }
+
+    // Whether the creep has reached the post it works from
+    pub fn is_posted(&self) -> bool {
+        matches!(self,
+            CreepRole::Excavator(ExcavatorCreep::Mining, _)
+            | CreepRole::RemoteExcavator(ExcavatorCreep::Mining, _)
+            | CreepRole::MineralExcavator(MineralExcavatorCreep::Mining)
+            | CreepRole::Reserver(ReserverCreep::Reserving, _))
+    }
+
+    pub fn is_successor_of(&self, other: &CreepRole) -> bool {
+        match (self, other) {
+            (CreepRole::Excavator(_, source), CreepRole::Excavator(_, source2)) => source == source2,
+            (CreepRole::RemoteExcavator(_, source), CreepRole::RemoteExcavator(_, source2)) => source == source2,
+            (CreepRole::MineralExcavator(_), CreepRole::MineralExcavator(_)) => true,
+            (CreepRole::Reserver(_, room), CreepRole::Reserver(_, room2)) => room == room2,
+            _ => false
+        }
+    }
+}
+
+fn creep_age(creep: &Creep) -> Option<u32> {
+    let lifetime = if creep.body().iter().any(|part| part.part() == Part::Claim) { CREEP_CLAIM_LIFE_TIME } else { CREEP_LIFE_TIME };
+    creep.ticks_to_live().map(|ttl| lifetime.saturating_sub(ttl))
+}
+
+// Sends predecessors off to be recycled once their successor has arrived at the post.
+// Excavators can't walk to a spawn without a tugboat, so they make room at the post right away
+fn do_handovers(mem: &mut Memory) {
+    let handovers = mem.creeps.iter()
+        .filter_map(|(successor, data)| {
+            let predecessor = data.predecessor.as_ref()?;
+            let predecessor_data = mem.creeps.get(predecessor)?;
+            let home = mem.colonies.view(predecessor_data.home)?;
+
+            let successor_creep = successor.resolve();
+            if successor_creep.spawning() { return None }
+
+            let predecessor_creep = predecessor.resolve();
+            let arrived = data.role.is_posted() || successor_creep.pos().get_range_to(predecessor_creep.pos()) <= 1;
+            let spawn = (predecessor_creep.get_active_bodyparts(Part::Move) > 0).then(|| get_recycle_spawn(&predecessor_creep, &home).id());
+            arrived.then(|| (successor.clone(), predecessor.clone(), spawn))
+        }).collect_vec();
+
+    for (successor, predecessor, spawn) in handovers {
+        let data = mem.creeps.get_mut(&successor).unwrap();
+        info!("{} took over as {} from {}", successor.resolve().name(), data.role.prefix(), predecessor.resolve().name());
+        data.predecessor = None;
+
+        match spawn {
+            Some(spawn) => if let Some(predecessor_data) = mem.creeps.get_mut(&predecessor) {
+                predecessor_data.role = CreepRole::Scrap(spawn);
+            },
+            None => { predecessor.resolve().suicide().ok(); }
         }
     }
 }
@@ -134,18 +238,37 @@ pub fn do_creeps(mem: &mut Memory) -> TugboatRequests {
             true
         }).collect();
 
+    do_handovers(mem);
+
     let mut movement = MovementRequests::new();
     for creep in &update_creeps {
         let creep_data = mem.creeps.get_mut(&creep.id()).unwrap();
         let Some(home) = mem.colonies.view(creep_data.home) else { continue; };
 
+        if !creep_data.boosts.is_empty() {
+            let labs = mem.lab_coordinators.entry(creep_data.home).or_default();
+            if !do_boosts(creep, &mut creep_data.boosts, &home, labs, &mut movement) { continue; }
+        }
+
+        // Defenders are recycled once their room is clear of hostiles
+        if let Some(room) = creep_data.role.defended_room() && !mem.threats.contains_key(&room) {
+            info!("{} is done defending {room}", creep.name());
+            creep_data.role = Scrap(get_recycle_spawn(creep, &home).id());
+        }
+
         let mut vcreep = VirtualCreep::new(creep.clone());
 
         match &mut creep_data.role {
             Flagship(state) => 
                 step(state, |state| state.update(&mut vcreep, &mut movement, &mut mem.flagship_coordinator)),
             Excavator(state, source) => 
-                step(state, |state| state.update(&mut vcreep, &source.resolve(), &home, &mut movement)),
+                step(state, |state| state.update(&mut vcreep, source.screeps_id(), &home, &mut movement)),
+            RemoteExcavator(state, source) => 
+                step(state, |state| state.update(&mut vcreep, *source, &home, &mut movement)),
+            MineralExcavator(state) => 
+                step(state, |state| state.update(&mut vcreep, &home, &mut movement)),
+            Reserver(state, room) => 
+                step(state, |state| state.update(&mut vcreep, *room, &home, &mut movement)),
             Truck(state) => {
                 let coordinator = mem.truck_coordinators.entry(creep_data.home).or_default();
                 step(state, |state| state.update(&mut vcreep, &home, &mut movement, coordinator));
@@ -159,10 +282,18 @@ pub fn do_creeps(mem: &mut Memory) -> TugboatRequests {
                 let coordinator = mem.fabricator_coordinators.entry(creep_data.home).or_default();
                 step(state, |state| state.update(&mut vcreep, &home, &mut movement, coordinator));
             },
+            MeleeDefender(state, room) =>
+                step(state, |state| state.update(&mut vcreep, DefenderKind::Melee, *room, &mut movement)),
+            RangedDefender(state, room) =>
+                step(state, |state| state.update(&mut vcreep, DefenderKind::Ranged, *room, &mut movement)),
             Tugboat(tugged, spawn) => movement.do_tugboat(creep, tugged.clone(), &spawn.resolve()),
             Scrap(spawn) => do_recycle(creep, &mut movement, &spawn.resolve()),
         }
 
+        if creep_data.travel_time.is_none() && creep_data.role.is_posted() {
+            creep_data.travel_time = creep_age(creep);
+        }
+
         if let Err(e) = vcreep.commit() {
             error!("Failed to comit intents for {}: {}", creep.name(), e);
         }
@@ -0,0 +1,47 @@
+use anyhow::anyhow;
+use enum_display::EnumDisplay;
+use screeps::{Part, Position, RoomName, game};
+use serde::{Deserialize, Serialize};
+
+use crate::{colony::ColonyView, creeps::virtual_creep::VirtualCreep, defer, defer_err, done, movement::requests::MovementRequests, next, next_if, statemachine::Transition};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone, EnumDisplay)]
+pub enum ReserverCreep {
+    #[default]
+    Going,
+    Reserving
+}
+
+impl ReserverCreep {
+    pub fn update(self, creep: &mut VirtualCreep, room: RoomName, home: &ColonyView<'_>, movement: &mut MovementRequests) -> anyhow::Result<Transition<Self>> {
+        use ReserverCreep::*;
+        use Transition::*;
+
+        let remote = home.remotes.active().find(|remote| remote.room == room);
+
+        match self {
+            Going => {
+                let Some(remote) = remote else {
+                    defer!(movement.move_vcreep_to(creep, Position::new(25.try_into().unwrap(), 25.try_into().unwrap(), room), 20), self)?;
+                    done!(self);
+                };
+
+                defer!(movement.move_vcreep_to(creep, remote.controller, 1), self)?;
+                next_if!(creep.body().part_count(Part::Claim) > 0, Reserving);
+
+                Ok(Done(self))
+            },
+            Reserving => {
+                let Some(remote) = remote else { next!(Going) };
+                defer!(movement.move_vcreep_to(creep, remote.controller, 1), self)?;
+
+                let controller = game::rooms().get(room)
+                    .and_then(|room| room.controller())
+                    .ok_or(anyhow!("Controller of {room} isn't visible"))?;
+
+                defer_err!(creep.reserve_controller(controller), self)?;
+                Ok(Done(self))
+            }
+        }
+    }
+}
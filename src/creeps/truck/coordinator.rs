@@ -1,16 +1,16 @@
-use std::cmp::Reverse;
+use std::{cmp::Reverse, hash::Hash};
 
-use screeps::{Creep, ResourceType, Room, StructureContainer, find};
+use screeps::{Creep, Position, ResourceType, Room, StructureContainer, find};
 use serde::{Deserialize, Serialize};
 
-use crate::{check::{Filtered, TriviallyChecked, deserialize_filter_check}, colony::plan::{ColonyPlan, refs::{PlannedStructureRef, PlannedStructureRefs}}, coordination::{allocations::{AllocationHandle, CreepAllocations, ResourceAmount}, tasks::{AddedToCollab, OverwriteableTaskData, Tasks}}, creeps::{truck::{state::TruckTask, stop::{ConsumerTruckStop, ProviderTruckStop}}, virtual_creep::VirtualCreep}, domain_traits::{EnergyStoreAccessors, HasId}, structure::{ConsumerStructure, ProviderStructure}};
+use crate::{check::{Filtered, TriviallyChecked, deserialize_filter_check}, colony::{plan::{ColonyPlan, refs::{PlannedStructureRef, PlannedStructureRefs}}, remote::Remotes}, coordination::{allocations::{AllocationHandle, CreepAllocations, ResourceAmount}, tasks::{AddedToCollab, OverwriteableTaskData, Tasks}}, creeps::{truck::{route::TruckRoute, state::{TruckTask, resource_destination}, stop::{ConsumerTruckStop, ProviderTruckStop, ResourceStop}}, virtual_creep::VirtualCreep}, domain_traits::{HasId, HasStoreExt}, labs::LabCoordinator, structure::{ConsumerStructure, ProviderStructure}};
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct TruckCoordinator {
     #[serde(deserialize_with = "deserialize_filter_check")] 
-    pub providers: Tasks<ProviderTruckStop, (ProviderTaskData, Filtered<CreepAllocations>)>,
+    pub providers: Tasks<ResourceStop<ProviderTruckStop>, (ProviderTaskData, Filtered<CreepAllocations>)>,
     #[serde(deserialize_with = "deserialize_filter_check")] 
-    pub consumers: Tasks<ConsumerTruckStop, (ConsumerTaskPriority, Filtered<CreepAllocations>)>
+    pub consumers: Tasks<ResourceStop<ConsumerTruckStop>, (ConsumerTaskPriority, Filtered<CreepAllocations>)>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,18 +28,28 @@ pub struct ConsumerTaskPriority(pub u32);
 impl TriviallyChecked for ConsumerTaskPriority {}
 impl OverwriteableTaskData for ConsumerTaskPriority {}
 
+const MAX_ROUTE_STOPS: usize = 6;
+const MAX_STOP_SPACING: u32 = 5;
+const MIN_CHAINED_PICKUP: u32 = 100;
+
 pub struct CreepStops {
     pub consumers: Vec<Creep>,
     pub providers: Vec<Creep>
 }
 
+// Energy the center structures are kept at by the trucks
+pub struct EnergyTargets {
+    pub terminal: u32,
+    pub central_link: u32
+}
+
 impl TruckCoordinator {
-    pub fn update(&mut self, plan: &ColonyPlan, room: &Room, creep_stops: CreepStops) {
-        self.update_providers(plan, room, creep_stops.providers);
-        self.update_consumers(plan, creep_stops.consumers);
+    pub fn update(&mut self, plan: &ColonyPlan, remotes: &Remotes, room: &Room, creep_stops: CreepStops, labs: &LabCoordinator, targets: &EnergyTargets) {
+        self.update_providers(plan, remotes, room, creep_stops.providers, labs, targets);
+        self.update_consumers(plan, creep_stops.consumers, labs, targets);
     }
 
-    fn update_providers(&mut self, plan: &ColonyPlan, room: &Room, provider_creeps: Vec<Creep>) {
+    fn update_providers(&mut self, plan: &ColonyPlan, remotes: &Remotes, room: &Room, provider_creeps: Vec<Creep>, lab_coordinator: &LabCoordinator, targets: &EnergyTargets) {
         let dropped_resources = room.find(find::DROPPED_RESOURCES, None).into_iter().map(|x| x.id()).map(ProviderTruckStop::Resource);
         let tombstones = room.find(find::TOMBSTONES, None).into_iter().map(|x| x.id()).map(ProviderTruckStop::Tombstone);
         let ruins = room.find(find::RUINS, None).into_iter().map(|x| x.id()).map(ProviderTruckStop::Ruin);
@@ -47,35 +57,69 @@ impl TruckCoordinator {
         let creep_providers = provider_creeps.into_iter().map(|x| x.id()).map(ProviderTruckStop::Creep);
         let center_link = plan.center.link.resolve().map(ProviderStructure::new).map(ProviderTruckStop::Structure);
         let unlinked_source_containers = plan.unlinked_source_containers().0.into_iter().filter_map(|x| x.resolve()).map(ProviderStructure::new).map(ProviderTruckStop::Structure);
+        let remote_source_containers = remotes.active().flat_map(|remote| remote.sources.values()).filter_map(|source_plan| source_plan.container.resolve()).map(ProviderStructure::new).map(ProviderTruckStop::Structure);
         
         let terminal = plan.center.terminal.resolve().map(ProviderStructure::new).map(ProviderTruckStop::Structure);
 
+        let mineral_container = plan.mineral.container.resolve().map(ProviderStructure::new).map(ProviderTruckStop::Structure);
+
         let mut providers = ProviderTasksBuilder::new();
-        providers.add_next_priority_group(dropped_resources).push_amount(0);
+        providers.add_next_priority_group(dropped_resources).any_resource().push_amount(0);
         providers.add_next_priority_group(creep_providers).push_amount(0);
-        providers.add_next_priority_group(tombstones);
-        providers.add_next_priority_group(ruins);
-        providers.add_next_priority_group(center_link).push_amount(0);
+        providers.add_next_priority_group(tombstones).any_resource();
+        providers.add_next_priority_group(ruins).any_resource();
+        providers.add_next_priority_group(center_link).min_leave(targets.central_link).push_amount(0);
         providers.add_next_priority_group(unlinked_source_containers).push_amount(500);
-        providers.add_next_priority_group(terminal).min_leave(10_000);
+        providers.add_next_priority_group(remote_source_containers).push_amount(500);
+        providers.add_next_priority_group(terminal).min_leave(targets.terminal.max(10_000));
+        for (lab, mineral, push_amount) in lab_coordinator.lab_unloads(plan) {
+            let stop = ProviderTruckStop::Structure(ProviderStructure::new(lab));
+            providers.add_next_priority_group([stop]).resource(mineral).push_amount(push_amount);
+        }
+        providers.add_next_priority_group(mineral_container).any_resource().push_amount(1000);
 
-        self.providers.set_tasks(providers.build());
+        self.providers.set_tasks(providers.build(|resource| resource == ResourceType::Energy || resource_destination(plan, resource).is_some()));
     }
 
-    fn update_consumers(&mut self, plan: &ColonyPlan, consumer_creeps: Vec<Creep>) {
+    fn update_consumers(&mut self, plan: &ColonyPlan, consumer_creeps: Vec<Creep>, lab_coordinator: &LabCoordinator, targets: &EnergyTargets) {
         let creep_consumers = consumer_creeps.into_iter().map(|x| x.id()).map(ConsumerTruckStop::Creep);
 
-        let center_spawn = plan.center.spawn.resolve().map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let center_spawns = plan.center.spawn.resolve().into_iter().chain(plan.center.extra_spawns.resolve()).map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
         let center_extensions = plan.center.extensions.iter().filter_map(PlannedStructureRef::resolve).map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
         let towers = plan.center.towers.iter().filter_map(PlannedStructureRef::resolve).map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
         let terminal = plan.center.terminal.resolve().map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let center_link = plan.center.link.resolve().map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let labs = plan.center.input_labs.iter().chain(plan.center.output_labs.iter()).filter_map(PlannedStructureRef::resolve)
+            .filter(|lab| !lab_coordinator.is_reserved(lab))
+            .map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let nuker = plan.center.nuker.resolve().map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let power_spawn = plan.center.power_spawn.resolve().map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
+        let controller_container = plan.controller.container.resolve()
+            .filter(|_| plan.controller.link.resolve().is_none())
+            .map(ConsumerStructure::new).map(ConsumerTruckStop::Structure);
 
         let mut consumers = ConsumerTasksBuilder::new();
-        consumers.add_next_priority_group(center_spawn);
+        consumers.add_next_priority_group(center_spawns);
         consumers.add_next_priority_group(center_extensions);
         consumers.add_next_priority_group(towers).threshold(0.8);
+        for (lab, reservation) in lab_coordinator.reserved_labs() {
+            let stop = ConsumerTruckStop::Structure(ConsumerStructure::new(lab));
+            consumers.add_next_priority_group([stop.clone()]).resource(reservation.compound).max_fill(reservation.mineral_required());
+            consumers.add_next_priority_group([stop]).max_fill(reservation.energy_required());
+        }
         consumers.add_next_priority_group(creep_consumers).threshold(0.35);
-        consumers.add_next_priority_group(terminal).max_fill(2_000).threshold(0.5);
+        consumers.add_next_priority_group(controller_container).threshold(0.5);
+        consumers.add_next_priority_group(center_link).max_fill(targets.central_link);
+        consumers.add_next_priority_group(terminal).max_fill(targets.terminal).threshold(0.5);
+        consumers.add_next_priority_group(labs.clone()).threshold(0.5);
+        for (lab, reagent, amount) in lab_coordinator.reagent_demands(plan) {
+            let stop = ConsumerTruckStop::Structure(ConsumerStructure::new(lab));
+            consumers.add_next_priority_group([stop]).resource(reagent).max_fill(amount);
+        }
+        consumers.add_next_priority_group(power_spawn.clone()).resource(ResourceType::Power).threshold(0.5);
+        consumers.add_next_priority_group(power_spawn).threshold(0.5);
+        consumers.add_next_priority_group(nuker.clone()).resource(ResourceType::Ghodium);
+        consumers.add_next_priority_group(nuker);
         self.consumers.set_tasks(consumers.build());
     }
 
@@ -86,43 +130,122 @@ impl TruckCoordinator {
         }
     }
 
-    pub fn assign_push_provider(&mut self, truck: &VirtualCreep) -> Option<ProviderTruckStop> {
+    pub fn assign_push_provider(&mut self, truck: &VirtualCreep) -> Option<ResourceStop<ProviderTruckStop>> {
         self.providers.iter_mut()
                 .filter(|(_, (_, collab))| collab.unreserved_amount() > 0)
                 .filter(|(_, (data, collab))| data.push_amount.is_some_and(|push_amount| collab.unreserved_amount() >= push_amount))
                 .max_by_key(|(provider, (data, _))|  {
                     (
                         data.priority, 
-                        Reverse(provider.pos().get_range_to(truck.pos()))
+                        Reverse(provider.stop.pos().get_range_to(truck.pos()))
                     )
                 }).added_to_collab(truck.handle(), truck.next_free_capacity(), ())
     }
 
-    pub fn assign_provider(&mut self, truck: &VirtualCreep) -> Option<ProviderTruckStop> {
+    pub fn assign_provider(&mut self, truck: &VirtualCreep) -> Option<ResourceStop<ProviderTruckStop>> {
         self.providers.iter_mut()
             .filter(|(_, (_, collab))| collab.unreserved_amount() > 0)
             .max_by_key(|(provider, (data, collab))| {
                 (
                     collab.unreserved_amount().min(truck.next_free_capacity()), 
                     data.priority,
-                    Reverse(provider.pos().get_range_to(truck.pos()))
+                    Reverse(provider.stop.pos().get_range_to(truck.pos()))
                 )
             }).added_to_collab(truck.handle(), truck.next_free_capacity(), ())
     }
 
-    pub fn assign_consumer(&mut self, truck: &VirtualCreep) -> Option<ConsumerTruckStop> {
-        self.consumers.iter_mut()
+    // Lets trucks fill up on the other resources of a pile they just collected from
+    pub fn assign_adjacent_provider(&mut self, truck: &VirtualCreep) -> Option<ResourceStop<ProviderTruckStop>> {
+        if truck.next_free_capacity() == 0 || truck.next_used_capacity(None) == 0 { return None }
+
+        self.providers.iter_mut()
+            .filter(|(_, (_, collab))| collab.unreserved_amount() > 0)
+            .filter(|(provider, _)| matches!(provider.stop, ProviderTruckStop::Tombstone(_) | ProviderTruckStop::Ruin(_) | ProviderTruckStop::Resource(_)))
+            .filter(|(provider, _)| provider.stop.pos().get_range_to(truck.pos()) <= 1)
+            .max_by_key(|(_, (data, collab))| (data.priority, collab.unreserved_amount()))
+            .added_to_collab(truck.handle(), truck.next_free_capacity(), ())
+    }
+
+    pub fn assign_consumer(&mut self, truck: &VirtualCreep, amount: impl Fn(ResourceType) -> u32) -> Option<ResourceStop<ConsumerTruckStop>> {
+        let consumer = self.consumers.iter_mut()
             .filter(|(_, (_, collab))| collab.unreserved_amount() > 0)
+            .filter(|(consumer, _)| amount(consumer.resource) > 0)
             .max_by_key(|(consumer, (priority, collab))| { 
                 (
                     priority.0, 
                     collab.unreserved_amount(), 
-                    Reverse(consumer.pos().get_range_to(truck.pos()))
+                    Reverse(consumer.stop.pos().get_range_to(truck.pos()))
                 )
-            }).added_to_collab(truck.handle(), truck.next_used_energy_capacity(), ())
+            });
+
+        let amount = consumer.as_ref().map_or(0, |(consumer, _)| amount(consumer.resource));
+        consumer.added_to_collab(truck.handle(), amount, ())
+    }
+
+    // Chains nearby providers onto the assigned one, so that the truck doesn't leave half empty
+    pub fn pickup_route(&mut self, truck: &VirtualCreep, first: Option<ResourceStop<ProviderTruckStop>>) -> Option<TruckRoute> {
+        let first = first?;
+        let resource = first.resource;
+        let is_pile = matches!(first.stop, ProviderTruckStop::Tombstone(_) | ProviderTruckStop::Ruin(_) | ProviderTruckStop::Resource(_));
+
+        let stops = chain_stops(&mut self.providers, truck, first, truck.next_free_capacity(), ProviderTruckStop::pos, |provider, _, amount| {
+            (is_pile || provider.resource == resource) && amount >= MIN_CHAINED_PICKUP
+        });
+
+        TruckRoute::new(truck, stops.into_iter().map(TruckTask::from), self)
+    }
+
+    // Chains consumers of the same resource onto the assigned one, without delaying ones of higher priority
+    pub fn delivery_route(&mut self, truck: &VirtualCreep, first: Option<ResourceStop<ConsumerTruckStop>>, amount: u32) -> Option<TruckRoute> {
+        let first = first?;
+        let resource = first.resource;
+        let priority = self.consumers.get(&first)?.0.0;
+
+        let stops = chain_stops(&mut self.consumers, truck, first, amount, ConsumerTruckStop::pos, |consumer, data, amount| {
+            consumer.resource == resource && data.0 >= priority && amount > 0
+        });
+
+        TruckRoute::new(truck, stops.into_iter().map(TruckTask::from), self)
     }
 }
 
+// Reserves what the truck can take on at a stop, replacing its previous reservation there
+fn reserve(collab: &mut CreepAllocations, truck: &VirtualCreep, capacity: u32) -> u32 {
+    collab.allocate(truck.handle(), 0, ());
+    let amount = collab.unreserved_amount().min(capacity);
+    collab.allocate(truck.handle(), amount, ());
+    amount
+}
+
+fn chain_stops<Stop: Clone + Hash + Eq, Data>(
+    tasks: &mut Tasks<ResourceStop<Stop>, (Data, Filtered<CreepAllocations>)>,
+    truck: &VirtualCreep,
+    first: ResourceStop<Stop>,
+    capacity: u32,
+    pos: impl Fn(&Stop) -> Position,
+    accept: impl Fn(&ResourceStop<Stop>, &Data, u32) -> bool
+) -> Vec<ResourceStop<Stop>> {
+    let Some((_, collab)) = tasks.get_mut(&first) else { return Vec::new() };
+    let mut left = capacity.saturating_sub(reserve(collab, truck, capacity));
+    let mut last = pos(&first.stop);
+    let mut stops = vec![first];
+
+    while left > 0 && stops.len() < MAX_ROUTE_STOPS {
+        let next = tasks.iter_mut()
+            .filter(|(stop, _)| !stops.contains(stop))
+            .filter(|(stop, _)| pos(&stop.stop).get_range_to(last) <= MAX_STOP_SPACING)
+            .filter(|(stop, (data, collab))| accept(stop, data, collab.unreserved_amount().min(left)))
+            .min_by_key(|(stop, _)| pos(&stop.stop).get_range_to(last));
+        let Some((stop, (_, collab))) = next else { break };
+
+        left -= reserve(collab, truck, left);
+        last = pos(&stop.stop);
+        stops.push(stop.clone());
+    }
+
+    stops
+}
+
 impl ColonyPlan {
     fn unlinked_source_containers(&self) -> PlannedStructureRefs<StructureContainer> {
         let center_link_exists = self.center.link.resolve().is_some();
@@ -153,13 +276,21 @@ impl ProviderTasksBuilder {
         &mut self.groups.push_mut((iter.into_iter().collect(), ProviderTasksGroupConfig::default())).1
     }
 
-    fn build(self) -> impl Iterator<Item = (ProviderTruckStop, (ProviderTaskData, ResourceAmount))> {
+    // Trucks store away whatever they pick up, so mixed providers only offer the resources there is room for
+    fn build(self, storable: impl Fn(ResourceType) -> bool + Copy) -> impl Iterator<Item = (ResourceStop<ProviderTruckStop>, (ProviderTaskData, ResourceAmount))> {
         self.groups.into_iter().rev().enumerate()
-            .flat_map(|(priority, (providers, config))| {
-                providers.into_iter().map(move |provider| {
-                    let provide = provider.get_resource_avaliable(ResourceType::Energy).saturating_sub(config.min_leave.unwrap_or(0));
-
-                    (provider, (ProviderTaskData { priority: priority as u32, push_amount: config.push_amount }, ResourceAmount(provide)))
+            .flat_map(move |(priority, (providers, config))| {
+                providers.into_iter().flat_map(move |provider| {
+                    let resources = if config.any_resource {
+                        provider.resources().into_iter().filter(|resource| storable(*resource)).collect()
+                    } else {
+                        vec![config.resource.unwrap_or(ResourceType::Energy)]
+                    };
+
+                    resources.into_iter().map(move |resource| {
+                        let provide = provider.get_resource_avaliable(Some(resource)).saturating_sub(config.min_leave.unwrap_or(0));
+                        (ResourceStop::new(provider.clone(), resource), (ProviderTaskData { priority: priority as u32, push_amount: config.push_amount }, ResourceAmount(provide)))
+                    })
                 })
             }).filter(|(_, (_, work))| work.0 > 0)
     }
@@ -168,12 +299,16 @@ impl ProviderTasksBuilder {
 #[derive(Default)]
 struct ProviderTasksGroupConfig {
     push_amount: Option<u32>, 
-    min_leave: Option<u32>
+    min_leave: Option<u32>,
+    any_resource: bool,
+    resource: Option<ResourceType>
 }
 
 impl ProviderTasksGroupConfig {
     fn push_amount(&mut self, x: u32) -> &mut Self { self.push_amount = Some(x); self }
     fn min_leave(&mut self, x: u32) -> &mut Self { self.min_leave = Some(x); self }
+    fn any_resource(&mut self) -> &mut Self { self.any_resource = true; self }
+    fn resource(&mut self, x: ResourceType) -> &mut Self { self.resource = Some(x); self }
 }
 
 struct ConsumerTasksBuilder {
@@ -189,22 +324,24 @@ impl ConsumerTasksBuilder {
         &mut self.groups.push_mut((iter.into_iter().collect(), ConsumerTasksGroupConfig::default())).1
     }
 
-    fn build(self) -> impl Iterator<Item = (ConsumerTruckStop, (ConsumerTaskPriority, ResourceAmount))> {
+    fn build(self) -> impl Iterator<Item = (ResourceStop<ConsumerTruckStop>, (ConsumerTaskPriority, ResourceAmount))> {
         self.groups.into_iter().rev().enumerate()
             .flat_map(|(priority, (consumers, config))| {
+                let resource = config.resource.unwrap_or(ResourceType::Energy);
+
                 consumers.into_iter()
                     .filter(move |consumer| {
                         let Some(fullness_threshold) = config.fullness_threshold else { return true };
 
-                        let upper_limit = config.max_fill.unwrap_or_else(|| consumer.energy_capacity());
-                        let ratio = consumer.used_energy_capacity() as f32 / upper_limit as f32;
+                        let upper_limit = config.max_fill.unwrap_or_else(|| consumer.capacity(Some(resource)));
+                        let ratio = consumer.used_capacity(Some(resource)) as f32 / upper_limit as f32;
                         ratio <= fullness_threshold
                     }).map(move |consumer| {
-                        let used = consumer.used_energy_capacity();
-                        let capacity_left = consumer.free_energy_capacity();
+                        let used = consumer.used_capacity(Some(resource));
+                        let capacity_left = consumer.free_capacity(Some(resource));
                         let consume = config.max_fill.map_or(capacity_left, |max_fill| max_fill.saturating_sub(used));
 
-                        (consumer, (ConsumerTaskPriority(priority as u32), ResourceAmount(consume)))
+                        (ResourceStop::new(consumer, resource), (ConsumerTaskPriority(priority as u32), ResourceAmount(consume)))
                     })
             }).filter(|(_, (_, work))| work.0 > 0)
     }
@@ -213,10 +350,12 @@ impl ConsumerTasksBuilder {
 #[derive(Default)]
 struct ConsumerTasksGroupConfig {
     max_fill: Option<u32>,
-    fullness_threshold: Option<f32>
+    fullness_threshold: Option<f32>,
+    resource: Option<ResourceType>
 }
 
 impl ConsumerTasksGroupConfig {
     fn max_fill(&mut self, x: u32) -> &mut Self { self.max_fill = Some(x); self }
     fn threshold(&mut self, x: f32) -> &mut Self { self.fullness_threshold = Some(x); self }
+    fn resource(&mut self, x: ResourceType) -> &mut Self { self.resource = Some(x); self }
 }
\ No newline at end of file
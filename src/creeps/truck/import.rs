@@ -6,19 +6,16 @@ use screeps::{HasPosition, ResourceType, RoomName};
 use serde::Deserialize;
 use anyhow::{Result, anyhow};
 
-use crate::{check::Check, colony::{ColonyBuffer, ColonyView, steps::ColonyStep}, coordination::allocations::CreepAllocationHandle, creeps::{truck::{TruckCoordinator, stop::ConsumerTruckStop}, virtual_creep::VirtualCreep}, defer, defer_err, domain_traits::EnergyStoreAccessors, done, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition};
-
-pub const STOP_IMPORT_STEP: ColonyStep = ColonyStep::UpgradeToLevel5;
-const START_EXPORT_STEP: ColonyStep = ColonyStep::UpgradeToLevel6;
+use crate::{check::Check, colony::{ColonyBuffer, ColonyView}, coordination::allocations::CreepAllocationHandle, creeps::{truck::{TruckCoordinator, stop::{ConsumerTruckStop, ResourceStop}}, virtual_creep::VirtualCreep}, defer, defer_err, domain_traits::EnergyStoreAccessors, done, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition, terminals::{exports_energy_to, is_exporting}};
 
 #[derive(Debug, Default, EnumDisplay)]
-#[derive_where(Serialize, Deserialize, Clone; ConsumerTruckStop<S>, ColonyBuffer<S>, S)]
+#[derive_where(Serialize, Deserialize, Clone; ResourceStop<ConsumerTruckStop<S>>, ColonyBuffer<S>, S)]
 pub enum ImportTruckState<S: CheckState = Checked> {
     #[default] Idle,
     CollectingFrom(RoomName),
     GoingHome,
     ProvidingIdle,
-    ProvidingTo(ConsumerTruckStop<S>),
+    ProvidingTo(ResourceStop<ConsumerTruckStop<S>>),
     StoringAway(ColonyBuffer<S>)
 }
 
@@ -52,7 +49,7 @@ impl ImportTruckState {
         match self {
             Self::Idle => {
                 let export_colony = colonies.values()
-                    .filter(|colony| colony.step >= START_EXPORT_STEP)
+                    .filter(|colony| is_exporting(colony) && !exports_energy_to(colony, home))
                     .max_by_key(|colony| colony.buffer.as_ref().map_or(0, EnergyStoreAccessors::used_energy_capacity))
                     .filter(|colony| colony.buffer.as_ref().is_some_and(|buffer| buffer.used_energy_capacity() > ENERGY_THRESHOLD));
                 
@@ -78,7 +75,7 @@ impl ImportTruckState {
             Self::ProvidingIdle => {
                 next_if!(creep.next_used_energy_capacity() == 0, Self::Idle);
 
-                if let Some(consumer) = coordinator.assign_consumer(creep) {
+                if let Some(consumer) = coordinator.assign_consumer(creep, |resource| creep.next_used_capacity(Some(resource))) {
                     next!(Self::ProvidingTo(consumer))
                 }
 
@@ -94,8 +91,8 @@ impl ImportTruckState {
                 let Some(mut task_handle) = coordinator.consumers.heartbeat(consumer, creep.handle()) else { next!(Self::ProvidingIdle) };
                 next_if!(creep.next_used_energy_capacity() == 0, Self::finish_task(task_handle));
 
-                defer!(movement.move_vcreep_to(creep, consumer.pos(), 1), self)?;
-                task_handle.consume(defer_err!(creep.transfer(consumer.clone(), ResourceType::Energy, None), self)?);
+                defer!(movement.move_vcreep_to(creep, consumer.stop.pos(), 1), self)?;
+                task_handle.consume(defer_err!(creep.transfer(consumer.stop.clone(), consumer.resource, None), self)?);
 
                 Self::finish_task(task_handle);
                 Ok(Next(Self::ProvidingIdle))
@@ -2,6 +2,7 @@ mod coordinator;
 mod stop;
 mod state;
 mod import;
+mod route;
 
-pub use self::{state::TruckCreep, import::{ImportTruckState, STOP_IMPORT_STEP}};
-pub use self::coordinator::{CreepStops, TruckCoordinator};
\ No newline at end of file
+pub use self::{state::TruckCreep, import::ImportTruckState};
+pub use self::coordinator::{CreepStops, EnergyTargets, TruckCoordinator};
\ No newline at end of file
@@ -0,0 +1,170 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use anyhow::bail;
+use derive_where::derive_where;
+use itertools::{Either, Itertools};
+use screeps::{Position, game};
+use serde::Deserialize;
+
+use crate::{check::{CheckFrom, FilterCheck}, creeps::{truck::{coordinator::TruckCoordinator, state::TruckTask, stop::{ConsumerTruckStop, ResourceStop}}, virtual_creep::VirtualCreep}, ids::{CheckState, Checked, Unchecked}, pathfinding};
+
+// Stops a truck has reserved, in the order it visits them
+#[derive(Debug)]
+#[derive_where(Serialize, Clone; TruckTask<S>)]
+pub struct TruckRoute<S: CheckState = Checked>(Vec<TruckTask<S>>);
+
+// Trucks used to be assigned a single stop at a time
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedTruckRoute<Task, Consumer> {
+    Route(Vec<Task>),
+    Task(Task),
+    FillingUpFor(Consumer)
+}
+
+impl<'de, S: CheckState> Deserialize<'de> for TruckRoute<S> where TruckTask<S>: Deserialize<'de>, ResourceStop<ConsumerTruckStop<S>>: Deserialize<'de> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match SerializedTruckRoute::<TruckTask<S>, ResourceStop<ConsumerTruckStop<S>>>::deserialize(deserializer)? {
+            SerializedTruckRoute::Route(tasks) => Self(tasks),
+            SerializedTruckRoute::Task(task) => Self(vec![task]),
+            SerializedTruckRoute::FillingUpFor(consumer) => Self(vec![TruckTask::ProvidingTo(consumer)])
+        })
+    }
+}
+
+impl CheckFrom for TruckRoute {
+    type Unchecked = TruckRoute<Unchecked>;
+    type Err = anyhow::Error;
+
+    fn check_from(us: Self::Unchecked) -> Result<Self, Self::Err> {
+        let (tasks, _): (Vec<TruckTask>, _) = us.0.filter_check();
+        if tasks.is_empty() { bail!("Route has no stops left") }
+        Ok(Self(tasks))
+    }
+}
+
+// Trucks share most of their legs between stops, so each leg is only pathed once per tick
+#[derive(Default)]
+struct LegLengths {
+    tick: u32,
+    lengths: HashMap<(Position, Position), Option<usize>>
+}
+
+thread_local! {
+    static LEG_LENGTHS: RefCell<LegLengths> = RefCell::new(LegLengths::default());
+}
+
+fn leg_length(from: Position, to: Position) -> Option<usize> {
+    LEG_LENGTHS.with_borrow_mut(|legs| {
+        if legs.tick != game::time() {
+            legs.tick = game::time();
+            legs.lengths.clear();
+        }
+
+        *legs.lengths.entry((from, to)).or_insert_with(|| {
+            let search = pathfinding::search(from, to, 1);
+            (!search.incomplete()).then(|| search.path().len())
+        })
+    })
+}
+
+// Greedily visits the closest remaining stop by path length. Stops which can't be reached are returned separately
+fn order_by_path_length<T>(from: Position, mut stops: Vec<T>, pos_of: impl Fn(&T) -> Position, mut path_length: impl FnMut(Position, Position) -> Option<usize>) -> (Vec<T>, Vec<T>) {
+    let mut ordered = Vec::with_capacity(stops.len());
+    let mut unreachable = Vec::new();
+    let mut pos = from;
+
+    while !stops.is_empty() {
+        let (reachable, blocked): (Vec<_>, Vec<_>) = stops.into_iter()
+            .partition_map(|stop| match path_length(pos, pos_of(&stop)) {
+                Some(length) => Either::Left((length, stop)),
+                None => Either::Right(stop)
+            });
+        unreachable.extend(blocked);
+
+        let Some(i) = reachable.iter().position_min_by_key(|(length, _)| *length) else { break };
+        stops = reachable.into_iter().map(|(_, stop)| stop).collect();
+
+        let stop = stops.swap_remove(i);
+        pos = pos_of(&stop);
+        ordered.push(stop);
+    }
+
+    (ordered, unreachable)
+}
+
+impl TruckRoute {
+    pub fn new(truck: &VirtualCreep, tasks: impl IntoIterator<Item = TruckTask>, coordinator: &mut TruckCoordinator) -> Option<Self> {
+        let (tasks, unreachable) = order_by_path_length(truck.pos(), tasks.into_iter().collect(), TruckTask::pos, leg_length);
+        Self(unreachable).release(truck, coordinator);
+
+        (!tasks.is_empty()).then_some(Self(tasks))
+    }
+
+    pub fn front(&self) -> &TruckTask {
+        &self.0[0]
+    }
+
+    pub fn advance(mut self) -> Option<Self> {
+        self.0.remove(0);
+        (!self.0.is_empty()).then_some(self)
+    }
+
+    // Keeps the reservations of the whole route alive, dropping the stops which have lost theirs
+    pub fn heartbeat(mut self, truck: &VirtualCreep, coordinator: &mut TruckCoordinator) -> Option<Self> {
+        self.0.retain(|task| coordinator.heartbeat(truck, task).is_some());
+        (!self.0.is_empty()).then_some(self)
+    }
+
+    pub fn release(self, truck: &VirtualCreep, coordinator: &mut TruckCoordinator) {
+        for task in self.0 {
+            if let Some(handle) = coordinator.heartbeat(truck, &task) { handle.release(); }
+        }
+    }
+
+    // Gives up the remaining pickups of the route, once the truck is full
+    pub fn without_pickups(self, truck: &VirtualCreep, coordinator: &mut TruckCoordinator) -> Option<Self> {
+        let (pickups, deliveries) = self.0.into_iter().partition(|task| matches!(task, TruckTask::CollectingFrom(_)));
+        Self(pickups).release(truck, coordinator);
+        (!deliveries.is_empty()).then_some(Self(deliveries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomName;
+
+    use super::*;
+
+    fn pos(x: u8) -> Position {
+        Position::new(x.try_into().unwrap(), 25.try_into().unwrap(), RoomName::new("W1N1").unwrap())
+    }
+
+    // Walks along y = 25, except that crossing x = 30 takes a detour of 20 tiles
+    fn path_length(from: Position, to: Position) -> usize {
+        let crossings = u8::from(from.x().u8() < 30) + u8::from(to.x().u8() < 30);
+        let detour = if crossings == 1 { 20 } else { 0 };
+        from.get_range_to(to) as usize + detour
+    }
+
+    #[test]
+    fn visits_the_closest_stop_by_path_first() {
+        let (ordered, unreachable) = order_by_path_length(pos(25), vec![31, 10, 40, 20], |x| pos(*x), |from, to| Some(path_length(from, to)));
+        assert_eq!(ordered, vec![20, 10, 31, 40]);
+        assert!(unreachable.is_empty());
+    }
+
+    #[test]
+    fn only_paths_from_the_stops_taken() {
+        let mut legs = Vec::new();
+        order_by_path_length(pos(25), vec![31, 10, 20], |x| pos(*x), |from, to| { legs.push((from, to)); Some(path_length(from, to)) });
+        assert_eq!(legs.iter().map(|(from, _)| from.x().u8()).collect_vec(), vec![25, 25, 25, 20, 20, 10]);
+    }
+
+    #[test]
+    fn unreachable_stops_are_skipped() {
+        let (ordered, unreachable) = order_by_path_length(pos(20), vec![40, 10, 25], |x| pos(*x), |from, to| (to != pos(25)).then(|| path_length(from, to)));
+        assert_eq!(ordered, vec![10, 40]);
+        assert_eq!(unreachable, vec![25]);
+    }
+}
@@ -3,16 +3,17 @@ use enum_display::EnumDisplay;
 use screeps::{HasPosition, Position, ResourceType};
 use serde::Deserialize;
 use anyhow::Result;
+use log::warn;
 
-use crate::{check::{Check, CheckFrom}, colony::ColonyView, coordination::allocations::CreepAllocationHandle, creeps::{truck::{TruckCreep::FillingUpFor, coordinator::TruckCoordinator, stop::{ConsumerTruckStop, ProviderTruckStop}}, virtual_creep::{IntentError, VirtualCreep}}, defer, defer_err, domain_traits::EnergyStoreAccessors, done_if, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition};
+use crate::{check::{Check, CheckFrom}, colony::{ColonyBuffer, ColonyView, plan::ColonyPlan}, creeps::{truck::{coordinator::TruckCoordinator, route::TruckRoute, stop::{ConsumerTruckStop, ProviderTruckStop, ResourceStop}}, virtual_creep::{IntentError, VirtualCreep}}, defer, defer_err, domain_traits::{EnergyStoreAccessors, HasStoreExt, ResolvableId}, done_if, ids::{CheckState, Checked, Unchecked}, movement::requests::MovementRequests, next, next_if, statemachine::Transition, structure::{ConsumerStructure, ProviderStructure}};
 
 #[derive(Debug, Default, EnumDisplay)]
-#[derive_where(Serialize, Deserialize, Clone; TruckTask<S>, ConsumerTruckStop<S>, S)]
+#[derive_where(Serialize, Deserialize, Clone; TruckRoute<S>, S)]
 pub enum TruckCreep<S: CheckState = Checked> {
     #[default] Idle,
-    Performing(TruckTask<S>),
+    Performing(TruckRoute<S>),
     StoringAway,
-    FillingUpFor(ConsumerTruckStop<S>)
+    FillingUpFor(TruckRoute<S>)
 }
 
 impl<'de> Deserialize<'de> for TruckCreep {
@@ -30,10 +31,10 @@ impl<'de> Deserialize<'de> for TruckCreep {
 }
 
 #[derive(Debug)]
-#[derive_where(Serialize, Deserialize, Clone; ProviderTruckStop<S>, ConsumerTruckStop<S>)]
+#[derive_where(Serialize, Deserialize, Clone; ResourceStop<ProviderTruckStop<S>>, ResourceStop<ConsumerTruckStop<S>>)]
 pub enum TruckTask<S: CheckState = Checked> {
-    CollectingFrom(ProviderTruckStop<S>),
-    ProvidingTo(ConsumerTruckStop<S>)
+    CollectingFrom(ResourceStop<ProviderTruckStop<S>>),
+    ProvidingTo(ResourceStop<ConsumerTruckStop<S>>)
 }
 
 impl CheckFrom for TruckTask {
@@ -49,9 +50,10 @@ impl CheckFrom for TruckTask {
 }
 
 impl TruckCreep {
-    fn finish_task(task_handle: CreepAllocationHandle<'_>) -> Self {
-        task_handle.release();
-        Self::Idle
+    pub fn refill_target(&self) -> Option<Position> {
+        let (Self::Performing(route) | Self::FillingUpFor(route)) = self else { return None };
+        let TruckTask::ProvidingTo(consumer) = route.front() else { return None };
+        (consumer.resource == ResourceType::Energy).then(|| consumer.stop.pos())
     }
 
     pub fn update(self, truck: &mut VirtualCreep, home: &ColonyView<'_>, movement: &mut MovementRequests, coordinator: &mut TruckCoordinator) -> Result<Transition<Self>> {
@@ -59,22 +61,28 @@ impl TruckCreep {
 
         match self {
             Self::Idle => {
+                let adjacent_provider = coordinator.assign_adjacent_provider(truck);
+                if let Some(route) = coordinator.pickup_route(truck, adjacent_provider) { next!(Self::Performing(route)) }
+
+                next_if!(truck.next_used_capacity(None) > truck.next_used_energy_capacity(), Self::StoringAway);
+
                 if truck.next_used_energy_capacity() > 0 {
-                    let consumer = coordinator.assign_consumer(truck);
-                    if let Some(consumer) = consumer { next!(Self::Performing(TruckTask::ProvidingTo(consumer))) }
+                    let consumer = coordinator.assign_consumer(truck, |resource| truck.next_used_capacity(Some(resource)));
+                    let amount = consumer.as_ref().map_or(0, |consumer| truck.next_used_capacity(Some(consumer.resource)));
+                    if let Some(route) = coordinator.delivery_route(truck, consumer, amount) { next!(Self::Performing(route)) }
 
                     next_if!(home.buffer.as_ref().is_some_and(|buffer| buffer.free_energy_capacity() > 0), Self::StoringAway);
                 } else {
                     let push_provider = coordinator.assign_push_provider(truck);
-                    if let Some(provider) = push_provider { next!(Self::Performing(TruckTask::CollectingFrom(provider))) }
+                    if let Some(route) = coordinator.pickup_route(truck, push_provider) { next!(Self::Performing(route)) }
 
-                    if home.buffer.as_ref().is_some_and(|buffer| buffer.used_energy_capacity() > 0) {
-                        let consumer = coordinator.assign_consumer(truck);
-                        if let Some(consumer) = consumer { next!(Self::FillingUpFor(consumer)) }
-                    }
+                    let consumer = coordinator.assign_consumer(truck, |resource| {
+                        if resource_source(home, resource).is_some() { truck.next_free_capacity() } else { 0 }
+                    });
+                    if let Some(route) = coordinator.delivery_route(truck, consumer, truck.next_free_capacity()) { next!(Self::FillingUpFor(route)) }
 
                     let provider = coordinator.assign_provider(truck);
-                    if let Some(provider) = provider { next!(Self::Performing(TruckTask::CollectingFrom(provider))) }
+                    if let Some(route) = coordinator.pickup_route(truck, provider) { next!(Self::Performing(route)) }
                 }
 
                 if let Some(buffer) = home.buffer.as_ref() {
@@ -83,49 +91,89 @@ impl TruckCreep {
 
                 Ok(Done(self))
             },
-            Self::Performing(ref task) => {
-                let Some(mut handle) = coordinator.heartbeat(truck, task) else { next!(Self::Idle) };
+            Self::Performing(route) => {
+                let Some(route) = route.heartbeat(truck, coordinator) else { next!(Self::Idle) };
+                let task = route.front().clone();
 
-                match task {
+                match &task {
                     TruckTask::CollectingFrom(_) => 
-                        next_if!(truck.next_free_capacity() == 0, Self::finish_task(handle)),
-                    TruckTask::ProvidingTo(task) => 
-                        next_if!(truck.next_used_energy_capacity() == 0, FillingUpFor(task.clone()))
+                        next_if!(truck.next_free_capacity() == 0, route.without_pickups(truck, coordinator).map_or(Self::Idle, Self::Performing)),
+                    TruckTask::ProvidingTo(consumer) => 
+                        next_if!(truck.next_used_capacity(Some(consumer.resource)) == 0, Self::FillingUpFor(route))
                 }
 
-                defer!(movement.move_vcreep_to(truck, task.pos(), 1), self)?;
+                defer!(movement.move_vcreep_to(truck, task.pos(), 1), Self::Performing(route))?;
 
-                done_if!(truck.incoming_energy() > 0, self);
-                handle.consume(defer_err!(task.creep_perform(truck), self)?);
+                done_if!(truck.incoming(None) > 0, Self::Performing(route));
+                let performed = defer_err!(task.creep_perform(truck), Self::Performing(route))?;
+
+                if let Some(mut handle) = coordinator.heartbeat(truck, &task) {
+                    handle.consume(performed);
+                    handle.release();
+                }
 
-                Ok(Next(Self::finish_task(handle)))
+                Ok(Next(route.advance().map_or(Self::Idle, Self::Performing)))
             },
-            Self::FillingUpFor(ref consumer) => {
-                let Some(mut handle) = coordinator.consumers.heartbeat(consumer, truck.handle()) else { next!(Self::Idle) };
+            Self::FillingUpFor(route) => {
+                let Some(route) = route.heartbeat(truck, coordinator) else { next!(Self::Idle) };
+                let TruckTask::ProvidingTo(consumer) = route.front().clone() else { next!(Self::Performing(route)) };
 
-                let Some(buffer) = home.buffer.as_ref().filter(|buffer| buffer.used_energy_capacity() > 0) else {
-                    next!(Self::finish_task(handle))
+                let Some(source) = resource_source(home, consumer.resource) else {
+                    route.release(truck, coordinator);
+                    next!(Self::Idle)
                 };
 
-                next_if!(truck.next_used_energy_capacity() > 0, Self::Performing(TruckTask::ProvidingTo(consumer.clone())));
+                next_if!(truck.next_used_capacity(Some(consumer.resource)) > 0, Self::Performing(route));
 
-                defer!(movement.move_vcreep_to(truck, buffer.pos(), 1), self)?;
+                defer!(movement.move_vcreep_to(truck, source.pos(), 1), Self::FillingUpFor(route))?;
 
-                done_if!(truck.outgoing() > 0, self);
-                handle.consume(defer_err!(truck.withdraw(*buffer, ResourceType::Energy, None), self)?);
+                done_if!(truck.outgoing() > 0, Self::FillingUpFor(route));
+                defer_err!(truck.withdraw(source, consumer.resource, None), Self::FillingUpFor(route))?;
 
-                Ok(Next(Self::Performing(TruckTask::ProvidingTo(consumer.clone()))))
+                Ok(Next(Self::Performing(route)))
             },
             Self::StoringAway => {
+                let resource = truck.carried_resources().into_iter().find(|ty| *ty != ResourceType::Energy);
+                if let Some(resource) = resource {
+                    // Cargo that can't be stored anymore, like when the terminal and storage filled up, is dropped instead
+                    let Some(destination) = resource_destination(home.plan, resource) else {
+                        warn!("Dropping {resource} of {} as there is nowhere to store it", truck.name());
+                        defer_err!(truck.drop(resource, None), self)?;
+                        next!(Self::Idle)
+                    };
+
+                    defer!(movement.move_vcreep_to(truck, destination.pos(), 1), self)?;
+
+                    done_if!(truck.incoming(None) > 0, self);
+                    defer_err!(truck.transfer(destination, resource, None), self)?;
+
+                    next!(Self::Idle);
+                }
+
                 let Some(buffer) = home.buffer.as_ref().filter(|buffer| buffer.free_energy_capacity() > 0) else { 
                     next!(Self::Idle)
                 };
                 
                 next_if!(truck.next_used_energy_capacity() == 0, Self::Idle);
 
+                // Trucks coming back from the remotes drop their energy into the links at the exits
+                let buffer_range = buffer.pos().get_range_to(truck.pos());
+                let entry_link = home.plan.entry_links.resolve().into_iter()
+                    .filter(|link| link.free_energy_capacity() > 0 && link.pos().get_range_to(truck.pos()) < buffer_range)
+                    .min_by_key(|link| link.pos().get_range_to(truck.pos()));
+
+                if let Some(link) = entry_link {
+                    defer!(movement.move_vcreep_to(truck, link.pos(), 1), self)?;
+
+                    done_if!(truck.incoming(None) > 0, self);
+                    defer_err!(truck.transfer(link, ResourceType::Energy, None), self)?;
+
+                    next!(Self::Idle);
+                }
+
                 defer!(movement.move_vcreep_to(truck, buffer.pos(), 1), self)?;
                 
-                done_if!(truck.incoming_energy() > 0, self);
+                done_if!(truck.incoming(None) > 0, self);
                 defer_err!(truck.transfer(*buffer, ResourceType::Energy, None), self)?;
 
                 Ok(Next(Self::Idle))
@@ -134,32 +182,60 @@ impl TruckCreep {
     }
 }
 
-impl From<ConsumerTruckStop> for TruckTask {
-    fn from(value: ConsumerTruckStop) -> Self {
+impl From<ResourceStop<ConsumerTruckStop>> for TruckTask {
+    fn from(value: ResourceStop<ConsumerTruckStop>) -> Self {
         TruckTask::ProvidingTo(value)
     }
 }
 
-impl From<ProviderTruckStop> for TruckTask {
-    fn from(value: ProviderTruckStop) -> Self {
+impl From<ResourceStop<ProviderTruckStop>> for TruckTask {
+    fn from(value: ResourceStop<ProviderTruckStop>) -> Self {
         TruckTask::CollectingFrom(value)
     }
 }
 
 impl TruckTask {
-    fn pos(&self) -> Position {
+    pub fn pos(&self) -> Position {
         match self {
-            TruckTask::CollectingFrom(provider) => provider.pos(),
-            TruckTask::ProvidingTo(consumer) => consumer.pos()
+            TruckTask::CollectingFrom(provider) => provider.stop.pos(),
+            TruckTask::ProvidingTo(consumer) => consumer.stop.pos()
         }
     }
 
     fn creep_perform(&self, truck: &mut VirtualCreep) -> anyhow::Result<u32, IntentError> {
         match self {
             TruckTask::CollectingFrom(provider) => 
-                provider.creep_withdraw(truck, ResourceType::Energy),
+                provider.stop.creep_withdraw(truck, provider.resource),
             TruckTask::ProvidingTo(consumer) => 
-                truck.transfer(consumer.clone(), ResourceType::Energy, None)
+                truck.transfer(consumer.stop.clone(), consumer.resource, None)
         }
     }
-}
\ No newline at end of file
+}
+
+const TERMINAL_RESOURCE_TARGET: u32 = 10_000;
+
+fn resource_source(home: &ColonyView<'_>, resource: ResourceType) -> Option<ProviderStructure> {
+    if resource == ResourceType::Energy {
+        return home.buffer.filter(|buffer| buffer.used_energy_capacity() > 0).map(|buffer| match buffer {
+            ColonyBuffer::Container(container) => ProviderStructure::new(container.resolve()),
+            ColonyBuffer::Storage(storage) => ProviderStructure::new(storage.resolve())
+        });
+    }
+
+    let storage = home.buffer.as_ref().and_then(ColonyBuffer::resolve_storage).filter(|storage| storage.used_capacity(Some(resource)) > 0);
+    storage.map(ProviderStructure::new)
+        .or_else(|| home.plan.center.terminal.resolve()
+            .filter(|terminal| terminal.used_capacity(Some(resource)) > 0)
+            .map(ProviderStructure::new))
+}
+
+// The terminal takes resources until it has enough to trade, labs are loaded through their own tasks
+pub fn resource_destination(plan: &ColonyPlan, resource: ResourceType) -> Option<ConsumerStructure> {
+    let terminal = plan.center.terminal.resolve()
+        .filter(|terminal| terminal.used_capacity(Some(resource)) < TERMINAL_RESOURCE_TARGET && terminal.free_capacity(Some(resource)) > 0);
+
+    terminal.map(ConsumerStructure::new)
+        .or_else(|| plan.center.storage.resolve()
+            .filter(|storage| storage.free_capacity(Some(resource)) > 0)
+            .map(ConsumerStructure::new))
+}
@@ -3,6 +3,7 @@ use std::hash::Hash;
 use anyhow::{anyhow, bail};
 use derive_where::derive_where;
 use screeps::{Creep, HasPosition, Position, Resource, ResourceType, Ruin, Tombstone};
+use serde::{Deserialize, Serialize};
 
 use crate::{check::{Check, CheckFrom}, creeps::virtual_creep::{IntentError, VirtualCreep}, domain_traits::{CreepId, HasStore, HasStoreExt, ObjectId, ResolvableId, Transferable}, ids::{CheckState, Checked, Unchecked}, structure::{ConsumerStructure, ProviderStructure}};
 
@@ -20,17 +21,15 @@ impl CheckFrom for ProviderTruckStop {
     type Unchecked = ProviderTruckStop<Unchecked>;
     type Err = anyhow::Error;
 
+    // Restricted stores report nothing used without a resource, so contents are checked per resource by ResourceStop
     fn check_from(uc: Self::Unchecked) -> Result<Self, Self::Err> {
-        let checked = match uc {
+        Ok(match uc {
             Self::Unchecked::Ruin(x) => Self::Ruin(x.check()?),
             Self::Unchecked::Resource(x) => Self::Resource(x.check()?),
             Self::Unchecked::Tombstone(x) => Self::Tombstone(x.check()?),
             Self::Unchecked::Structure(x) => Self::Structure(x.check()?),
             Self::Unchecked::Creep(x) => Self::Creep(x.check()?),
-        };
-
-        if checked.get_resource_avaliable(ResourceType::Energy) == 0 { bail!("Provider is empty"); }
-        Ok(checked)
+        })
     }
 }
 
@@ -45,14 +44,24 @@ impl ProviderTruckStop {
         }
     }
 
-    pub fn get_resource_avaliable(&self, ty: ResourceType) -> u32 { 
+    pub fn resources(&self) -> Vec<ResourceType> {
         match self {
-            Self::Ruin(id) => id.resolve().store().get_used_capacity(Some(ty)),
-            Self::Tombstone(id) => id.resolve().store().get_used_capacity(Some(ty)),
-            Self::Structure(id) => id.store().get_used_capacity(Some(ty)),
-            Self::Creep(id) => id.resolve().store().get_used_capacity(Some(ty)),
+            Self::Ruin(id) => id.resolve().store().store_types(),
+            Self::Tombstone(id) => id.resolve().store().store_types(),
+            Self::Structure(id) => id.store().store_types(),
+            Self::Creep(id) => id.resolve().store().store_types(),
+            Self::Resource(id) => vec![id.resolve().resource_type()],
+        }
+    }
+
+    pub fn get_resource_avaliable(&self, ty: Option<ResourceType>) -> u32 { 
+        match self {
+            Self::Ruin(id) => id.resolve().store().get_used_capacity(ty),
+            Self::Tombstone(id) => id.resolve().store().get_used_capacity(ty),
+            Self::Structure(id) => id.store().get_used_capacity(ty),
+            Self::Creep(id) => id.resolve().store().get_used_capacity(ty),
             Self::Resource(id) => 
-                if id.resolve().resource_type() == ty { id.resolve().amount() } else { 0 },
+                if ty.is_none_or(|ty| id.resolve().resource_type() == ty) { id.resolve().amount() } else { 0 },
         }
     }
 
@@ -84,14 +93,12 @@ impl CheckFrom for ConsumerTruckStop {
     type Unchecked = ConsumerTruckStop<Unchecked>;
     type Err = anyhow::Error;
 
+    // Restricted stores report no free capacity without a resource, so space is checked per resource by ResourceStop
     fn check_from(us: Self::Unchecked) -> Result<Self, Self::Err> {
-        let checked = match us {
+        Ok(match us {
             Self::Unchecked::Structure(x) => Self::Structure(x.check()?),
             Self::Unchecked::Creep(x) => Self::Creep(x.check()?),
-        };
-
-        if checked.free_capacity(Some(ResourceType::Energy)) == 0 { bail!("Consumer has no free space") }
-        Ok(checked)
+        })
     }
 }
 
@@ -120,4 +127,55 @@ impl Transferable for ConsumerTruckStop {
             ConsumerTruckStop::Creep(id) => id.resolve().transfer_from(creep, ty, amount),
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "SerializedResourceStop<T>")]
+pub struct ResourceStop<T> {
+    pub stop: T,
+    pub resource: ResourceType
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedResourceStop<T> {
+    Resource { stop: T, resource: ResourceType },
+    Energy(T)
+}
+
+impl<T> From<SerializedResourceStop<T>> for ResourceStop<T> {
+    fn from(stop: SerializedResourceStop<T>) -> Self {
+        match stop {
+            SerializedResourceStop::Resource { stop, resource } => Self { stop, resource },
+            SerializedResourceStop::Energy(stop) => Self { stop, resource: ResourceType::Energy }
+        }
+    }
+}
+
+impl<T> ResourceStop<T> {
+    pub fn new(stop: T, resource: ResourceType) -> Self {
+        Self { stop, resource }
+    }
+}
+
+impl CheckFrom for ResourceStop<ProviderTruckStop> {
+    type Unchecked = ResourceStop<ProviderTruckStop<Unchecked>>;
+    type Err = anyhow::Error;
+
+    fn check_from(uc: Self::Unchecked) -> Result<Self, Self::Err> {
+        let checked: Self = ResourceStop::new(uc.stop.check()?, uc.resource);
+        if checked.stop.get_resource_avaliable(Some(checked.resource)) == 0 { bail!("Provider has no {}", checked.resource); }
+        Ok(checked)
+    }
+}
+
+impl CheckFrom for ResourceStop<ConsumerTruckStop> {
+    type Unchecked = ResourceStop<ConsumerTruckStop<Unchecked>>;
+    type Err = anyhow::Error;
+
+    fn check_from(uc: Self::Unchecked) -> Result<Self, Self::Err> {
+        let checked: Self = ResourceStop::new(uc.stop.check()?, uc.resource);
+        if checked.stop.free_capacity(Some(checked.resource)) == 0 { bail!("Consumer has no space for {}", checked.resource); }
+        Ok(checked)
+    }
+}
@@ -2,7 +2,7 @@ use std::{collections::HashMap, error::Error};
 
 use anyhow::Result;
 use enum_display::EnumDisplay;
-use screeps::{ConstructionSite, Creep, HasPosition, Part, Position, Resource, ResourceType, SharedCreepProperties, Source, StructureController};
+use screeps::{ConstructionSite, Creep, HARVEST_MINERAL_POWER, HasPosition, Mineral, Part, Position, Resource, ResourceType, SharedCreepProperties, Source, StructureController};
 
 use crate::{domain_traits::{HasStoreExt, Repairable, Transferable, Withdrawable}, ids::{GetHandle, Handle}, movement::requests::{MoveToResult, MovementRequests}, spawn::prototype::Body, statemachine::ShouldYield};
 
@@ -318,9 +318,14 @@ impl VirtualCreep {
         self.total_outgoing_resources
     }
 
+    pub fn carried_resources(&self) -> Vec<ResourceType> {
+        self.creep.store().store_types().into_iter()
+            .filter(|ty| self.get_resource(*ty) > 0)
+            .collect()
+    }
+
     pub fn curr_used_energy_capacity(&self) -> u32 { self.curr_used_capacity(Some(ResourceType::Energy)) }
     pub fn next_used_energy_capacity(&self) -> u32 { self.next_used_capacity(Some(ResourceType::Energy)) }
-    pub fn incoming_energy(&self) -> u32 { self.incoming(Some(ResourceType::Energy)) }
     
     pub fn build(&mut self, target: ConstructionSite) -> Result<u32, IntentError> {
         let amount = self.part_amount(Part::Work, 5)
@@ -343,7 +348,38 @@ impl VirtualCreep {
         ).map(|_| ())
     }
 
-    #[expect(unused)]
+    pub fn reserve_controller(&mut self, target: StructureController) -> Result<(), IntentError> {
+        self.register_intent(
+            IntentType::ReserveController, 
+            Intent::new(move |creep| creep.reserve_controller(&target), None)
+        ).map(|_| ())
+    }
+
+    pub fn attack(&mut self, target: Creep) -> Result<(), IntentError> {
+        self.register_intent(
+            IntentType::Attack,
+            Intent::new(move |creep| creep.attack(&target), None)
+        ).map(|_| ())
+    }
+
+    pub fn ranged_attack(&mut self, target: Creep) -> Result<(), IntentError> {
+        self.register_intent(
+            IntentType::RangedAttack,
+            Intent::new(move |creep| creep.ranged_attack(&target), None)
+        ).map(|_| ())
+    }
+
+    pub fn heal_self(&mut self) -> Result<(), IntentError> {
+        self.register_intent(
+            IntentType::Heal,
+            Intent::new(|creep| creep.heal(creep), None)
+        ).map(|_| ())
+    }
+
+    pub fn is_damaged(&self) -> bool {
+        self.creep.hits() < self.creep.hits_max()
+    }
+
     pub fn drop(&mut self, ty: ResourceType, amount: Option<u32>) -> Result<u32, IntentError> {
         let amount = amount.unwrap_or(self.get_resource(ty));
 
@@ -370,6 +406,16 @@ impl VirtualCreep {
         )
     }
 
+    pub fn harvest_mineral(&mut self, mineral: Mineral) -> Result<u32, IntentError> {
+        let amount = self.part_amount(Part::Work, HARVEST_MINERAL_POWER)
+            .min(mineral.mineral_amount());
+
+        self.register_intent(
+            IntentType::Harvest,
+            Intent::new(move |creep| creep.harvest(&mineral), None)
+        ).map(|_| amount)
+    }
+
     pub fn pickup(&mut self, target: Resource) -> Result<u32, IntentError> {
         let ty = target.resource_type();
         let amount = target.amount()
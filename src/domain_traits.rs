@@ -188,7 +188,7 @@ pub mod screeps_objects {
         Deposit, Mineral, Nuke, PowerCreep, Resource, Ruin, Source, Structure,
         StructureContainer, StructureController, StructureExtension, 
         StructureExtractor, StructureFactory, StructureLab, StructureLink, 
-        StructureObserver, StructurePowerBank, StructurePowerSpawn, 
+        StructureNuker, StructureObserver, StructurePowerBank, StructurePowerSpawn, 
         StructurePortal, StructureRampart, StructureRoad, StructureSpawn, 
         StructureStorage, StructureTerminal, StructureTower, 
         StructureWall, Tombstone
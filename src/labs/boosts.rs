@@ -0,0 +1,107 @@
+use log::{info, warn};
+use screeps::{Boost, CREEP_LIFE_TIME, Creep, HasPosition, LAB_BOOST_ENERGY, LAB_BOOST_MINERAL, Part, ResourceType, SharedCreepProperties, find};
+use serde::{Deserialize, Serialize};
+
+use crate::{colony::ColonyView, creeps::CreepData, domain_traits::{CreepId, HasStoreExt, ResolvableId}, labs::{LabCoordinator, labs, stored_amount}, movement::requests::MovementRequests, spawn::prototype::RelativePrototype};
+
+// Boosting is given up on when the creep has waited this long for its lab to be filled
+const MAX_BOOST_WAIT: u32 = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BoostReservation {
+    pub creep: String,
+    pub compound: ResourceType,
+    pub parts: u32
+}
+
+impl BoostReservation {
+    pub fn new(boost: &PendingBoost) -> Self {
+        Self { creep: boost.creep.clone(), compound: boost.compound, parts: boost.parts }
+    }
+
+    pub fn is_for(&self, creep: &str, compound: ResourceType) -> bool {
+        self.creep == creep && self.compound == compound
+    }
+
+    pub fn mineral_required(&self) -> u32 {
+        self.parts * LAB_BOOST_MINERAL
+    }
+
+    pub fn energy_required(&self) -> u32 {
+        self.parts * LAB_BOOST_ENERGY
+    }
+}
+
+pub struct PendingBoost {
+    pub creep: String,
+    pub compound: ResourceType,
+    pub parts: u32
+}
+
+pub fn boosted_part(compound: ResourceType) -> Option<Part> {
+    Some(match compound.boost()? {
+        Boost::Harvest(_) | Boost::BuildAndRepair(_) | Boost::Dismantle(_) | Boost::UpgradeController(_) => Part::Work,
+        Boost::Attack(_) => Part::Attack,
+        Boost::RangedAttack(_) => Part::RangedAttack,
+        Boost::Heal(_) => Part::Heal,
+        Boost::Carry(_) => Part::Carry,
+        Boost::Move(_) => Part::Move,
+        Boost::Tough(_) => Part::Tough
+    })
+}
+
+fn unboosted_parts(creep: &Creep, compound: ResourceType) -> u32 {
+    let Some(part) = boosted_part(compound) else { return 0 };
+    creep.body().iter().filter(|bodypart| bodypart.part() == part && bodypart.boost().is_none()).count() as u32
+}
+
+pub fn pending_boosts<'a>(creeps: impl IntoIterator<Item = (&'a CreepId, &'a CreepData)>) -> Vec<PendingBoost> {
+    creeps.into_iter()
+        .flat_map(|(id, data)| {
+            let creep = id.resolve();
+            data.boosts.iter().map(move |compound| PendingBoost { creep: creep.name(), compound: *compound, parts: unboosted_parts(&creep, *compound) })
+        }).filter(|boost| boost.parts > 0)
+        .collect()
+}
+
+// Everything in the colony that could end up in a lab, including what is being carried there
+fn colony_amount(colony: &ColonyView<'_>, compound: ResourceType) -> u32 {
+    let labs = labs(colony).map(|lab| lab.used_capacity(Some(compound))).sum::<u32>();
+    let creeps = colony.room.find(find::MY_CREEPS, None).iter().map(|creep| creep.store().get_used_capacity(Some(compound))).sum::<u32>();
+
+    stored_amount(colony, compound) + labs + creeps
+}
+
+// Only asks for the boost if there are labs and enough of the compound to boost every part
+pub fn boosted_if_available(proto: RelativePrototype, colony: &ColonyView<'_>, compound: ResourceType) -> RelativePrototype {
+    let Some(part) = boosted_part(compound) else { return proto };
+    let required = proto.body().part_count(part) as u32 * LAB_BOOST_MINERAL;
+
+    if required == 0 || labs(colony).next().is_none() || colony_amount(colony, compound) < required { return proto }
+    proto.boosted(compound)
+}
+
+// Walks the creep to its reserved labs one boost at a time. Returns whether the creep is ready to start its role
+pub fn do_boosts(creep: &Creep, boosts: &mut Vec<ResourceType>, colony: &ColonyView<'_>, coordinator: &LabCoordinator, movement: &mut MovementRequests) -> bool {
+    let Some(compound) = boosts.first().copied() else { return true };
+    let parts = unboosted_parts(creep, compound);
+    let lab = coordinator.boost_lab(&creep.name(), compound);
+    let waited_too_long = creep.ticks_to_live().is_some_and(|ttl| ttl + MAX_BOOST_WAIT < CREEP_LIFE_TIME);
+    let missing = colony_amount(colony, compound) < parts * LAB_BOOST_MINERAL;
+
+    let Some(lab) = lab.filter(|_| parts > 0 && !waited_too_long && !missing) else {
+        if parts > 0 { warn!("{} is continuing without its {compound} boost", creep.name()) }
+        boosts.remove(0);
+        return boosts.is_empty();
+    };
+
+    if !movement.move_creep_to(creep, lab.pos(), 1).in_range() { return false }
+
+    let ready = lab.used_capacity(Some(compound)) >= parts * LAB_BOOST_MINERAL && lab.used_capacity(Some(ResourceType::Energy)) >= parts * LAB_BOOST_ENERGY;
+    if ready && lab.boost_creep(creep, None).is_ok() {
+        info!("Boosted {} parts of {} with {compound}", parts, creep.name());
+        boosts.remove(0);
+    }
+
+    false
+}
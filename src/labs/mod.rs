@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use screeps::{HasId, HasPosition, ObjectId, ResourceType, StructureLab};
+use serde::{Deserialize, Serialize};
+use serde_json_any_key::any_key_map;
+
+use crate::{colony::{ColonyBuffer, ColonyView, plan::refs::PlannedStructureRef}, domain_traits::HasStoreExt, labs::{boosts::{BoostReservation, PendingBoost}, reactions::Reaction}};
+
+pub mod boosts;
+pub mod reactions;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct LabCoordinator {
+    #[serde(with = "any_key_map")]
+    boosts: HashMap<ObjectId<StructureLab>, BoostReservation>,
+    #[serde(default)]
+    reaction: Option<Reaction>,
+    // Only compounds asked for with SetStockpile are produced, and bought on the market
+    #[serde(default, with = "any_key_map")]
+    stockpile: HashMap<ResourceType, u32>,
+    #[serde(default)]
+    last_planned: u32
+}
+
+impl LabCoordinator {
+    pub fn update(&mut self, colony: &ColonyView<'_>, pending: &[PendingBoost]) {
+        self.boosts.retain(|_, reservation| pending.iter().any(|boost| reservation.is_for(&boost.creep, boost.compound)));
+
+        for boost in pending {
+            if self.boosts.values().any(|reservation| reservation.is_for(&boost.creep, boost.compound)) { continue; }
+
+            // Labs already holding the compound are preferred, then output labs so reactions can keep running
+            let lab = labs(colony)
+                .filter(|lab| !self.boosts.contains_key(&lab.id()))
+                .filter(|lab| lab.mineral_type().is_none_or(|mineral| mineral == boost.compound))
+                .max_by_key(|lab| (lab.mineral_type().is_some(), is_output_lab(colony, lab)));
+
+            if let Some(lab) = lab {
+                self.boosts.insert(lab.id(), BoostReservation::new(boost));
+            }
+        }
+    }
+
+    pub fn boost_lab(&self, creep: &str, compound: ResourceType) -> Option<StructureLab> {
+        self.boosts.iter()
+            .find(|(_, reservation)| reservation.is_for(creep, compound))
+            .and_then(|(lab, _)| lab.resolve())
+    }
+
+    pub fn reserved_labs(&self) -> impl Iterator<Item = (StructureLab, &BoostReservation)> {
+        self.boosts.iter().filter_map(|(lab, reservation)| Some((lab.resolve()?, reservation)))
+    }
+
+    pub fn is_reserved(&self, lab: &StructureLab) -> bool {
+        self.boosts.contains_key(&lab.id())
+    }
+}
+
+pub fn labs(colony: &ColonyView<'_>) -> impl Iterator<Item = StructureLab> {
+    colony.plan.center.input_labs.iter()
+        .chain(colony.plan.center.output_labs.iter())
+        .filter_map(PlannedStructureRef::resolve)
+}
+
+fn is_output_lab(colony: &ColonyView<'_>, lab: &StructureLab) -> bool {
+    colony.plan.center.output_labs.iter().any(|output| output.pos == lab.pos())
+}
+
+// Resources kept in storage and the terminal
+pub fn stored_amount(colony: &ColonyView<'_>, resource: ResourceType) -> u32 {
+    let storage = colony.buffer.as_ref().and_then(ColonyBuffer::resolve_storage).map_or(0, |storage| storage.used_capacity(Some(resource)));
+    let terminal = colony.plan.center.terminal.resolve().map_or(0, |terminal| terminal.used_capacity(Some(resource)));
+
+    storage + terminal
+}
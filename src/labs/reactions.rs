@@ -0,0 +1,210 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use itertools::Itertools;
+use log::{info, warn};
+use screeps::{LAB_MINERAL_CAPACITY, LAB_REACTION_AMOUNT, ResourceType, RoomName, StructureLab, game};
+use serde::{Deserialize, Serialize};
+
+use crate::{colony::{ColonyView, plan::{ColonyPlan, refs::PlannedStructureRef}}, commands::{Command, handle_commands}, domain_traits::HasStoreExt, labs::{LabCoordinator, labs, stored_amount}, memory::Memory};
+
+const REACTION_BATCH: u32 = 1000;
+const REPLAN_INTERVAL: u32 = 100;
+const PRODUCT_UNLOAD_AMOUNT: u32 = 500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Reaction {
+    pub product: ResourceType,
+    pub remaining: u32
+}
+
+impl Reaction {
+    fn reagents(self) -> [ResourceType; 2] {
+        self.product.reaction_components().expect("Reactions are only planned for compounds")
+    }
+}
+
+// Walks down the reaction tree of the product until it reaches a reaction whose reagents are in stock
+fn next_reaction(product: ResourceType, amount: u32, stock: &impl Fn(ResourceType) -> u32) -> Option<Reaction> {
+    let reagents = product.reaction_components()?;
+
+    for reagent in reagents {
+        let missing = amount.saturating_sub(stock(reagent));
+        if missing > 0 && let Some(reaction) = next_reaction(reagent, missing, stock) { return Some(reaction) }
+    }
+
+    let possible = reagents.into_iter().map(stock).min()?.min(amount);
+    let possible = possible - possible % LAB_REACTION_AMOUNT;
+    (possible > 0).then_some(Reaction { product, remaining: possible })
+}
+
+// Collects the base minerals needed to produce the resource which aren't in stock
+fn collect_missing(resource: ResourceType, needed: u32, stock: &impl Fn(ResourceType) -> u32, missing: &mut HashMap<ResourceType, u32>) {
+    let short = needed.saturating_sub(stock(resource));
+    if short == 0 { return }
+
+    let Some(reagents) = resource.reaction_components() else {
+        *missing.entry(resource).or_default() += short;
+        return;
+    };
+
+    for reagent in reagents {
+        collect_missing(reagent, short, stock, missing);
+    }
+}
+
+fn lab_stock(colony: &ColonyView<'_>, resource: ResourceType) -> u32 {
+    stored_amount(colony, resource) + labs(colony).map(|lab| lab.used_capacity(Some(resource))).sum::<u32>()
+}
+
+impl LabCoordinator {
+    fn input_labs(&self, plan: &ColonyPlan) -> Option<[StructureLab; 2]> {
+        let labs = plan.center.input_labs.iter()
+            .filter_map(PlannedStructureRef::resolve)
+            .filter(|lab| !self.is_reserved(lab))
+            .collect_vec();
+
+        labs.try_into().ok()
+    }
+
+    fn output_labs(&self, plan: &ColonyPlan) -> impl Iterator<Item = StructureLab> {
+        plan.center.output_labs.iter()
+            .filter_map(PlannedStructureRef::resolve)
+            .filter(|lab| !self.is_reserved(lab))
+    }
+
+    fn plan_reaction(&self, colony: &ColonyView<'_>) -> Option<Reaction> {
+        let stock = |resource| lab_stock(colony, resource);
+
+        self.stockpile.iter()
+            .map(|(product, target)| (*product, target.saturating_sub(stock(*product))))
+            .filter(|(_, deficit)| *deficit > 0)
+            .sorted_by_key(|(_, deficit)| Reverse(*deficit))
+            .find_map(|(product, deficit)| next_reaction(product, deficit.min(REACTION_BATCH), &stock))
+    }
+
+    // Base minerals which have to be brought in to reach the stockpile targets
+    pub fn missing_minerals(&self, colony: &ColonyView<'_>) -> HashMap<ResourceType, u32> {
+        let stock = |resource| lab_stock(colony, resource);
+
+        let mut missing = HashMap::new();
+        for (product, target) in &self.stockpile {
+            collect_missing(*product, *target, &stock, &mut missing);
+        }
+
+        missing
+    }
+
+    pub fn is_stockpiled(&self, resource: ResourceType) -> bool {
+        self.stockpile.contains_key(&resource)
+    }
+
+    pub fn run_reactions(&mut self, colony: &ColonyView<'_>) {
+        if self.input_labs(colony.plan).is_none() { return }
+
+        // Idle and stalled labs only look for a new reaction now and then, as stock changes slowly
+        let finished = self.reaction.is_some_and(|reaction| reaction.remaining == 0);
+        let stalled = self.reaction.is_none_or(|reaction| reaction.reagents().iter().any(|reagent| lab_stock(colony, *reagent) < LAB_REACTION_AMOUNT));
+        if finished || (stalled && game::time() >= self.last_planned + REPLAN_INTERVAL) {
+            self.last_planned = game::time();
+            self.reaction = self.plan_reaction(colony);
+
+            if let Some(reaction) = self.reaction {
+                info!("Reacting {} {} in {}", reaction.remaining, reaction.product, colony.name);
+            }
+        }
+
+        let Some(reaction) = self.reaction else { return };
+        let Some([input1, input2]) = self.input_labs(colony.plan) else { return };
+
+        let [reagent1, reagent2] = reaction.reagents();
+        if input1.mineral_type() != Some(reagent1) || input2.mineral_type() != Some(reagent2) { return }
+
+        let mut remaining = reaction.remaining;
+        for lab in self.output_labs(colony.plan) {
+            if remaining == 0 { break; }
+            if lab.cooldown() > 0 || lab.mineral_type().is_some_and(|mineral| mineral != reaction.product) { continue; }
+
+            if lab.run_reaction(&input1, &input2).is_ok() {
+                remaining = remaining.saturating_sub(LAB_REACTION_AMOUNT);
+            }
+        }
+
+        self.reaction = Some(Reaction { remaining, ..reaction });
+    }
+
+    // Reagents the input labs should be loaded with
+    pub fn reagent_demands(&self, plan: &ColonyPlan) -> Vec<(StructureLab, ResourceType, u32)> {
+        let (Some(reaction), Some(labs)) = (self.reaction, self.input_labs(plan)) else { return Vec::new() };
+
+        labs.into_iter()
+            .zip(reaction.reagents())
+            .filter(|(lab, reagent)| lab.mineral_type().is_none_or(|mineral| mineral == *reagent))
+            .map(|(lab, reagent)| (lab, reagent, reaction.remaining.min(LAB_MINERAL_CAPACITY)))
+            .collect()
+    }
+
+    // Minerals to take out of the labs, along with the amount worth making a trip for
+    pub fn lab_unloads(&self, plan: &ColonyPlan) -> Vec<(StructureLab, ResourceType, u32)> {
+        let reagents = self.reaction.map(Reaction::reagents);
+        let product = self.reaction.map(|reaction| reaction.product);
+
+        let inputs = self.input_labs(plan).into_iter().flatten().enumerate()
+            .filter_map(|(i, lab)| {
+                let mineral = lab.mineral_type()?;
+                (reagents.is_none_or(|reagents| reagents[i] != mineral)).then_some((lab, mineral, 0))
+            });
+
+        let outputs = self.output_labs(plan)
+            .filter_map(|lab| {
+                let mineral = lab.mineral_type()?;
+                Some((lab, mineral, if Some(mineral) == product { PRODUCT_UNLOAD_AMOUNT } else { 0 }))
+            });
+
+        inputs.chain(outputs).collect()
+    }
+}
+
+pub fn do_labs(mem: &mut Memory) {
+    handle_stockpile_commands(mem);
+
+    for colony in mem.colonies.view_all() {
+        mem.lab_coordinators.entry(colony.name).or_default().run_reactions(&colony);
+    }
+}
+
+fn handle_stockpile_commands(mem: &mut Memory) {
+    handle_commands(|command| {
+        let Command::SetStockpile { room, resource, amount } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+
+        if resource.reaction_components().is_none() {
+            warn!("{resource} can't be produced in labs");
+            return true;
+        }
+
+        let coordinator = mem.lab_coordinators.entry(room).or_default();
+        if *amount == 0 { coordinator.stockpile.remove(resource); } else { coordinator.stockpile.insert(*resource, *amount); }
+        coordinator.last_planned = 0;
+
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::ListStockpile { room } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+        let Some(coordinator) = mem.lab_coordinators.get(&room) else {
+            info!("{room} has no labs");
+            return true;
+        };
+
+        for (resource, amount) in coordinator.stockpile.iter().sorted_by_key(|(resource, _)| resource.to_string()) {
+            info!("{resource}: {amount}");
+        }
+
+        if let Some(reaction) = coordinator.reaction {
+            info!("Currently reacting {} {}", reaction.remaining, reaction.product);
+        }
+
+        true
+    });
+}
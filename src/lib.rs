@@ -8,16 +8,14 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
 #![allow(clippy::cast_sign_loss, clippy::cast_precision_loss )]
 
-use std::cmp::Reverse;
-
 use getrandom::register_custom_getrandom;
 use itertools::Itertools;
 use log::info;
 use rand::{RngCore, SeedableRng, rngs::StdRng};
-use screeps::{StructureLink, game};
+use screeps::game;
 use wasm_bindgen::prelude::*;
 
-use crate::{creeps::do_creeps, domain_traits::EnergyStoreAccessors, memory::Memory, spawn::do_spawns, tower::do_towers};
+use crate::{creeps::{do_creeps, truck::EnergyTargets}, labs::{boosts::pending_boosts, reactions::do_labs}, links::{central_link_target, do_links}, market::do_market, memory::Memory, spawn::do_spawns, terminals::{do_terminals, terminal_energy_target}, threat::assess_threats, tower::do_towers};
 
 mod logging;
 mod names;
@@ -38,6 +36,11 @@ mod coordination;
 mod check;
 mod ids;
 mod structure;
+mod labs;
+mod links;
+mod market;
+mod terminals;
+mod threat;
 
 static INIT_LOGGING: std::sync::Once = std::sync::Once::new();
 
@@ -71,12 +74,16 @@ pub fn game_loop() {
     }
 
     update_coordinators(&mut mem);
+    assess_threats(&mut mem);
     let tugboat_requests = do_creeps(&mut mem);
 
     do_spawns(&mut mem, tugboat_requests);
 
     do_towers();
-    do_links(&mut mem);
+    do_links(&mem);
+    do_labs(&mut mem);
+    do_terminals(&mem);
+    do_market(&mut mem);
 
     mem.tick_times.push_front(game::cpu::get_used());
     if mem.tick_times.len() > 500 { mem.tick_times.pop_back(); }
@@ -101,34 +108,16 @@ fn update_coordinators(mem: &mut Memory) {
     for colony in mem.colonies.view_all() {
         let creep_stops = mem.get_creep_stops(colony.name);
 
-        mem.truck_coordinators.entry(colony.name).or_default().update(colony.plan, &colony.room, creep_stops);
-        mem.fabricator_coordinators.entry(colony.name).or_default().update(&colony.room);
-    }
-}
+        let boosts = pending_boosts(mem.creeps.iter().filter(|(_, data)| data.home == colony.name));
+        let lab_coordinator = mem.lab_coordinators.entry(colony.name).or_default();
+        lab_coordinator.update(&colony, &boosts);
 
-fn do_links(mem: &mut Memory) {
-    for colony in mem.colonies.view_all(){
-        let central_link: Option<StructureLink> = colony.plan.center.link.resolve();
-        let Some(central_link) = central_link else { continue };
-
-        let source_links: Vec<StructureLink> = colony.plan.sources.values()
-            .filter_map(|plan| {
-                let link = plan.link.resolve()?;
-
-                let link_energy = link.used_energy_capacity();
-                let container_energy = plan.container.resolve().map_or(0, |container| container.used_energy_capacity());
-
-                Some((link, link_energy + container_energy))
-            }).sorted_by_key(|(_, energy)| Reverse(*energy))
-            .map(|(link, _)| link)
-            .collect_vec();
-
-        for source_link in source_links {
-            if source_link.used_energy_capacity() > 400
-                && central_link.free_energy_capacity() > 50 {
-                    source_link.transfer_energy(&central_link, None).ok();
-                    break;
-                }
-        }
+        mem.truck_coordinators.entry(colony.name).or_default().update(colony.plan, colony.remotes, &colony.room, creep_stops, lab_coordinator, &EnergyTargets {
+            terminal: terminal_energy_target(&colony),
+            central_link: central_link_target(&colony)
+        });
+
+        let remote_rooms = colony.remotes.active().filter_map(|remote| game::rooms().get(remote.room)).collect_vec();
+        mem.fabricator_coordinators.entry(colony.name).or_default().update(&colony.room, &remote_rooms);
     }
-}
\ No newline at end of file
+}
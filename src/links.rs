@@ -0,0 +1,230 @@
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+use log::warn;
+use screeps::{ENERGY_REGEN_TIME, HasPosition, LINK_CAPACITY, LINK_LOSS_RATIO, Part, Position, SOURCE_ENERGY_CAPACITY, StructureLink, UPGRADE_CONTROLLER_POWER, find};
+
+use crate::{colony::ColonyView, domain_traits::EnergyStoreAccessors, memory::Memory};
+
+// How far ahead links plan, a link which would run dry or overflow within this many ticks is served now
+const FORECAST_TICKS: u32 = 25;
+
+// Smaller transfers aren't worth the cooldown they put on the sender
+const MIN_TRANSFER: u32 = 100;
+
+const SOURCE_INCOME: u32 = SOURCE_ENERGY_CAPACITY / ENERGY_REGEN_TIME;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LinkRole {
+    Source,
+    // Filled by remote trucks on their way in, so their income can't be forecast
+    Entry,
+    Central,
+    Controller
+}
+
+#[derive(Clone, Debug)]
+struct NetworkLink {
+    role: LinkRole,
+    pos: Position,
+    energy: u32,
+    ready: bool,
+    // Energy the link is expected to gain over the forecast, negative when it is drained
+    forecast: i32
+}
+
+impl NetworkLink {
+    fn of_link(link: &StructureLink, role: LinkRole, forecast: i32) -> Self {
+        Self { role, pos: link.pos(), energy: link.used_energy_capacity(), ready: link.cooldown() == 0, forecast }
+    }
+
+    fn projected(&self) -> i32 {
+        self.energy as i32 + self.forecast
+    }
+
+    fn supply(&self) -> u32 {
+        if self.ready { self.energy } else { 0 }
+    }
+
+    fn free(&self) -> u32 {
+        LINK_CAPACITY.saturating_sub(self.energy)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct LinkTransfer {
+    from: usize,
+    to: usize,
+    amount: u32
+}
+
+// Energy upgrading creeps take out of the controller link per tick
+fn upgrade_rate(colony: &ColonyView<'_>) -> u32 {
+    colony.room.find(find::MY_CREEPS, None).into_iter()
+        .filter(|creep| creep.pos().get_range_to(colony.controller.pos()) <= 3)
+        .map(|creep| u32::from(creep.get_active_bodyparts(Part::Work)) * UPGRADE_CONTROLLER_POWER)
+        .sum()
+}
+
+fn network(colony: &ColonyView<'_>) -> Vec<(StructureLink, NetworkLink)> {
+    let sources = colony.plan.sources.values()
+        .filter_map(|source_plan| source_plan.link.resolve())
+        .map(|link| (LinkRole::Source, link, (SOURCE_INCOME * FORECAST_TICKS) as i32));
+
+    let entries = colony.plan.entry_links.resolve().into_iter()
+        .map(|link| (LinkRole::Entry, link, 0));
+
+    let central = colony.plan.center.link.resolve()
+        .map(|link| (LinkRole::Central, link, 0));
+
+    let controller = colony.plan.controller.link.resolve()
+        .map(|link| (LinkRole::Controller, link, -((upgrade_rate(colony) * FORECAST_TICKS) as i32)));
+
+    sources.chain(entries).chain(central).chain(controller)
+        .map(|(role, link, forecast)| {
+            let network_link = NetworkLink::of_link(&link, role, forecast);
+            (link, network_link)
+        })
+        .collect()
+}
+
+// The central link is filled from storage when the source links can't keep up with the controller
+pub fn central_link_target(colony: &ColonyView<'_>) -> u32 {
+    central_target(&network(colony).into_iter().map(|(_, link)| link).collect_vec())
+}
+
+fn central_target(links: &[NetworkLink]) -> u32 {
+    let income = links.iter().filter(|link| link.role == LinkRole::Source).map(|link| link.forecast).sum::<i32>();
+    let drain = links.iter().filter(|link| link.role == LinkRole::Controller).map(|link| -link.forecast).sum::<i32>();
+    let has_central = links.iter().any(|link| link.role == LinkRole::Central);
+
+    if has_central && drain > income { LINK_CAPACITY } else { 0 }
+}
+
+// Every hop loses energy, so consumers are served straight from the source links whenever possible
+fn best_supplier(links: &[NetworkLink], to: usize, suppliers: &[LinkRole]) -> Option<usize> {
+    links.iter().enumerate()
+        .filter(|(i, link)| *i != to && suppliers.contains(&link.role) && link.supply() >= MIN_TRANSFER)
+        .max_by_key(|(_, link)| (
+            Reverse(suppliers.iter().position(|role| *role == link.role)),
+            link.supply(),
+            Reverse(link.pos.get_range_to(links[to].pos))
+        ))
+        .map(|(i, _)| i)
+}
+
+fn plan_transfer(links: &mut [NetworkLink], transfers: &mut Vec<LinkTransfer>, from: usize, to: usize) {
+    let amount = links[from].supply().min(links[to].free());
+    if amount < MIN_TRANSFER { return }
+
+    let loss = (amount as f32 * LINK_LOSS_RATIO).ceil() as u32;
+    links[from].energy -= amount;
+    links[from].ready = false;
+    links[to].energy += amount - loss;
+    transfers.push(LinkTransfer { from, to, amount });
+}
+
+fn plan_transfers(mut links: Vec<NetworkLink>) -> Vec<LinkTransfer> {
+    let mut transfers = Vec::new();
+
+    for controller in links.iter().positions(|link| link.role == LinkRole::Controller).collect_vec() {
+        if links[controller].projected() > 0 { continue; }
+
+        if let Some(supplier) = best_supplier(&links, controller, &[LinkRole::Source, LinkRole::Entry, LinkRole::Central]) {
+            plan_transfer(&mut links, &mut transfers, supplier, controller);
+        }
+    }
+
+    // Source links hold on to their energy for the controller, until they would overflow,
+    // while entry links are emptied right away to make room for the next truck
+    let Some(central) = links.iter().position(|link| link.role == LinkRole::Central) else { return transfers };
+    for supplier in links.iter().positions(|link| matches!(link.role, LinkRole::Source | LinkRole::Entry)).collect_vec() {
+        if links[supplier].role == LinkRole::Source && links[supplier].projected() < LINK_CAPACITY as i32 { continue; }
+        plan_transfer(&mut links, &mut transfers, supplier, central);
+    }
+
+    transfers
+}
+
+fn do_link_network(colony: &ColonyView<'_>) {
+    let (structures, links): (Vec<_>, Vec<_>) = network(colony).into_iter().unzip();
+
+    for LinkTransfer { from, to, amount } in plan_transfers(links) {
+        if let Err(err) = structures[from].transfer_energy(&structures[to], Some(amount)) {
+            warn!("Unable to transfer {amount} energy between links: {err}");
+        }
+    }
+}
+
+pub fn do_links(mem: &Memory) {
+    for colony in mem.colonies.view_all() {
+        do_link_network(&colony);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomName;
+
+    use super::*;
+
+    fn link(role: LinkRole, x: u8, energy: u32, forecast: i32) -> NetworkLink {
+        let pos = Position::new(x.try_into().unwrap(), 25.try_into().unwrap(), RoomName::new("W1N1").unwrap());
+        NetworkLink { role, pos, energy, ready: true, forecast }
+    }
+
+    fn source(x: u8, energy: u32) -> NetworkLink {
+        link(LinkRole::Source, x, energy, (SOURCE_INCOME * FORECAST_TICKS) as i32)
+    }
+
+    #[test]
+    fn closest_source_supplies_the_controller() {
+        let links = vec![source(5, 400), source(30, 400), link(LinkRole::Central, 20, 800, 0), link(LinkRole::Controller, 35, 0, -100)];
+        assert_eq!(plan_transfers(links), vec![LinkTransfer { from: 1, to: 3, amount: 400 }]);
+    }
+
+    #[test]
+    fn central_link_supplies_the_controller_last() {
+        let links = vec![source(5, 50), link(LinkRole::Entry, 45, 300, 0), link(LinkRole::Central, 20, 800, 0), link(LinkRole::Controller, 35, 0, -100)];
+        assert_eq!(plan_transfers(links)[0], LinkTransfer { from: 1, to: 3, amount: 300 });
+
+        let links = vec![source(5, 50), link(LinkRole::Central, 20, 800, 0), link(LinkRole::Controller, 35, 0, -100)];
+        assert_eq!(plan_transfers(links), vec![LinkTransfer { from: 1, to: 2, amount: 800 }]);
+    }
+
+    #[test]
+    fn supplied_controller_is_left_alone() {
+        let links = vec![source(5, 400), link(LinkRole::Controller, 35, 500, -100)];
+        assert!(plan_transfers(links).is_empty());
+    }
+
+    #[test]
+    fn source_links_hold_energy_until_they_would_overflow() {
+        let links = vec![source(5, 500), link(LinkRole::Central, 20, 0, 0)];
+        assert!(plan_transfers(links).is_empty());
+
+        let links = vec![source(5, 600), link(LinkRole::Central, 20, 0, 0)];
+        assert_eq!(plan_transfers(links), vec![LinkTransfer { from: 0, to: 1, amount: 600 }]);
+    }
+
+    #[test]
+    fn entry_links_are_emptied_right_away() {
+        let links = vec![link(LinkRole::Entry, 45, 200, 0), link(LinkRole::Central, 20, 700, 0)];
+        assert_eq!(plan_transfers(links), vec![LinkTransfer { from: 0, to: 1, amount: 100 }]);
+    }
+
+    #[test]
+    fn links_on_cooldown_send_nothing() {
+        let links = vec![NetworkLink { ready: false, ..source(5, 800) }, link(LinkRole::Central, 20, 0, 0), link(LinkRole::Controller, 35, 0, -100)];
+        assert!(plan_transfers(links).is_empty());
+    }
+
+    #[test]
+    fn central_link_is_filled_when_sources_fall_short() {
+        let controller = |drain: i32| link(LinkRole::Controller, 35, 0, -drain);
+
+        assert_eq!(central_target(&[source(5, 0), link(LinkRole::Central, 20, 0, 0), controller(200)]), 0);
+        assert_eq!(central_target(&[source(5, 0), link(LinkRole::Central, 20, 0, 0), controller(1000)]), LINK_CAPACITY);
+        assert_eq!(central_target(&[source(5, 0), controller(1000)]), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::anyhow;
+use js_sys::JsString;
+use screeps::{LodashFilter, MarketResourceType, OrderType, ResourceType, RoomName, game};
+
+#[derive(Clone, Debug)]
+pub struct MarketOrder {
+    pub id: String,
+    pub order_type: OrderType,
+    pub resource: ResourceType,
+    pub price: f64,
+    pub amount: u32,
+    pub room: RoomName
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PriceRecord {
+    pub avg_price: f64,
+    pub stddev_price: f64,
+    pub volume: u32
+}
+
+// Everything the trader needs from the market, so that it can be run against a local order book
+pub trait OrderBook {
+    fn credits(&self) -> f64;
+    fn cpu_used(&self) -> f64;
+    fn orders(&self, resource: ResourceType, order_type: OrderType) -> Vec<MarketOrder>;
+    fn history(&self, resource: ResourceType) -> Vec<PriceRecord>;
+    fn transaction_cost(&self, amount: u32, from: RoomName, to: RoomName) -> u32;
+    fn deal(&mut self, order: &MarketOrder, amount: u32, room: RoomName) -> anyhow::Result<()>;
+}
+
+pub struct GameOrderBook;
+
+impl OrderBook for GameOrderBook {
+    fn credits(&self) -> f64 {
+        game::market::credits()
+    }
+
+    fn cpu_used(&self) -> f64 {
+        game::cpu::get_used()
+    }
+
+    fn orders(&self, resource: ResourceType, order_type: OrderType) -> Vec<MarketOrder> {
+        let filter = LodashFilter::new();
+        filter.resource_type(MarketResourceType::Resource(resource));
+
+        game::market::get_all_orders(Some(&filter)).into_iter()
+            .filter(|order| order.order_type() == order_type)
+            .filter_map(|order| Some(MarketOrder {
+                id: order.id().into(),
+                order_type,
+                resource,
+                price: order.price(),
+                amount: order.remaining_amount(),
+                room: RoomName::new(&String::from(order.room_name()?)).ok()?
+            })).collect()
+    }
+
+    fn history(&self, resource: ResourceType) -> Vec<PriceRecord> {
+        game::market::get_history(Some(resource)).into_iter()
+            .map(|record| PriceRecord { avg_price: record.avg_price(), stddev_price: record.stddev_price(), volume: record.volume() })
+            .collect()
+    }
+
+    fn transaction_cost(&self, amount: u32, from: RoomName, to: RoomName) -> u32 {
+        calc_transaction_cost(amount, from, to)
+    }
+
+    fn deal(&mut self, order: &MarketOrder, amount: u32, room: RoomName) -> anyhow::Result<()> {
+        game::market::deal(&JsString::from(order.id.as_str()), amount, Some(room)).map_err(|err| anyhow!("Unable to deal on order {}: {err}", order.id))
+    }
+}
+
+// Energy paid for sending an amount between two rooms, by the market as well as between terminals
+pub fn calc_transaction_cost(amount: u32, from: RoomName, to: RoomName) -> u32 {
+    game::market::calc_transaction_cost(amount, &JsString::from(from.to_string()), &JsString::from(to.to_string()))
+}
+
+// Energy each transfer costs per unit of resource, in place of the distance based cost of the game
+#[cfg(test)]
+#[derive(Default, Clone, Copy)]
+pub struct FlatTransactionCost(pub f64);
+
+#[cfg(test)]
+impl FlatTransactionCost {
+    pub fn of(self, amount: u32) -> u32 {
+        (f64::from(amount) * self.0).ceil() as u32
+    }
+}
+
+// Order book kept in memory which fills deals straight away, for testing the trader
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeOrderBook {
+    pub credits: f64,
+    pub cpu_used: f64,
+    pub orders: Vec<MarketOrder>,
+    pub history: Vec<(ResourceType, PriceRecord)>,
+    pub transfer_cost: FlatTransactionCost,
+    pub deals: Vec<(String, u32, RoomName)>
+}
+
+#[cfg(test)]
+impl OrderBook for FakeOrderBook {
+    fn credits(&self) -> f64 {
+        self.credits
+    }
+
+    fn cpu_used(&self) -> f64 {
+        self.cpu_used
+    }
+
+    fn orders(&self, resource: ResourceType, order_type: OrderType) -> Vec<MarketOrder> {
+        self.orders.iter()
+            .filter(|order| order.resource == resource && order.order_type == order_type && order.amount > 0)
+            .cloned()
+            .collect()
+    }
+
+    fn history(&self, resource: ResourceType) -> Vec<PriceRecord> {
+        self.history.iter().filter(|(of_type, _)| *of_type == resource).map(|(_, record)| *record).collect()
+    }
+
+    fn transaction_cost(&self, amount: u32, _from: RoomName, _to: RoomName) -> u32 {
+        self.transfer_cost.of(amount)
+    }
+
+    fn deal(&mut self, order: &MarketOrder, amount: u32, room: RoomName) -> anyhow::Result<()> {
+        let listed = self.orders.iter_mut().find(|listed| listed.id == order.id).ok_or_else(|| anyhow!("No order {}", order.id))?;
+        if listed.amount < amount { return Err(anyhow!("Order {} only has {} left", order.id, listed.amount)) }
+
+        listed.amount -= amount;
+        let credits = f64::from(amount) * order.price;
+        self.credits += if order.order_type == OrderType::Sell { -credits } else { credits };
+        self.deals.push((order.id.clone(), amount, room));
+        Ok(())
+    }
+}
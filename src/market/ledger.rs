@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use enum_display::EnumDisplay;
+use log::info;
+use screeps::{ResourceType, RoomName};
+use serde::{Deserialize, Serialize};
+
+const LEDGER_SIZE: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, EnumDisplay)]
+pub enum TradeKind {
+    Bought,
+    Sold
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Trade {
+    pub time: u32,
+    pub room: RoomName,
+    pub kind: TradeKind,
+    pub resource: ResourceType,
+    pub amount: u32,
+    pub price: f64,
+    pub energy_cost: u32
+}
+
+impl Trade {
+    // Positive when credits were earned
+    pub fn credits(&self) -> f64 {
+        let total = f64::from(self.amount) * self.price;
+        match self.kind {
+            TradeKind::Bought => -total,
+            TradeKind::Sold => total
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Ledger(VecDeque<Trade>);
+
+impl Ledger {
+    pub fn record(&mut self, trade: Trade) {
+        info!("{} {} {} for {:.3} credits each in {}", trade.kind, trade.amount, trade.resource, trade.price, trade.room);
+
+        self.0.push_front(trade);
+        self.0.truncate(LEDGER_SIZE);
+    }
+
+    pub fn balance(&self) -> f64 {
+        self.0.iter().map(Trade::credits).sum()
+    }
+
+    pub fn energy_spent(&self) -> u32 {
+        self.0.iter().map(|trade| trade.energy_cost).sum()
+    }
+
+    pub fn print(&self, count: usize) {
+        if self.0.is_empty() {
+            info!("No trades have been made");
+            return;
+        }
+
+        for trade in self.0.iter().take(count) {
+            info!(
+                "[{}] {} {} {} {} at {:.3} ({:+.0} credits, {} energy)",
+                trade.time, trade.room, trade.kind, trade.amount, trade.resource, trade.price, trade.credits(), trade.energy_cost
+            );
+        }
+
+        info!("Balance over the last {} trades: {:+.0} credits, {} energy spent on transfers", self.0.len(), self.balance(), self.energy_spent());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{ResourceType, RoomName};
+
+    use super::*;
+
+    fn trade(kind: TradeKind, amount: u32, price: f64) -> Trade {
+        Trade { time: 0, room: RoomName::new("W1N1").unwrap(), kind, resource: ResourceType::Hydrogen, amount, price, energy_cost: 10 }
+    }
+
+    #[test]
+    fn bought_trades_cost_credits() {
+        assert!((trade(TradeKind::Bought, 100, 2.0).credits() + 200.0).abs() < f64::EPSILON);
+        assert!((trade(TradeKind::Sold, 100, 2.0).credits() - 200.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn balance_sums_trades() {
+        let mut ledger = Ledger::default();
+        ledger.record(trade(TradeKind::Sold, 300, 1.0));
+        ledger.record(trade(TradeKind::Bought, 100, 0.5));
+
+        assert!((ledger.balance() - 250.0).abs() < f64::EPSILON);
+        assert_eq!(ledger.energy_spent(), 20);
+    }
+
+    #[test]
+    fn keeps_latest_trades() {
+        let mut ledger = Ledger::default();
+        for time in 0..LEDGER_SIZE as u32 + 10 {
+            ledger.record(Trade { time, ..trade(TradeKind::Sold, 1, 1.0) });
+        }
+
+        assert_eq!(ledger.0.len(), LEDGER_SIZE);
+        assert_eq!(ledger.0.front().map(|trade| trade.time), Some(LEDGER_SIZE as u32 + 9));
+    }
+}
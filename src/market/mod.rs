@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use log::warn;
+use screeps::{OrderType, ResourceType, RoomName, StructureTerminal, game};
+use serde::{Deserialize, Serialize};
+
+use crate::{colony::ColonyView, commands::{Command, handle_commands}, domain_traits::{EnergyStoreAccessors, HasStoreExt}, labs::{LabCoordinator, stored_amount}, market::{book::{GameOrderBook, MarketOrder, OrderBook, PriceRecord}, ledger::{Ledger, Trade, TradeKind}}, memory::Memory};
+
+pub mod book;
+pub mod ledger;
+
+const TRADE_INTERVAL: u32 = 10;
+const CREDITS_PER_TICK: f64 = 50.0;
+const MIN_CREDITS: f64 = 10_000.0;
+const CPU_BUDGET: f64 = 5.0;
+
+// Prices within this many standard deviations of the recent average are accepted
+const PRICE_TOLERANCE: f64 = 0.5;
+
+const MINERAL_SURPLUS: u32 = 30_000;
+const ENERGY_SURPLUS: u32 = 600_000;
+const DEFAULT_LEDGER_PRINT: usize = 20;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct MarketMemory {
+    pub ledger: Ledger
+}
+
+pub struct TradeBudget {
+    credits: f64,
+    cpu_limit: f64,
+    // Price history only changes once a day, so it is looked up once per resource and tick
+    bounds: HashMap<ResourceType, Option<PriceBounds>>
+}
+
+impl TradeBudget {
+    pub fn new(book: &impl OrderBook) -> Self {
+        Self {
+            credits: (CREDITS_PER_TICK * f64::from(TRADE_INTERVAL)).min(book.credits() - MIN_CREDITS).max(0.0),
+            cpu_limit: book.cpu_used() + CPU_BUDGET,
+            bounds: HashMap::new()
+        }
+    }
+
+    fn is_exhausted(&self, book: &impl OrderBook) -> bool {
+        book.cpu_used() >= self.cpu_limit
+    }
+
+    fn price_bounds(&mut self, resource: ResourceType, book: &impl OrderBook) -> Option<PriceBounds> {
+        *self.bounds.entry(resource).or_insert_with(|| PriceBounds::from_history(&book.history(resource)))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PriceBounds {
+    pub floor: f64,
+    pub ceiling: f64
+}
+
+impl PriceBounds {
+    // Volume weighted over the recent order history
+    pub fn from_history(history: &[PriceRecord]) -> Option<Self> {
+        let volume = history.iter().map(|record| f64::from(record.volume)).sum::<f64>();
+        if volume <= 0.0 { return None }
+
+        let avg = history.iter().map(|record| record.avg_price * f64::from(record.volume)).sum::<f64>() / volume;
+        let stddev = history.iter().map(|record| record.stddev_price * f64::from(record.volume)).sum::<f64>() / volume;
+
+        Some(Self {
+            floor: (avg - PRICE_TOLERANCE * stddev).max(0.0),
+            ceiling: avg + PRICE_TOLERANCE * stddev
+        })
+    }
+}
+
+// Terminal side of a trade, so that the trader doesn't need the game objects
+pub struct TradingRoom {
+    pub name: RoomName,
+    pub energy: u32,
+    pub stock: HashMap<ResourceType, u32>,
+    pub wanted: HashMap<ResourceType, u32>
+}
+
+pub fn buy(room: &TradingRoom, resource: ResourceType, amount: u32, book: &mut impl OrderBook, budget: &mut TradeBudget, time: u32) -> Option<Trade> {
+    let bounds = budget.price_bounds(resource, book)?;
+    let order = book.orders(resource, OrderType::Sell).into_iter()
+        .filter(|order| order.price <= bounds.ceiling)
+        .min_by(|a, b| a.price.total_cmp(&b.price))?;
+
+    let affordable = (budget.credits / order.price) as u32;
+    let amount = amount.min(order.amount).min(affordable);
+    deal(room, &order, amount, book, time).inspect(|trade| budget.credits += trade.credits())
+}
+
+pub fn sell(room: &TradingRoom, resource: ResourceType, amount: u32, book: &mut impl OrderBook, budget: &mut TradeBudget, time: u32) -> Option<Trade> {
+    let bounds = budget.price_bounds(resource, book)?;
+    let order = book.orders(resource, OrderType::Buy).into_iter()
+        .filter(|order| order.price >= bounds.floor)
+        .max_by(|a, b| a.price.total_cmp(&b.price))?;
+
+    // Energy sold has to leave enough behind to pay for the transfer
+    let amount = if resource == ResourceType::Energy { amount.min(room.energy / 2) } else { amount };
+    deal(room, &order, amount.min(order.amount), book, time)
+}
+
+fn deal(room: &TradingRoom, order: &MarketOrder, amount: u32, book: &mut impl OrderBook, time: u32) -> Option<Trade> {
+    if amount == 0 { return None }
+
+    let energy_cost = book.transaction_cost(amount, room.name, order.room);
+    let energy_used = energy_cost + if order.resource == ResourceType::Energy && order.order_type == OrderType::Buy { amount } else { 0 };
+    if energy_used > room.energy { return None }
+
+    book.deal(order, amount, room.name).inspect_err(|err| warn!("{err}")).ok()?;
+
+    Some(Trade {
+        time,
+        room: room.name,
+        kind: if order.order_type == OrderType::Sell { TradeKind::Bought } else { TradeKind::Sold },
+        resource: order.resource,
+        amount,
+        price: order.price,
+        energy_cost
+    })
+}
+
+// A terminal can only make one deal per tick, so missing reaction inputs are bought before surplus is sold
+pub fn trade(room: &TradingRoom, book: &mut impl OrderBook, budget: &mut TradeBudget, time: u32) -> Option<Trade> {
+    let purchases = room.wanted.iter().sorted_by_key(|(resource, _)| resource.to_string());
+    for (resource, amount) in purchases {
+        if budget.is_exhausted(book) { return None }
+        if let Some(trade) = buy(room, *resource, *amount, book, budget, time) { return Some(trade) }
+    }
+
+    let sales = room.stock.iter()
+        .filter(|(resource, _)| !room.wanted.contains_key(resource))
+        .sorted_by_key(|(resource, _)| resource.to_string());
+    for (resource, amount) in sales {
+        if budget.is_exhausted(book) { return None }
+        if let Some(trade) = sell(room, *resource, *amount, book, budget, time) { return Some(trade) }
+    }
+
+    None
+}
+
+fn trading_room(colony: &ColonyView<'_>, terminal: &StructureTerminal, labs: Option<&LabCoordinator>) -> TradingRoom {
+    let surplus = |resource: ResourceType| {
+        let (stored, kept) = if resource == ResourceType::Energy {
+            (colony.buffer.map_or(0, |buffer| buffer.used_energy_capacity()), ENERGY_SURPLUS)
+        } else {
+            (stored_amount(colony, resource), MINERAL_SURPLUS)
+        };
+
+        stored.saturating_sub(kept).min(terminal.used_capacity(Some(resource)))
+    };
+
+    TradingRoom {
+        name: colony.name,
+        energy: terminal.used_energy_capacity(),
+        stock: terminal.store().store_types().into_iter()
+            .filter(|resource| labs.is_none_or(|labs| !labs.is_stockpiled(*resource)))
+            .map(|resource| (resource, surplus(resource)))
+            .filter(|(_, amount)| *amount > 0)
+            .collect(),
+        wanted: labs.map(|labs| labs.missing_minerals(colony)).unwrap_or_default()
+    }
+}
+
+pub fn do_market(mem: &mut Memory) {
+    handle_commands(|command| {
+        let Command::PrintLedger { count } = command else { return false };
+        mem.market.ledger.print(count.unwrap_or(DEFAULT_LEDGER_PRINT));
+        true
+    });
+
+    if !game::time().is_multiple_of(TRADE_INTERVAL) { return }
+
+    let mut book = GameOrderBook;
+    let mut budget = TradeBudget::new(&book);
+
+    for colony in mem.colonies.view_all() {
+        if budget.is_exhausted(&book) { break; }
+
+        let Some(terminal) = colony.plan.center.terminal.resolve().filter(|terminal| terminal.cooldown() == 0) else { continue; };
+        let room = trading_room(&colony, &terminal, mem.lab_coordinators.get(&colony.name));
+
+        if let Some(trade) = trade(&room, &mut book, &mut budget, game::time()) {
+            mem.market.ledger.record(trade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{OrderType, ResourceType, RoomName};
+
+    use super::*;
+    use crate::market::book::{FakeOrderBook, FlatTransactionCost};
+
+    const TIME: u32 = 1234;
+
+    fn order(id: &str, order_type: OrderType, resource: ResourceType, price: f64, amount: u32) -> MarketOrder {
+        MarketOrder { id: id.to_string(), order_type, resource, price, amount, room: RoomName::new("W5N5").unwrap() }
+    }
+
+    // Prices between 0.9 and 1.1 are accepted for hydrogen and oxygen
+    fn book(orders: Vec<MarketOrder>) -> FakeOrderBook {
+        let record = PriceRecord { avg_price: 1.0, stddev_price: 0.2, volume: 1000 };
+        FakeOrderBook {
+            credits: 1_000_000.0,
+            orders,
+            history: vec![(ResourceType::Hydrogen, record), (ResourceType::Oxygen, record)],
+            ..FakeOrderBook::default()
+        }
+    }
+
+    fn room(stock: &[(ResourceType, u32)], wanted: &[(ResourceType, u32)]) -> TradingRoom {
+        TradingRoom {
+            name: RoomName::new("W1N1").unwrap(),
+            energy: 10_000,
+            stock: stock.iter().copied().collect(),
+            wanted: wanted.iter().copied().collect()
+        }
+    }
+
+    #[test]
+    fn budget_is_capped_per_interval() {
+        let budget = TradeBudget::new(&book(Vec::new()));
+        assert!((budget.credits - CREDITS_PER_TICK * f64::from(TRADE_INTERVAL)).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn budget_keeps_credit_reserve() {
+        let budget = TradeBudget::new(&FakeOrderBook { credits: MIN_CREDITS + 100.0, ..book(Vec::new()) });
+        assert!((budget.credits - 100.0).abs() < f64::EPSILON);
+
+        let budget = TradeBudget::new(&FakeOrderBook { credits: MIN_CREDITS / 2.0, ..book(Vec::new()) });
+        assert!(budget.credits.abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn budget_is_exhausted_by_cpu() {
+        let mut book = FakeOrderBook { cpu_used: 10.0, ..book(Vec::new()) };
+        let budget = TradeBudget::new(&book);
+        assert!(!budget.is_exhausted(&book));
+
+        book.cpu_used += CPU_BUDGET;
+        assert!(budget.is_exhausted(&book));
+    }
+
+    #[test]
+    fn trading_stops_once_cpu_is_exhausted() {
+        let mut book = book(vec![order("a", OrderType::Sell, ResourceType::Hydrogen, 1.0, 1000)]);
+        let mut budget = TradeBudget::new(&book);
+        book.cpu_used += CPU_BUDGET;
+
+        assert!(trade(&room(&[], &[(ResourceType::Hydrogen, 100)]), &mut book, &mut budget, TIME).is_none());
+        assert!(book.deals.is_empty());
+    }
+
+    #[test]
+    fn price_history_is_looked_up_once() {
+        let mut book = book(vec![order("a", OrderType::Sell, ResourceType::Hydrogen, 1.0, 1000)]);
+        let mut budget = TradeBudget::new(&book);
+
+        assert!(trade(&room(&[], &[(ResourceType::Hydrogen, 100)]), &mut book, &mut budget, TIME).is_some());
+        book.history.clear();
+        assert!(trade(&room(&[], &[(ResourceType::Hydrogen, 100)]), &mut book, &mut budget, TIME).is_some());
+    }
+
+    #[test]
+    fn purchases_stop_at_budget() {
+        let mut book = book(vec![order("a", OrderType::Sell, ResourceType::Hydrogen, 1.0, 10_000)]);
+        let mut budget = TradeBudget::new(&book);
+
+        let trade = trade(&room(&[], &[(ResourceType::Hydrogen, 2000)]), &mut book, &mut budget, TIME).unwrap();
+        assert_eq!(trade.kind, TradeKind::Bought);
+        assert_eq!(trade.amount, 500);
+        assert_eq!(trade.time, TIME);
+        assert!(budget.credits.abs() < f64::EPSILON);
+
+        assert!(super::trade(&room(&[], &[(ResourceType::Hydrogen, 2000)]), &mut book, &mut budget, TIME).is_none());
+    }
+
+    #[test]
+    fn overpriced_orders_are_ignored() {
+        let mut book = book(vec![
+            order("a", OrderType::Sell, ResourceType::Hydrogen, 2.0, 1000),
+            order("b", OrderType::Buy, ResourceType::Oxygen, 0.5, 1000)
+        ]);
+        let mut budget = TradeBudget::new(&book);
+
+        let room = room(&[(ResourceType::Oxygen, 1000)], &[(ResourceType::Hydrogen, 100)]);
+        assert!(trade(&room, &mut book, &mut budget, TIME).is_none());
+        assert!(book.deals.is_empty());
+    }
+
+    #[test]
+    fn missing_minerals_are_bought_before_surplus_is_sold() {
+        let mut book = book(vec![
+            order("a", OrderType::Sell, ResourceType::Hydrogen, 1.0, 1000),
+            order("b", OrderType::Buy, ResourceType::Oxygen, 1.0, 1000)
+        ]);
+        let mut budget = TradeBudget::new(&book);
+
+        let trade = trade(&room(&[(ResourceType::Oxygen, 1000)], &[(ResourceType::Hydrogen, 100)]), &mut book, &mut budget, TIME).unwrap();
+        assert_eq!((trade.kind, trade.resource, trade.amount), (TradeKind::Bought, ResourceType::Hydrogen, 100));
+    }
+
+    #[test]
+    fn surplus_is_sold_to_the_best_order() {
+        let mut book = book(vec![
+            order("a", OrderType::Buy, ResourceType::Oxygen, 0.95, 1000),
+            order("b", OrderType::Buy, ResourceType::Oxygen, 1.05, 300)
+        ]);
+        let mut budget = TradeBudget::new(&book);
+
+        let trade = trade(&room(&[(ResourceType::Oxygen, 1000)], &[]), &mut book, &mut budget, TIME).unwrap();
+        assert_eq!((trade.kind, trade.resource, trade.amount), (TradeKind::Sold, ResourceType::Oxygen, 300));
+        assert_eq!(book.deals, vec![("b".to_string(), 300, RoomName::new("W1N1").unwrap())]);
+    }
+
+    #[test]
+    fn wanted_minerals_are_not_sold() {
+        let mut book = book(vec![order("a", OrderType::Buy, ResourceType::Hydrogen, 1.0, 1000)]);
+        let mut budget = TradeBudget::new(&book);
+
+        let room = room(&[(ResourceType::Hydrogen, 1000)], &[(ResourceType::Hydrogen, 100)]);
+        assert!(trade(&room, &mut book, &mut budget, TIME).is_none());
+    }
+
+    #[test]
+    fn trades_need_energy_for_the_transfer() {
+        let mut book = FakeOrderBook { transfer_cost: FlatTransactionCost(1.0), ..book(vec![order("a", OrderType::Buy, ResourceType::Oxygen, 1.0, 1000)]) };
+        let mut budget = TradeBudget::new(&book);
+
+        let room = TradingRoom { energy: 500, ..room(&[(ResourceType::Oxygen, 1000)], &[]) };
+        assert!(trade(&room, &mut book, &mut budget, TIME).is_none());
+    }
+}
@@ -6,7 +6,7 @@ use screeps::RoomName;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{callbacks::Callbacks, check::filter_check_any_key_map, colony::Colonies, commands::{Command, pop_command}, creeps::{CreepData, fabricator::FabricatorCoordinator, flagship::FlagshipCoordinator, truck::TruckCoordinator}, domain_traits::CreepId, movement::MovementMemory};
+use crate::{callbacks::Callbacks, check::filter_check_any_key_map, colony::{Colonies, PlannerStrategy, remote::Remotes, steps::ColonyStep}, commands::{Command, pop_command}, creeps::{CreepData, fabricator::FabricatorCoordinator, flagship::FlagshipCoordinator, truck::TruckCoordinator}, domain_traits::CreepId, labs::LabCoordinator, market::MarketMemory, movement::MovementMemory, spawn::queue::SpawnQueue, threat::Threat};
 
 extern crate serde_json_path_to_error as serde_json;
 
@@ -24,12 +24,27 @@ pub struct Memory {
     #[serde(with = "filter_check_any_key_map")]
     pub creeps: HashMap<CreepId, CreepData>,
     pub colonies: Colonies,
+    // Remotes of reset colonies, handed to the colony once it is planned again
+    #[serde(default)]
+    pub detached_remotes: HashMap<RoomName, Remotes>,
+    #[serde(default)]
+    pub planner_strategies: HashMap<RoomName, PlannerStrategy>,
+    #[serde(default)]
+    pub perimeter_steps: HashMap<RoomName, ColonyStep>,
 
     pub callbacks: Callbacks,
     pub flagship_coordinator: FlagshipCoordinator,
     pub truck_coordinators: HashMap<RoomName, TruckCoordinator>,
     pub fabricator_coordinators: HashMap<RoomName, FabricatorCoordinator>,
-    pub movement: MovementMemory
+    pub movement: MovementMemory,
+    #[serde(default)]
+    pub spawn_queues: HashMap<RoomName, SpawnQueue>,
+    #[serde(default)]
+    pub lab_coordinators: HashMap<RoomName, LabCoordinator>,
+    #[serde(default)]
+    pub market: MarketMemory,
+    #[serde(default)]
+    pub threats: HashMap<RoomName, Threat>
 }
 
 impl Memory {
@@ -2,11 +2,12 @@ use std::{cell::RefCell, collections::{HashMap, HashSet, VecDeque}, hash::Hash,
 
 use screeps::{Creep, Direction, HasPosition, Position, SharedCreepProperties, Spawning, StructureSpawn};
 use serde::{Deserialize, Serialize};
-use crate::{check::{TriviallyChecked, filter_check_any_key_map}, commands::{Command, pop_command}, domain_traits::{CreepId, HasId, ObjectId, ResolvableId}};
+use crate::{check::{TriviallyChecked, filter_check_any_key_map}, commands::{Command, pop_command}, domain_traits::{CreepId, HasId, ObjectId, ResolvableId}, movement::traffic::TrafficHeatmap};
 
 pub mod requests;
 mod simplifier;
 mod solver;
+pub mod traffic;
 
 thread_local! {
     static SELECTED: RefCell<HashSet<screeps::ObjectId<Creep>>> = RefCell::new(HashSet::new());
@@ -30,7 +31,10 @@ pub struct MovementMemory {
     paths: HashMap<CreepId, CachedPath>,
 
     #[serde(with = "filter_check_any_key_map")]
-    pub spawning_directions: HashMap<CreepId, Vec<Direction>>
+    pub spawning_directions: HashMap<CreepId, Vec<Direction>>,
+
+    #[serde(default)]
+    pub traffic: TrafficHeatmap
 }
 
 #[derive(Serialize, Deserialize)]
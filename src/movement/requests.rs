@@ -103,6 +103,7 @@ impl MovementRequests {
         let tugboat_requests = self.handle_unpaired_tuggeds();
 
         MovementSolver::solve(self.collect_creeps().simplify(), mem);
+        mem.traffic.decay();
 
         tugboat_requests
     }
@@ -320,9 +320,12 @@ impl<'m> MovementSolver<'m> {
         for (creep, action) in self.creep_actions {
             match action {
                 CreepAction::Move { dir } => {
-                    creep.resolve().move_direction(dir).unwrap();
+                    let creep = creep.resolve();
+                    self.mem.traffic.record(creep.pos() + dir);
+                    creep.move_direction(dir).unwrap();
                 },
                 CreepAction::Pulled { next } => {
+                    self.mem.traffic.record(next.pos());
                     creep.resolve().move_pulled_by(&next).unwrap();
                     next.pull(&creep.resolve()).unwrap();
                 },
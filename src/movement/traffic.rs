@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use screeps::{Position, RectStyle, RoomName, RoomVisual, RoomXY, game};
+use serde::{Deserialize, Serialize};
+use serde_json_any_key::any_key_map;
+
+const DECAY_INTERVAL: u32 = 1500;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct TrafficHeatmap {
+    rooms: HashMap<RoomName, RoomTraffic>,
+    last_decay: u32
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RoomTraffic(#[serde(with = "any_key_map")] HashMap<RoomXY, u32>);
+
+impl TrafficHeatmap {
+    pub fn record(&mut self, pos: Position) {
+        *self.rooms.entry(pos.room_name()).or_default().0.entry(pos.xy()).or_default() += 1;
+    }
+
+    pub fn decay(&mut self) {
+        if game::time() < self.last_decay + DECAY_INTERVAL { return; }
+        self.last_decay = game::time();
+
+        for traffic in self.rooms.values_mut() {
+            traffic.0.values_mut().for_each(|count| *count /= 2);
+            traffic.0.retain(|_, count| *count > 0);
+        }
+
+        self.rooms.retain(|_, traffic| !traffic.0.is_empty());
+    }
+
+    pub fn room(&self, room: RoomName) -> Option<&RoomTraffic> {
+        self.rooms.get(&room)
+    }
+}
+
+impl RoomTraffic {
+    pub fn get(&self, xy: RoomXY) -> u32 {
+        self.0.get(&xy).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.0.values().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RoomXY, u32)> {
+        self.0.iter().map(|(xy, count)| (*xy, *count))
+    }
+
+    pub fn draw(&self, visuals: &RoomVisual) {
+        let Some(max) = self.0.values().max().copied() else { return };
+
+        for (xy, count) in self.iter() {
+            let heat = count as f32 / max as f32;
+            let style = RectStyle::default().fill("#ff6a00").opacity(0.1 + 0.6 * heat);
+            visuals.rect(f32::from(xy.x.u8()) - 0.5, f32::from(xy.y.u8()) - 0.5, 1.0, 1.0, Some(style));
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use std::cmp::Reverse;
+
 use itertools::Itertools;
-use screeps::{Structure, StructureExtension, StructureSpawn};
+use screeps::{HasPosition, Position, Source, Structure, StructureExtension, StructureSpawn};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -11,6 +13,15 @@ pub enum EnergyStructure<S: CheckState = Checked> {
     Extension(ObjectId<StructureExtension, S>)
 }
 
+impl EnergyStructure {
+    fn pos(&self) -> Position {
+        match self {
+            EnergyStructure::Spawn(id) => id.resolve().pos(),
+            EnergyStructure::Extension(id) => id.resolve().pos(),
+        }
+    }
+}
+
 impl HasStore for EnergyStructure {
     fn store(&self) -> screeps::Store {
         match self {
@@ -83,12 +94,14 @@ impl EnergyPool {
 
 pub struct EnergyGroup {
     structures_left: Vec<EnergyStructure>,
-    energy: EnergyPool
+    energy: EnergyPool,
+    reserved_for: Option<ObjectId<Source>>
 }
 
 impl EnergyGroup {
-    pub fn new(structures: Vec<EnergyStructure>, refilled: bool) -> Self {
+    pub fn new(structures: Vec<EnergyStructure>, refilled: bool, reserved_for: Option<ObjectId<Source>>) -> Self {
         Self {
+            reserved_for,
             energy: EnergyPool::new(
                 structures.iter().map(EnergyStoreAccessors::used_energy_capacity).sum::<u32>(),
                 EnergyPoolType::refilled_if(
@@ -118,6 +131,10 @@ impl EnergyGroup {
         structures
     }
 
+    fn is_usable_by(&self, source: Option<ObjectId<Source>>) -> bool {
+        self.reserved_for.is_none_or(|reserved| Some(reserved) == source)
+    }
+
     pub fn reserve_future(&mut self, amount: u32) {
         assert!(self.energy.future_energy() >= amount);
 
@@ -127,26 +144,40 @@ impl EnergyGroup {
     }
 }
 
+// Any spawn can be funded by any group, except that source groups only fund the excavators of their source
 pub struct ColonyEnergy(Vec<EnergyGroup>);
 
 impl ColonyEnergy {
-    pub fn new(groups: Vec<EnergyGroup>) -> Self {
+    pub fn new(mut groups: Vec<EnergyGroup>, refill_route: &[Position]) -> Self {
+        // Structures close to where trucks are already going are drained first, so refilling them is cheap
+        for group in &mut groups {
+            group.structures_left.sort_by_cached_key(|structure| {
+                Reverse(refill_route.iter().map(|pos| pos.get_range_to(structure.pos())).min().unwrap_or(0))
+            });
+        }
+
         Self(groups)
     }
 
-    pub fn energy(&self) -> u32 {
-        self.0.iter().map(|group| group.energy.current).sum()
+    fn usable_groups(&mut self, source: Option<ObjectId<Source>>) -> impl Iterator<Item = &mut EnergyGroup> {
+        self.0.iter_mut()
+            .filter(move |group| group.is_usable_by(source))
+            .sorted_by_key(|group| !group.energy.is_refilled())
     }
 
-    pub fn future_energy(&self) -> u32 {
-        self.0.iter().map(|group| group.energy.future_energy()).sum()
+    pub fn energy(&self, source: Option<ObjectId<Source>>) -> u32 {
+        self.0.iter().filter(|group| group.is_usable_by(source)).map(|group| group.energy.current).sum()
     }
 
-    pub fn allocate(&mut self, mut amount: u32) -> Vec<EnergyStructure> {
-        assert!(self.energy() >= amount);
+    pub fn future_energy(&self, source: Option<ObjectId<Source>>) -> u32 {
+        self.0.iter().filter(|group| group.is_usable_by(source)).map(|group| group.energy.future_energy()).sum()
+    }
+
+    pub fn allocate(&mut self, mut amount: u32, source: Option<ObjectId<Source>>) -> Vec<EnergyStructure> {
+        assert!(self.energy(source) >= amount);
 
         let mut extensions = Vec::new();
-        for group in self.0.iter_mut().sorted_by_key(|group| !group.energy.is_refilled()) {
+        for group in self.usable_groups(source) {
             if amount == 0 { break; }
 
             let group_amount = amount.min(group.energy.current);
@@ -158,10 +189,10 @@ impl ColonyEnergy {
         extensions
     }
 
-    pub fn reserve_future(&mut self, mut amount: u32) {
-        assert!(self.future_energy() >= amount);
+    pub fn reserve_future(&mut self, mut amount: u32, source: Option<ObjectId<Source>>) {
+        assert!(self.future_energy(source) >= amount);
 
-        for group in self.0.iter_mut().sorted_by_key(|group| !group.energy.is_refilled()) {
+        for group in self.usable_groups(source) {
             if amount == 0 { return }
 
             let group_amount = amount.min(group.energy.future_energy());
@@ -170,4 +201,4 @@ impl ColonyEnergy {
             group.reserve_future(group_amount);
         }
     }
-}
+}
\ No newline at end of file
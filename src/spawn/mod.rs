@@ -1,23 +1,29 @@
 pub mod prototype;
+pub mod queue;
 mod energy;
 mod roles;
 mod roster;
 mod policies;
 
-use crate::{memory::Memory, movement::requests::TugboatRequests};
-use policies::{schedule_excavators, schedule_fabricators, schedule_flagships, schedule_import_trucks, schedule_remote_fabricators, schedule_tugboats, schedule_trucks};
+use crate::{memory::Memory, movement::requests::TugboatRequests, spawn::queue::handle_queue_commands};
+use policies::{schedule_defenders, schedule_excavators, schedule_fabricators, schedule_flagships, schedule_import_trucks, schedule_mineral_excavators, schedule_remote_excavators, schedule_remote_fabricators, schedule_reservers, schedule_tugboats, schedule_trucks};
 use roster::Rosters;
 
 #[expect(clippy::needless_pass_by_value)]
 pub fn do_spawns(mem: &mut Memory, tugboat_requests: TugboatRequests) {
+    handle_queue_commands(mem);
     let mut rosters = Rosters::new(mem);
 
     for (colony, roster) in rosters.iter_mut() {
         let view = mem.colonies.view(*colony).unwrap();
 
+        schedule_defenders(roster, &view, &mem.threats);
         schedule_excavators(roster, &view);
         schedule_tugboats(roster, &tugboat_requests);
         schedule_trucks(roster, &view);
+        schedule_reservers(roster, &view);
+        schedule_remote_excavators(roster, &view);
+        schedule_mineral_excavators(roster, &view);
         schedule_fabricators(roster, &view);
     }
 
@@ -25,5 +31,6 @@ pub fn do_spawns(mem: &mut Memory, tugboat_requests: TugboatRequests) {
     schedule_flagships(&mut rosters, mem);
     schedule_import_trucks(&mut rosters, mem);
 
-    rosters.gather_new_creeps(mem);
+    rosters.spawn_queued();
+    rosters.store(mem);
 }
@@ -1,10 +1,52 @@
-use std::sync::LazyLock;
+use std::{collections::HashMap, iter, sync::LazyLock};
 
 use itertools::Itertools;
 use log::warn;
-use screeps::{Creep, HasPosition, Part};
+use screeps::{Creep, HasPosition, Part, ResourceType, RoomName};
+
+use crate::{colony::{ColonyView, plan::SourcePlan, remote::REMOTE_MINING_STEP, steps::ColonyStep}, creeps::{CreepRole, defender::DefenderCreep, excavator::ExcavatorCreep, fabricator::FabricatorCreep, flagship::FlagshipCreep, mineral_excavator::MineralExcavatorCreep, reserver::ReserverCreep, truck::{ImportTruckState, TruckCreep}}, domain_traits::{EnergyStoreAccessors, HasId, HasName}, labs::boosts::boosted_if_available, logging::LogResultErr, memory::Memory, movement::requests::TugboatRequests, spawn::{prototype::{Body, BodyOptimizer, Prototype, RelativePrototype, TerrainMix}, queue::{RequestInfo, SpawnPreference}, roles::RoleSelector, roster::{ColonyRoster, Rosters}}, terminals::{exports_energy_to, is_exporting}, threat::{Threat, defense_strength}};
+
+const DEFENDER_PRIORITY: i32 = 110;
+const EXCAVATOR_PRIORITY: i32 = 100;
+const TUGBOAT_PRIORITY: i32 = 90;
+const TRUCK_PRIORITY: i32 = 80;
+const RESERVER_PRIORITY: i32 = 70;
+const REMOTE_EXCAVATOR_PRIORITY: i32 = 60;
+const MINERAL_EXCAVATOR_PRIORITY: i32 = 50;
+const FABRICATOR_PRIORITY: i32 = 40;
+const REMOTE_FABRICATOR_PRIORITY: i32 = 30;
+const FLAGSHIP_PRIORITY: i32 = 20;
+const IMPORT_TRUCK_PRIORITY: i32 = 10;
+
+// Threats which need more defenders than this aren't worth feeding more creeps to
+const MAX_DEFENDERS: usize = 4;
+static MELEE_DEFENDER_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| BodyOptimizer::new(Body::of_part(Part::Attack, 1)).full_speed());
+static RANGED_DEFENDER_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| { use Part::*; BodyOptimizer::new(Body::from(vec![RangedAttack, Heal])).full_speed() });
+pub fn schedule_defenders(roster: &mut ColonyRoster, view: &ColonyView<'_>, threats: &HashMap<RoomName, Threat>) {
+    // Defenders still queued for rooms which have been cleared would hold back the economy
+    roster.cancel_requests(|request| request.role.defended_room().is_some_and(|room| !threats.contains_key(&room)));
+
+    for room in iter::once(view.name).chain(view.remotes.active().map(|remote| remote.room)) {
+        let Some(threat) = threats.get(&room) else { continue; };
+        let needed = threat.defense_needed();
+
+        loop {
+            let defenders = roster.local_creeps().of_role(RoleSelector::DefenderFor(room)).count();
+            let strength = roster.local_creeps().of_role(RoleSelector::DefenderFor(room)).map(|proto| defense_strength(proto.body())).sum::<u32>();
+            if strength >= needed || defenders >= MAX_DEFENDERS { break; }
+
+            let requested = roster.request(RequestInfo::new(DEFENDER_PRIORITY, "defenders", format!("{strength}/{needed} defense in {room}")), |info| {
+                Some(if threat.is_melee() {
+                    RelativePrototype::new(RANGED_DEFENDER_BODY.optimize(info.future_energy)?, CreepRole::RangedDefender(DefenderCreep::default(), room))
+                } else {
+                    RelativePrototype::new(MELEE_DEFENDER_BODY.optimize(info.future_energy)?, CreepRole::MeleeDefender(DefenderCreep::default(), room))
+                })
+            }).log_err();
 
-use crate::{colony::{ColonyView, plan::SourcePlan, steps::ColonyStep}, creeps::{CreepRole, excavator::ExcavatorCreep, fabricator::FabricatorCreep, flagship::FlagshipCreep, truck::{ImportTruckState, TruckCreep, STOP_IMPORT_STEP}}, domain_traits::{EnergyStoreAccessors, HasId, HasName}, logging::LogResultErr, memory::Memory, movement::requests::TugboatRequests, spawn::{prototype::{Body, Prototype, RelativePrototype}, roles::RoleSelector, roster::{ColonyRoster, Rosters}}};
+            if requested.is_none() { break; }
+        }
+    }
+}
 
 fn get_excavator_body(energy: u32, source_plan: &SourcePlan) -> Body {
     let target_excavator_works = if source_plan.get_construction_site().is_some() { 7 } else { 5 };
@@ -15,22 +57,14 @@ fn get_excavator_body(energy: u32, source_plan: &SourcePlan) -> Body {
 pub fn schedule_excavators(roster: &mut ColonyRoster, view: &ColonyView<'_>) {
     for (source, source_plan) in &view.plan.sources {
         let Some(source) = source.resolve() else { continue; };
-        if !roster.has_free() { continue; }
         if roster.local_creeps().of_role(RoleSelector::SourceExcavator(source.id())).next().is_some() { continue; }
 
-        let has_source_spawn = source_plan.spawn.resolve().is_some();
-
-        roster.schedule_selected(
-            |mut iter| {
-                if has_source_spawn {
-                    iter.find(|(_, spawn)| spawn.is_source_spawn(&source.id())).map(|(ix, _)| ix)
-                } else {
-                    ColonyRoster::default_select(iter)
-                }
-            },
+        let reserved_energy = roster.reserved_future_energy(source.id());
+        roster.request(
+            RequestInfo::new(EXCAVATOR_PRIORITY, "excavators", "Source has no excavator").prefer(SpawnPreference::Source(source.id())),
             |info| {
                 Some(RelativePrototype::new(
-                    get_excavator_body(info.future_energy, source_plan),
+                    get_excavator_body(info.future_energy + reserved_energy, source_plan),
                     CreepRole::Excavator(ExcavatorCreep::default(), source.id())
                 ))
             }
@@ -38,6 +72,65 @@ pub fn schedule_excavators(roster: &mut ColonyRoster, view: &ColonyView<'_>) {
     }
 }
 
+static REMOTE_EXCAVATOR_BODY: LazyLock<Body> = LazyLock::new(|| Body::of_part(Part::Carry, 1) + Body::of_part(Part::Work, 5) + Body::of_part(Part::Move, 3));
+pub fn schedule_remote_excavators(roster: &mut ColonyRoster, view: &ColonyView<'_>) {
+    if roster.syndrome().any_problems() { return }
+
+    for source in view.remotes.active().flat_map(|remote| remote.sources.keys()) {
+        if roster.local_creeps().of_role(RoleSelector::RemoteExcavator(*source)).next().is_some() { continue; }
+
+        roster.request(RequestInfo::new(REMOTE_EXCAVATOR_PRIORITY, "remote excavators", "Remote source has no excavator"), |info| {
+            (info.future_energy >= REMOTE_EXCAVATOR_BODY.energy_required()).then(|| RelativePrototype::new(
+                REMOTE_EXCAVATOR_BODY.clone(),
+                CreepRole::RemoteExcavator(ExcavatorCreep::default(), *source)
+            ))
+        }).log_err();
+    }
+}
+
+const MAX_MINERAL_EXCAVATOR_WORKS: usize = 20;
+pub fn schedule_mineral_excavators(roster: &mut ColonyRoster, view: &ColonyView<'_>) {
+    if roster.syndrome().any_problems() { return }
+    if roster.local_creeps().of_role(RoleSelector::MineralExcavator).next().is_some() { return }
+
+    let plan = &view.plan.mineral;
+    if !plan.extractor.is_complete() || !plan.container.is_complete() { return }
+    if plan.resolve_mineral().is_none_or(|mineral| mineral.mineral_amount() == 0) { return }
+
+    roster.request(RequestInfo::new(MINERAL_EXCAVATOR_PRIORITY, "mineral excavators", "Mineral has no excavator"), |info| {
+        let works = (info.future_energy / Part::Work.cost()) as usize;
+        Some(RelativePrototype::new(
+            Body::of_part(Part::Work, works.min(MAX_MINERAL_EXCAVATOR_WORKS)),
+            CreepRole::MineralExcavator(MineralExcavatorCreep::default())
+        ))
+    }).log_err();
+}
+
+static RESERVER_TEMPLATE: LazyLock<Body> = LazyLock::new(|| { use Part::*; Body::from(vec![Claim, Move]) });
+static SCOUT_BODY: LazyLock<Body> = LazyLock::new(|| Body::of_part(Part::Move, 1));
+pub fn schedule_reservers(roster: &mut ColonyRoster, view: &ColonyView<'_>) {
+    if roster.syndrome().any_problems() || !view.progress.has_reached(REMOTE_MINING_STEP) { return }
+
+    for remote in view.remotes.active() {
+        if roster.local_creeps().part_count(RoleSelector::ReserverFor(remote.room), Part::Claim) > 0 { continue; }
+
+        roster.request(RequestInfo::new(RESERVER_PRIORITY, "reservers", format!("Reserving {}", remote.room)), |info| {
+            Some(RelativePrototype::new(
+                RESERVER_TEMPLATE.scaled(info.future_energy.min((RESERVER_TEMPLATE.clone() * 2).energy_required()), None)?,
+                CreepRole::Reserver(ReserverCreep::default(), remote.room)
+            ))
+        }).log_err();
+    }
+
+    for room in view.remotes.unscouted(view.name) {
+        if roster.local_creeps().of_role(RoleSelector::ReserverFor(room)).next().is_some() { continue; }
+
+        roster.request(RequestInfo::new(RESERVER_PRIORITY, "reservers", format!("Scouting {room}")), |_| {
+            Some(RelativePrototype::new(SCOUT_BODY.clone(), CreepRole::Reserver(ReserverCreep::default(), room)))
+        }).log_err();
+    }
+}
+
 // Truck capacity C = 50y energy
 // Roundtrip time T = 2x ticks
 // Production P = 10 energy per tick
@@ -56,15 +149,18 @@ const TRUCK_FABRICATOR_CARRY: f32 = 10.0; // TODO: Fix this properly
 
 const TRUCK_CARRY_MARGIN: f32 = 0.25;
 
-static TRUCK_TEMPLATE: LazyLock<Body> = LazyLock::new(|| { use Part::*; Body::from(vec![Move, Carry, Carry]) });
-static MAX_TRUCK_ENERGY: LazyLock<u32> = LazyLock::new(||  (TRUCK_TEMPLATE.clone() * 10).energy_required());
-fn get_truck_body(energy: u32) -> Option<Body> {
-    TRUCK_TEMPLATE.scaled(energy.min(*MAX_TRUCK_ENERGY), Some(2))
-}
+const MAX_TRUCK_CARRY: usize = 20;
+static TRUCK_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| {
+    BodyOptimizer::new(Body::of_part(Part::Carry, 1))
+        .on(TerrainMix::ROADS)
+        .full_speed()
+        .max_units(MAX_TRUCK_CARRY)
+});
 
 pub fn schedule_trucks(roster: &mut ColonyRoster, colony: &ColonyView<'_>) {
     let total_carry_for_sources = colony.plan.sources.values()
         .filter(|source_plan| !source_plan.link.is_complete() && source_plan.container.is_complete())
+        .chain(colony.remotes.active().flat_map(|remote| remote.sources.values()).filter(|source_plan| source_plan.container.is_complete()))
         .map(|source_plan| source_plan.distance as f32 * TRUCK_SOURCE_CARRY_PER_DIST)
         .sum::<f32>();
 
@@ -74,31 +170,37 @@ pub fn schedule_trucks(roster: &mut ColonyRoster, colony: &ColonyView<'_>) {
         ((1.0 + TRUCK_CARRY_MARGIN) * (total_carry_for_sources + TRUCK_CENTER_CARRY + TRUCK_FABRICATOR_CARRY)).ceil() as usize
     };
 
-    while roster.has_free() {
-        if roster.local_creeps().part_count(RoleSelector::Truck, Part::Carry) >= target_carry { break; }
+    loop {
+        let carry = roster.local_creeps().part_count(RoleSelector::Truck, Part::Carry);
+        if carry >= target_carry { break; }
 
-        roster.schedule(|info| {
+        let requested = roster.request(RequestInfo::new(TRUCK_PRIORITY, "trucks", format!("{carry}/{target_carry} carry")), |info| {
             Some(RelativePrototype::new(
-                get_truck_body(info.future_energy)?,
+                TRUCK_BODY.optimize(info.future_energy)?,
                 CreepRole::Truck(TruckCreep::default())
             ))
         }).log_err();
+
+        if requested.is_none() { break; }
     }
 }
 
-static IMPORT_TRUCK_TEMPLATE: LazyLock<Body> = LazyLock::new(|| { use Part::*; Body::from(vec![Move, Carry]) });
+static IMPORT_TRUCK_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| BodyOptimizer::new(Body::of_part(Part::Carry, 1)).full_speed());
 pub fn schedule_import_trucks(rosters: &mut Rosters, mem: &mut Memory) {
-    for colony in mem.colonies.view_all() {
-        if colony.step >= STOP_IMPORT_STEP { continue; }
+    let colonies = mem.colonies.view_all().collect_vec();
+    for colony in &colonies {
+        // Colonies import until they export themselves, or until an exporter supplies them through the terminals
+        if is_exporting(colony) { continue; }
+        if colonies.iter().any(|exporter| exports_energy_to(exporter, colony)) { continue; }
 
         let roster = rosters.get(colony.name).unwrap();
         if roster.local_creeps().part_count(RoleSelector::ImportTruck, Part::Carry) > 100 {
             continue;
         }
 
-        rosters.schedule(|info| {
+        rosters.request(RequestInfo::new(IMPORT_TRUCK_PRIORITY, "import trucks", format!("Importing energy to {}", colony.name)), |info| {
             Some(Prototype::absolute(
-                IMPORT_TRUCK_TEMPLATE.scaled(info.future_energy, None)?,
+                IMPORT_TRUCK_BODY.optimize(info.future_energy)?,
                 CreepRole::ImportTruck(ImportTruckState::default()),
                 colony.name
             ))
@@ -113,7 +215,7 @@ pub fn schedule_flagships(rosters: &mut Rosters, mem: &mut Memory) {
 
     if rosters.global_creeps().of_role(RoleSelector::Flagship).count() > 0 { return; }
 
-    rosters.schedule(|_| {
+    rosters.request(RequestInfo::new(FLAGSHIP_PRIORITY, "flagships", "Rooms to claim"), |_| {
         Some(Prototype::relative(
             FLAGSHIP_TEMPLATE.clone(),
             CreepRole::Flagship(FlagshipCreep::default())
@@ -145,18 +247,15 @@ pub fn schedule_tugboats(roster: &mut ColonyRoster, tugboat_requests: &TugboatRe
         });
 
     for tugged in tugged {
-        if !roster.has_free() { continue; }
         if roster.local_creeps().of_role(RoleSelector::TugboatFor(tugged.id())).next().is_some() { continue; }
+        let Some(spawn) = roster.nearest_spawn(tugged.pos()).map(HasId::id) else { continue; };
 
-        roster.schedule_selected(
-            |iter| {
-                iter.min_by_key(|(_, spawn)| spawn.spawn.pos().get_range_to(tugged.pos()))
-                    .map(|(ix, _)| ix)
-            },
+        roster.request(
+            RequestInfo::new(TUGBOAT_PRIORITY, "tugboats", format!("Tugging {}", tugged.name())).prefer(SpawnPreference::Near(tugged.pos())),
             |info| {
                 Some(RelativePrototype::new(
                     get_tugboat_body(info.future_energy, &tugged),
-                    CreepRole::Tugboat(tugged.id(), info.spawn.id())
+                    CreepRole::Tugboat(tugged.id(), spawn)
                 ))
             }
         ).log_err();
@@ -166,35 +265,43 @@ pub fn schedule_tugboats(roster: &mut ColonyRoster, tugboat_requests: &TugboatRe
 const TARGET_IDLE_FABRICATOR_WORK_COUNT: usize = 20;
 const TARGET_SURPLUS_FABRICATOR_WORK_COUNT: usize = 40;
 const BUFFER_ENERGY_SURPLUS_THRESHOLD: u32 = 50_000;
-static FABRICATOR_TEMPLATE: LazyLock<Body> = LazyLock::new(|| { use Part::*; Body::from(vec![Carry, Carry, Move, Work, Carry]) });
+const FABRICATOR_BOOST: ResourceType = ResourceType::CatalyzedGhodiumAcid;
+static FABRICATOR_UNIT: LazyLock<Body> = LazyLock::new(|| Body::of_part(Part::Work, 1) + Body::of_part(Part::Carry, 3));
+static FABRICATOR_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| BodyOptimizer::new(FABRICATOR_UNIT.clone()).on(TerrainMix::ROADS).loaded(0.5).travelling(0.25));
+static REMOTE_FABRICATOR_BODY: LazyLock<BodyOptimizer> = LazyLock::new(|| BodyOptimizer::new(FABRICATOR_UNIT.clone()).loaded(0.5).travelling(0.5));
 pub fn schedule_fabricators(roster: &mut ColonyRoster, colony: &ColonyView<'_>) {
     if roster.syndrome().any_problems() { return }
 
     let buffer_energy = colony.buffer.map_or(0, |buffer| buffer.used_energy_capacity());
     let work_target = if buffer_energy >= BUFFER_ENERGY_SURPLUS_THRESHOLD { TARGET_SURPLUS_FABRICATOR_WORK_COUNT } else { TARGET_IDLE_FABRICATOR_WORK_COUNT };
 
-    while roster.has_free() {
-        if roster.local_creeps().part_count(RoleSelector::Fabricator, Part::Work) >= work_target { break; }
+    loop {
+        let works = roster.local_creeps().part_count(RoleSelector::Fabricator, Part::Work);
+        if works >= work_target { break; }
 
-        roster.schedule(|info| {
-            Some(RelativePrototype::new(
-                FABRICATOR_TEMPLATE.scaled(info.future_energy, None)?,
+        let requested = roster.request(RequestInfo::new(FABRICATOR_PRIORITY, "fabricators", format!("{works}/{work_target} work")), |info| {
+            let proto = RelativePrototype::new(
+                FABRICATOR_BODY.optimize(info.future_energy)?,
                 CreepRole::Fabricator(FabricatorCreep::default())
-            ))
+            );
+
+            Some(boosted_if_available(proto, colony, FABRICATOR_BOOST))
         }).log_err();
+
+        if requested.is_none() { break; }
     }
 }
 
 pub fn schedule_remote_fabricators(rosters: &mut Rosters, mem: &mut Memory) {
     for colony in mem.colonies.view_all() {
-        if !matches!(colony.step, ColonyStep::BuildSpawn) { continue; }
+        if colony.progress.is_complete(ColonyStep::BuildSpawn) { continue; }
 
         let roster = rosters.get(colony.name).unwrap();
         if roster.local_creeps().of_role(RoleSelector::Fabricator).next().is_some() { continue; }
 
-        rosters.schedule(|info| {
+        rosters.request(RequestInfo::new(REMOTE_FABRICATOR_PRIORITY, "remote fabricators", format!("Building spawn in {}", colony.name)), |info| {
             Some(Prototype::absolute(
-                FABRICATOR_TEMPLATE.scaled(info.future_energy, None)?,
+                REMOTE_FABRICATOR_BODY.optimize(info.future_energy)?,
                 CreepRole::Fabricator(FabricatorCreep::default()),
                 colony.name
             ))
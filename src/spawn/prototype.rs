@@ -1,10 +1,18 @@
-use std::{iter, ops::{Add, Mul}};
+use std::{fmt::Display, iter, ops::{Add, Mul}, str::FromStr};
 
-use screeps::{Creep, MAX_CREEP_SIZE, Part, RoomName};
+use anyhow::{anyhow, bail};
+use itertools::Itertools;
+use screeps::{Creep, MAX_CREEP_SIZE, Part, ResourceType, RoomName};
+use serde::{Deserialize, Serialize};
 
 use crate::{creeps::{CreepData, CreepRole}, domain_traits::{CreepId, ResolvableId}};
 
-#[derive(Clone)]
+const PART_CODES: [(Part, char); 8] = [
+    (Part::Move, 'M'), (Part::Work, 'W'), (Part::Carry, 'C'), (Part::Attack, 'A'),
+    (Part::RangedAttack, 'R'), (Part::Heal, 'H'), (Part::Claim, 'K'), (Part::Tough, 'T')
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Body(Vec<Part>);
 
 impl Body {
@@ -59,6 +67,44 @@ impl Body {
     }
 }
 
+// Written as counted part codes, e.g. 10W5C5M
+impl Display for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (part, parts) in &self.0.iter().chunk_by(|part| **part) {
+            let code = PART_CODES.iter().find(|(part2, _)| *part2 == part).map_or('?', |(_, code)| *code);
+            write!(f, "{}{code}", parts.count())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Body {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut digits = String::new();
+
+        for c in s.trim().chars() {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                continue;
+            }
+
+            let part = PART_CODES.iter().find(|(_, code)| *code == c.to_ascii_uppercase()).map(|(part, _)| *part).ok_or(anyhow!("Unknown part {c}"))?;
+            let count = if digits.is_empty() { 1 } else { digits.parse()? };
+            parts.extend(iter::repeat_n(part, count));
+            digits.clear();
+        }
+
+        if !digits.is_empty() { bail!("Missing part after {digits}") }
+        if parts.is_empty() || parts.len() > MAX_CREEP_SIZE as usize { bail!("Invalid body size {}", parts.len()) }
+
+        Ok(Body(parts))
+    }
+}
+
 impl Mul<usize> for Body {
     type Output = Self;
 
@@ -81,24 +127,147 @@ impl From<Vec<Part>> for Body {
     }
 }
 
+// Fraction of a path spent on each kind of terrain
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainMix {
+    pub road: f32,
+    pub plain: f32,
+    pub swamp: f32
+}
+
+impl TerrainMix {
+    pub const ROADS: TerrainMix = TerrainMix { road: 1.0, plain: 0.0, swamp: 0.0 };
+    pub const PLAINS: TerrainMix = TerrainMix { road: 0.0, plain: 1.0, swamp: 0.0 };
+
+    // Average fatigue generated per tile by each weighted part
+    fn fatigue(&self) -> f32 {
+        let total = self.road + self.plain + self.swamp;
+        if total <= 0.0 { return 0.0 }
+
+        (self.road + 2.0 * self.plain + 10.0 * self.swamp) / total
+    }
+}
+
+// Candidates at least this efficient compared to the best are considered equal, and the biggest one is picked
+const BODY_EFFICIENCY_TOLERANCE: f32 = 0.95;
+
+// Chooses the number of work units and move parts with the best throughput per energy
+// A unit is one copy of the non-move parts of the role's work profile
+#[derive(Clone, Debug)]
+pub struct BodyOptimizer {
+    unit: Body,
+    terrain: TerrainMix,
+    load: f32,
+    travel: f32,
+    max_ticks_per_tile: Option<f32>,
+    max_units: Option<usize>
+}
+
+impl BodyOptimizer {
+    pub fn new(unit: Body) -> Self {
+        assert!(unit.part_count(Part::Move) == 0, "Move parts are chosen by the optimizer");
+
+        Self {
+            unit,
+            terrain: TerrainMix::PLAINS,
+            load: 1.0,
+            travel: 1.0,
+            max_ticks_per_tile: None,
+            max_units: None
+        }
+    }
+
+    pub fn on(mut self, terrain: TerrainMix) -> Self {
+        self.terrain = terrain;
+        self
+    }
+
+    // Fraction of the carry parts which are filled while moving
+    pub fn loaded(mut self, load: f32) -> Self {
+        self.load = load.clamp(0.0, 1.0);
+        self
+    }
+
+    // Fraction of the creep's time spent moving rather than working in place
+    pub fn travelling(mut self, travel: f32) -> Self {
+        self.travel = travel.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn full_speed(mut self) -> Self {
+        self.max_ticks_per_tile = Some(1.0);
+        self
+    }
+
+    pub fn max_units(mut self, max_units: usize) -> Self {
+        self.max_units = Some(max_units);
+        self
+    }
+
+    fn ticks_per_tile(&self, units: usize, moves: usize) -> f32 {
+        let carry = self.unit.part_count(Part::Carry) as f32;
+        let other = (self.unit.total_parts() - self.unit.part_count(Part::Carry)) as f32;
+        let fatigue = units as f32 * (other + self.load * carry) * self.terrain.fatigue();
+
+        (fatigue / (2.0 * moves as f32)).max(1.0)
+    }
+
+    fn throughput(&self, units: usize, moves: usize) -> f32 {
+        units as f32 / (1.0 - self.travel + self.travel * self.ticks_per_tile(units, moves))
+    }
+
+    pub fn optimize(&self, energy: u32) -> Option<Body> {
+        let unit_cost = self.unit.energy_required();
+        let unit_size = self.unit.total_parts();
+        let max_units = self.max_units.unwrap_or(MAX_CREEP_SIZE as usize);
+
+        let candidates = (1..=max_units)
+            .take_while(|units| units * unit_size < MAX_CREEP_SIZE as usize && *units as u32 * unit_cost + Part::Move.cost() <= energy)
+            .flat_map(|units| (1..=MAX_CREEP_SIZE as usize - units * unit_size).map(move |moves| (units, moves)))
+            .filter(|(units, moves)| *units as u32 * unit_cost + *moves as u32 * Part::Move.cost() <= energy)
+            .filter(|(units, moves)| self.max_ticks_per_tile.is_none_or(|max| self.ticks_per_tile(*units, *moves) <= max))
+            .map(|(units, moves)| {
+                let throughput = self.throughput(units, moves);
+                let cost = units as u32 * unit_cost + moves as u32 * Part::Move.cost();
+                (units, moves, throughput, throughput / cost as f32)
+            })
+            .collect_vec();
+
+        let best_efficiency = candidates.iter().map(|(_, _, _, efficiency)| *efficiency).max_by(f32::total_cmp)?;
+        let (units, moves, _, _) = candidates.into_iter()
+            .filter(|(_, _, _, efficiency)| *efficiency >= BODY_EFFICIENCY_TOLERANCE * best_efficiency)
+            .max_by(|a, b| a.2.total_cmp(&b.2).then(b.1.cmp(&a.1)))?;
+
+        let parts = self.unit.0.iter().copied().sorted_by_key(|part| self.unit.0.iter().position(|other| other == part)).collect_vec();
+        Some(Body::from(parts) * units + Body::of_part(Part::Move, moves))
+    }
+}
+
 #[derive(Clone)]
 pub struct RelativePrototype {
     body: Body,
     role: CreepRole,
+    boosts: Vec<ResourceType>
 }
 
 impl RelativePrototype {
     pub fn new(body: Body, role: CreepRole) -> Self {
-        Self { body, role }
+        Self { body, role, boosts: Vec::new() }
     }
 
     pub fn from_creep(id: &CreepId, data: &CreepData) -> Self {
         Self {
             body: Body(id.resolve().body().into_iter().map(|part| part.part()).collect()),
             role: data.role.clone(),
+            boosts: data.boosts.clone()
         }
     }
 
+    pub fn boosted(mut self, compound: ResourceType) -> Self {
+        self.boosts.push(compound);
+        self
+    }
+
     pub fn with_home(self, home: RoomName) -> AbsolutePrototype {
         AbsolutePrototype { proto: self, home }
     }
@@ -120,7 +289,7 @@ pub struct AbsolutePrototype {
 
 impl AbsolutePrototype {
     pub fn new(body: Body, role: CreepRole, home: RoomName) -> Self {
-        Self { proto: RelativePrototype { body, role }, home }
+        Self { proto: RelativePrototype::new(body, role), home }
     }
 
     pub fn from_creep(id: &CreepId, data: &CreepData) -> Self {
@@ -138,6 +307,10 @@ impl AbsolutePrototype {
         &self.proto.role
     }
 
+    pub fn boosts(&self) -> &[ResourceType] {
+        &self.proto.boosts
+    }
+
     pub fn home(&self) -> RoomName {
         self.home
     }
@@ -168,3 +341,61 @@ impl Prototype {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(body: &str) -> Body {
+        body.parse().unwrap()
+    }
+
+    #[test]
+    fn keeps_unit_ratio() {
+        let optimized = BodyOptimizer::new(body("2W1C")).optimize(2000).unwrap();
+
+        assert!(optimized.part_count(Part::Carry) > 1);
+        assert_eq!(optimized.part_count(Part::Work), 2 * optimized.part_count(Part::Carry));
+    }
+
+    #[test]
+    fn full_speed_needs_a_move_per_fatigue() {
+        let on_plains = BodyOptimizer::new(body("1C")).full_speed().optimize(1500).unwrap();
+        assert_eq!(on_plains.part_count(Part::Move), on_plains.part_count(Part::Carry));
+
+        let on_roads = BodyOptimizer::new(body("1C")).on(TerrainMix::ROADS).full_speed().optimize(1500).unwrap();
+        assert_eq!(on_roads.part_count(Part::Move), on_roads.part_count(Part::Carry).div_ceil(2));
+        assert!(on_roads.part_count(Part::Carry) > on_plains.part_count(Part::Carry));
+    }
+
+    #[test]
+    fn empty_carry_parts_cause_no_fatigue() {
+        let optimized = BodyOptimizer::new(body("1C")).loaded(0.0).full_speed().optimize(1000).unwrap();
+        assert_eq!(optimized.part_count(Part::Move), 1);
+    }
+
+    #[test]
+    fn stationary_creeps_get_one_move() {
+        let optimized = BodyOptimizer::new(body("1W")).travelling(0.0).optimize(1000).unwrap();
+        assert_eq!(optimized.part_count(Part::Move), 1);
+    }
+
+    #[test]
+    fn stays_within_energy_and_size() {
+        let worker = BodyOptimizer::new(body("1W1C")).on(TerrainMix::ROADS);
+
+        for energy in (200..=12_900).step_by(350) {
+            let optimized = worker.optimize(energy).unwrap();
+            assert!(optimized.energy_required() <= energy, "{optimized} costs more than {energy}");
+            assert!(optimized.total_parts() <= MAX_CREEP_SIZE as usize, "{optimized} has too many parts");
+        }
+
+        assert!(worker.optimize(150).is_none());
+    }
+
+    #[test]
+    fn respects_max_units() {
+        let optimized = BodyOptimizer::new(body("1A")).max_units(3).optimize(5000).unwrap();
+        assert_eq!(optimized.part_count(Part::Attack), 3);
+    }
+}
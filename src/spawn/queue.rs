@@ -0,0 +1,273 @@
+use std::{cell::RefCell, cmp::Reverse, collections::HashSet};
+
+use anyhow::{anyhow, bail};
+use clap::ValueEnum;
+use derive_where::derive_where;
+use itertools::Itertools;
+use log::{info, warn};
+use screeps::{Position, ResourceType, RoomName, Source, game};
+use serde::{Deserialize, Serialize};
+
+use crate::{check::{Check, CheckFrom, deserialize_filter_check}, commands::{Command, handle_commands}, creeps::{CreepRole, fabricator::FabricatorCreep, flagship::FlagshipCreep, mineral_excavator::MineralExcavatorCreep, truck::{ImportTruckState, TruckCreep}}, domain_traits::ObjectId, ids::{CheckState, Checked, Unchecked}, memory::Memory, spawn::prototype::{AbsolutePrototype, Body, RelativePrototype}};
+
+const REQUEST_LIFETIME: u32 = 100;
+const MANUAL_REQUEST_LIFETIME: u32 = 1500;
+const MANUAL_PRIORITY: i32 = 100;
+const MANUAL_REQUESTER: &str = "manual";
+
+thread_local! {
+    static VISUALIZED: RefCell<HashSet<RoomName>> = RefCell::new(HashSet::new());
+}
+
+pub fn is_visualized(room: RoomName) -> bool {
+    VISUALIZED.with_borrow(|visualized| visualized.contains(&room))
+}
+
+#[derive(Debug)]
+#[derive_where(Serialize, Deserialize, Clone, Copy; ObjectId<Source, S>)]
+pub enum SpawnPreference<S: CheckState = Checked> {
+    Central,
+    Source(ObjectId<Source, S>),
+    Near(Position)
+}
+
+impl CheckFrom for SpawnPreference {
+    type Unchecked = SpawnPreference<Unchecked>;
+    type Err = anyhow::Error;
+
+    fn check_from(us: Self::Unchecked) -> anyhow::Result<Self> {
+        Ok(match us {
+            SpawnPreference::Central => Self::Central,
+            SpawnPreference::Source(source) => Self::Source(source.check()?),
+            SpawnPreference::Near(pos) => Self::Near(pos)
+        })
+    }
+}
+
+#[derive(Debug)]
+#[derive_where(Serialize, Deserialize, Clone; CreepRole<S>, SpawnPreference<S>)]
+pub struct SpawnRequest<S: CheckState = Checked> {
+    pub id: u32,
+    pub body: Body,
+    #[serde(default)]
+    pub boosts: Vec<ResourceType>,
+    pub role: CreepRole<S>,
+    pub home: RoomName,
+    pub preference: SpawnPreference<S>,
+    pub priority: i32,
+    pub requester: String,
+    pub deadline: u32,
+    pub reason: String
+}
+
+impl CheckFrom for SpawnRequest {
+    type Unchecked = SpawnRequest<Unchecked>;
+    type Err = anyhow::Error;
+
+    fn check_from(us: Self::Unchecked) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: us.id,
+            body: us.body,
+            boosts: us.boosts,
+            role: us.role.check()?,
+            home: us.home,
+            preference: us.preference.check()?,
+            priority: us.priority,
+            requester: us.requester,
+            deadline: us.deadline,
+            reason: us.reason
+        })
+    }
+}
+
+impl SpawnRequest {
+    pub fn proto(&self) -> AbsolutePrototype {
+        self.boosts.iter()
+            .fold(RelativePrototype::new(self.body.clone(), self.role.clone()), |proto, compound| proto.boosted(*compound))
+            .with_home(self.home)
+    }
+}
+
+pub struct RequestInfo {
+    priority: i32,
+    requester: &'static str,
+    reason: String,
+    preference: SpawnPreference,
+    deadline: u32
+}
+
+impl RequestInfo {
+    pub fn new(priority: i32, requester: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            priority,
+            requester,
+            reason: reason.into(),
+            preference: SpawnPreference::Central,
+            deadline: game::time() + REQUEST_LIFETIME
+        }
+    }
+
+    pub fn prefer(mut self, preference: SpawnPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    pub fn into_request(self, proto: &AbsolutePrototype) -> SpawnRequest {
+        SpawnRequest {
+            id: 0,
+            body: proto.body().clone(),
+            boosts: proto.boosts().to_vec(),
+            role: proto.role().clone(),
+            home: proto.home(),
+            preference: self.preference,
+            priority: self.priority,
+            requester: self.requester.to_string(),
+            deadline: self.deadline,
+            reason: self.reason
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SpawnQueue {
+    #[serde(deserialize_with = "deserialize_filter_check")]
+    requests: Vec<SpawnRequest>,
+    next_id: u32
+}
+
+impl SpawnQueue {
+    pub fn push(&mut self, mut request: SpawnRequest) -> u32 {
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+
+        request.id = id;
+        self.requests.push(request);
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<SpawnRequest> {
+        let index = self.requests.iter().position(|request| request.id == id)?;
+        Some(self.requests.remove(index))
+    }
+
+    // Expired requests are dropped, so that their requesters can ask again with up to date bodies
+    pub fn drop_expired(&mut self, room: RoomName) {
+        self.requests.retain(|request| {
+            let expired = request.deadline < game::time();
+            if expired && request.requester == MANUAL_REQUESTER {
+                warn!("Manual request {} for a {} in {room} expired before it could be spawned", request.id, request.role.prefix());
+            }
+
+            !expired
+        });
+    }
+
+    pub fn retain(&mut self, keep: impl FnMut(&SpawnRequest) -> bool) {
+        self.requests.retain(keep);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SpawnRequest> {
+        self.requests.iter()
+    }
+
+    pub fn by_priority(&self) -> Vec<SpawnRequest> {
+        self.requests.iter()
+            .sorted_by_key(|request| (Reverse(request.priority), request.deadline, request.id))
+            .cloned()
+            .collect()
+    }
+
+    fn bump(&mut self, id: u32) -> anyhow::Result<()> {
+        let highest = self.requests.iter().map(|request| request.priority).max().unwrap_or_default();
+        let request = self.requests.iter_mut().find(|request| request.id == id).ok_or(anyhow!("No spawn request {id}"))?;
+
+        request.priority = highest + 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum)]
+pub enum ManualRole {
+    Truck,
+    Fabricator,
+    MineralExcavator,
+    ImportTruck,
+    Flagship
+}
+
+impl ManualRole {
+    fn role(self) -> CreepRole {
+        match self {
+            ManualRole::Truck => CreepRole::Truck(TruckCreep::default()),
+            ManualRole::Fabricator => CreepRole::Fabricator(FabricatorCreep::default()),
+            ManualRole::MineralExcavator => CreepRole::MineralExcavator(MineralExcavatorCreep::default()),
+            ManualRole::ImportTruck => CreepRole::ImportTruck(ImportTruckState::default()),
+            ManualRole::Flagship => CreepRole::Flagship(FlagshipCreep::default()),
+        }
+    }
+}
+
+pub fn handle_queue_commands(mem: &mut Memory) {
+    handle_commands(|command| {
+        let Command::ListSpawnQueue { room } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+        let Some(queue) = mem.spawn_queues.get(&room).filter(|queue| !queue.requests.is_empty()) else {
+            info!("Spawn queue of {room} is empty");
+            return true;
+        };
+
+        for request in queue.by_priority() {
+            info!(
+                "#{} [{}] {} {} for {} by {}, due in {} ticks: {}",
+                request.id, request.priority, request.role.prefix(), request.body, request.home,
+                request.requester, request.deadline.saturating_sub(game::time()), request.reason
+            );
+        }
+
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::BumpSpawnRequest { room, id } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+
+        let result = mem.spawn_queues.get_mut(&room).ok_or(anyhow!("{room} has no spawn queue")).and_then(|queue| queue.bump(*id));
+        if let Err(err) = result { warn!("Unable to bump spawn request: {err}") }
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::RequestSpawn { room, role, body, priority } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+
+        match request_manual_spawn(mem, room, *role, body, *priority) {
+            Ok(id) => info!("Queued manual spawn request {id} in {room}"),
+            Err(err) => warn!("Unable to request spawn: {err}")
+        }
+
+        true
+    });
+
+    handle_commands(|command| {
+        let Command::VisualizeSpawnQueue { room } = command else { return false };
+        let Ok(room) = RoomName::new(room) else { return true };
+
+        VISUALIZED.with_borrow_mut(|visualized| {
+            if !visualized.remove(&room) { visualized.insert(room); }
+        });
+
+        true
+    });
+}
+
+fn request_manual_spawn(mem: &mut Memory, room: RoomName, role: ManualRole, body: &str, priority: Option<i32>) -> anyhow::Result<u32> {
+    if mem.colonies.view(room).is_none() { bail!("{room} is not a colony") }
+
+    let body: Body = body.parse()?;
+    let proto = AbsolutePrototype::new(body, role.role(), room);
+
+    let mut request = RequestInfo::new(priority.unwrap_or(MANUAL_PRIORITY), MANUAL_REQUESTER, "Requested from the console").into_request(&proto);
+    request.deadline = game::time() + MANUAL_REQUEST_LIFETIME;
+
+    Ok(mem.spawn_queues.entry(room).or_default().push(request))
+}
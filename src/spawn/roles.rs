@@ -1,16 +1,20 @@
-use screeps::{Part, Source};
+use screeps::{Part, RoomName, Source};
 
 use crate::{creeps::CreepRole, domain_traits::{CreepId, ObjectId}, spawn::{prototype::{AbsolutePrototype, RelativePrototype}, roster::{ColonyCreeps, GlobalCreeps}}};
 
 pub enum RoleSelector {
     #[expect(unused)] Excavator,
     SourceExcavator(ObjectId<Source>),
+    RemoteExcavator(screeps::ObjectId<Source>),
+    MineralExcavator,
+    ReserverFor(RoomName),
     Truck,
     ImportTruck,
     Flagship,
     #[expect(unused)] Tugboat,
     TugboatFor(CreepId),
-    Fabricator
+    Fabricator,
+    DefenderFor(RoomName)
 }
 
 impl RoleSelector {
@@ -18,19 +22,23 @@ impl RoleSelector {
         match self {
             RoleSelector::Excavator => matches!(role, CreepRole::Excavator(_, _)),
             RoleSelector::SourceExcavator(source) => matches!(role, CreepRole::Excavator(_, source2) if *source2 == *source),
+            RoleSelector::RemoteExcavator(source) => matches!(role, CreepRole::RemoteExcavator(_, source2) if *source2 == *source),
+            RoleSelector::MineralExcavator => matches!(role, CreepRole::MineralExcavator(_)),
+            RoleSelector::ReserverFor(room) => matches!(role, CreepRole::Reserver(_, room2) if *room2 == *room),
             RoleSelector::Truck => matches!(role, CreepRole::Truck(_)),
             RoleSelector::ImportTruck => matches!(role, CreepRole::ImportTruck(_)),
             RoleSelector::Flagship => matches!(role, CreepRole::Flagship(_)),
             RoleSelector::Tugboat => matches!(role, CreepRole::Tugboat(_, _)),
             RoleSelector::TugboatFor(tugged) => matches!(role, CreepRole::Tugboat(tugged2, _) if *tugged2 == *tugged),
             RoleSelector::Fabricator => matches!(role, CreepRole::Fabricator(_)),
+            RoleSelector::DefenderFor(room) => role.defended_room() == Some(*room),
         }
     }
 }
 
 impl ColonyCreeps {
     pub fn of_role(&self, role: RoleSelector) -> impl Iterator<Item = &RelativePrototype> {
-        self.values().filter(move |proto| role.matches(proto.role()))
+        self.active().filter(move |proto| role.matches(proto.role()))
     }
 
     pub fn part_count(&self, role: RoleSelector, part: Part) -> usize {
@@ -40,7 +48,7 @@ impl ColonyCreeps {
 
 impl GlobalCreeps {
     pub fn of_role(&self, role: RoleSelector) -> impl Iterator<Item = &AbsolutePrototype> {
-        self.values().filter(move |proto| role.matches(proto.role()))
+        self.all().filter(move |proto| role.matches(proto.role()))
     }
 
     #[expect(unused)]
@@ -1,11 +1,11 @@
-use std::{cell::RefCell, collections::{HashMap, hash_map}, rc::Rc};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, iter, ops::Deref, rc::Rc};
 
-use derive_deref::Deref;
 use itertools::Itertools;
-use screeps::{Creep, Direction, HasPosition, RoomName, Source, SpawnOptions, Structure, StructureSpawn, action_error_codes::SpawnCreepErrorCode, game};
+use log::warn;
+use screeps::{CREEP_SPAWN_TIME, Creep, Direction, HasPosition, Position, RoomName, RoomVisual, Source, SpawnOptions, Structure, StructureSpawn, TextAlign, TextStyle, action_error_codes::SpawnCreepErrorCode, game};
 use thiserror::Error;
 
-use crate::{colony::ColonyView, creeps::{CreepData, CreepRole, excavator::ExcavatorCreep}, domain_traits::{CreepId, HasId, ObjectId, ResolvableId}, memory::Memory, names::UsedNames, spawn::{energy::{ColonyEnergy, EnergyGroup, EnergyStructure}, prototype::{AbsolutePrototype, Prototype, RelativePrototype}}};
+use crate::{colony::ColonyView, creeps::{CreepData, CreepRole, excavator::ExcavatorCreep}, domain_traits::{CreepId, HasId, ObjectId, ResolvableId}, memory::Memory, names::UsedNames, spawn::{energy::{ColonyEnergy, EnergyGroup, EnergyStructure}, prototype::{AbsolutePrototype, Prototype, RelativePrototype}, queue::{RequestInfo, SpawnPreference, SpawnQueue, SpawnRequest, is_visualized}}};
 
 pub type SharedUsedNames = Rc<RefCell<UsedNames>>;
 
@@ -23,28 +23,36 @@ pub struct ColonySyndrome {
 impl ColonySyndrome {
     fn new(creeps: &ColonyCreeps, view: &ColonyView<'_>) -> Self {
         Self {
-            any_trucks: creeps.0.values().any(|proto| matches!(proto.role(), CreepRole::Truck(_))),
-            any_excavating_excavators: creeps.0.values().any(|proto| matches!(proto.role(), CreepRole::Excavator(ExcavatorCreep::Mining, _))),
+            any_trucks: creeps.values().any(|proto| matches!(proto.role(), CreepRole::Truck(_))),
+            any_excavating_excavators: creeps.values().any(|proto| matches!(proto.role(), CreepRole::Excavator(ExcavatorCreep::Mining, _))),
             excavators:
                 view.plan.sources.keys()
                     .filter_map(|id| Some(id.resolve()?.id()))
                     .filter_map(|source| {
-                        let Some((excavator, proto)) = creeps.0.iter().find(|(_, proto)| matches!(proto.role(), CreepRole::Excavator(_, source2) if source == *source2)) else {
-                            return Some((source, ExcavatorSyndrome::NoExcavator));
-                        };
+                        let excavators = creeps.iter()
+                            .filter(|(_, proto)| matches!(proto.role(), CreepRole::Excavator(_, source2) if source == *source2))
+                            .collect_vec();
 
-                        let CreepRole::Excavator(state, _) = proto.role() else { unreachable!() };
+                        if excavators.is_empty() { return Some((source, ExcavatorSyndrome::NoExcavator)) }
 
-                        if matches!(state, ExcavatorCreep::Going)
-                            && creeps.0.values().all(|proto| !matches!(proto.role(), CreepRole::Tugboat(tugged, _) if *excavator == *tugged)) {
-                                return Some((source, ExcavatorSyndrome::NoTugboatFor(excavator.clone())));
-                        }
+                        // During a handover the posted predecessor keeps mining while its successor is on its way
+                        if excavators.iter().any(|(_, proto)| proto.role().is_posted()) { return None }
+
+                        let (excavator, _) = excavators.into_iter().find(|(excavator, proto)| {
+                            matches!(proto.role(), CreepRole::Excavator(ExcavatorCreep::Going, _))
+                                && creeps.values().all(|proto| !matches!(proto.role(), CreepRole::Tugboat(tugged, _) if **excavator == *tugged))
+                        })?;
 
-                        None
+                        Some((source, ExcavatorSyndrome::NoTugboatFor(excavator.clone())))
                     }).collect()
         }
     }
 
+    // Without trucks or working excavators the spawns aren't refilled, so those are never held back
+    fn is_recovery(&self, role: &CreepRole) -> bool {
+        (!self.any_trucks || !self.any_excavating_excavators) && matches!(role, CreepRole::Excavator(_, _) | CreepRole::Truck(_))
+    }
+
     pub fn any_problems(&self) -> bool {
         !self.any_trucks || !self.any_excavating_excavators || !self.excavators.is_empty()
     }
@@ -69,18 +77,80 @@ impl ColonySyndrome {
     }
 }
 
-#[derive(Deref)]
-pub struct ColonyCreeps(HashMap<CreepId, RelativePrototype>);
+pub struct ColonyCreeps {
+    creeps: HashMap<CreepId, RelativePrototype>,
+    expiring: HashSet<CreepId>,
+    replaced: HashSet<CreepId>,
+    queued: Vec<RelativePrototype>
+}
 
 impl ColonyCreeps {
     pub fn new(colony: RoomName, mem: &Memory) -> Self {
-        Self(
-            mem.creeps.iter()
-                .filter(|(_, data)| data.home == colony)
+        let local_creeps = mem.creeps.iter().filter(|(_, data)| data.home == colony);
+
+        Self {
+            creeps: local_creeps.clone()
                 .map(|(id, data)| (id.clone(), RelativePrototype::from_creep(id, data)))
+                .collect(),
+            expiring: local_creeps.clone()
+                .filter(|(id, data)| is_expiring(&id.resolve(), data))
+                .map(|(id, _)| id.clone())
+                .collect(),
+            replaced: local_creeps
+                .filter_map(|(_, data)| data.predecessor.clone())
+                .collect(),
+            queued: mem.spawn_queues.values()
+                .flat_map(SpawnQueue::iter)
+                .filter(|request| request.home == colony && request.deadline >= game::time())
+                .map(|request| request.proto().relative().clone())
                 .collect()
-        )
+        }
     }
+
+    // Creeps that will still be alive when a replacement could take over their post, and those queued to be spawned
+    pub fn active(&self) -> impl Iterator<Item = &RelativePrototype> {
+        self.creeps.iter()
+            .filter(|(id, _)| !self.expiring.contains(id))
+            .map(|(_, proto)| proto)
+            .chain(&self.queued)
+    }
+
+    fn take_predecessor(&mut self, role: &CreepRole) -> Option<CreepId> {
+        let predecessor = self.expiring.iter()
+            .filter(|id| !self.replaced.contains(id))
+            .find(|id| role.is_successor_of(self.creeps[*id].role()))?
+            .clone();
+
+        self.replaced.insert(predecessor.clone());
+        Some(predecessor)
+    }
+}
+
+impl Deref for ColonyCreeps {
+    type Target = HashMap<CreepId, RelativePrototype>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.creeps
+    }
+}
+
+// Where refilling trucks are and where they are headed
+fn refill_route(colony: &ColonyView<'_>, mem: &Memory) -> Vec<Position> {
+    mem.creeps.iter()
+        .filter(|(_, data)| data.home == colony.name)
+        .filter_map(|(truck, data)| {
+            let CreepRole::Truck(state) = &data.role else { return None };
+            Some([truck.resolve().pos(), state.refill_target()?])
+        })
+        .flatten()
+        .chain(iter::once(colony.center))
+        .collect()
+}
+
+// A creep expires once a successor spawned now would arrive at its post after it dies
+fn is_expiring(creep: &Creep, data: &CreepData) -> bool {
+    let spawn_time = creep.body().len() as u32 * CREEP_SPAWN_TIME;
+    creep.ticks_to_live().is_some_and(|ttl| ttl < spawn_time + data.travel_time.unwrap_or(0))
 }
 
 enum SpawnState {
@@ -151,45 +221,20 @@ pub struct ColonyRoster {
     spawns: Vec<ColonySpawn>,
     energy: ColonyEnergy,
     local_creeps: ColonyCreeps,
+    queue: SpawnQueue,
 
     syndrome: ColonySyndrome,
 
     names: SharedUsedNames
 }
 
-pub struct ColonySpawnIterator<'a> {
-    index: usize,
-    spawns: &'a [ColonySpawn]
-}
-
-impl<'a> Iterator for ColonySpawnIterator<'a> {
-    type Item = (usize, &'a ColonySpawn);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(spawn) = self.spawns.get(self.index) {
-            let index = self.index;
-            self.index += 1;
-
-            if !spawn.is_free() { continue; }
-
-            return Some((index, spawn))
-        }
-
-        None
-    }
-}
-
 pub struct SpawnInfo {
-    pub spawn: StructureSpawn,
-    #[expect(unused)]
-    pub energy: u32,
     pub future_energy: u32
 }
 
 pub enum ScheduleDecision {
-    Scheduled(CreepId, AbsolutePrototype),
-    WaitingForEnergy,
-    WaitingForSpawn
+    Scheduled,
+    WaitingForEnergy
 }
 
 #[derive(Debug, Error)]
@@ -209,15 +254,15 @@ impl ColonyRoster {
         let mut groups = Vec::new();
 
         groups.push(EnergyGroup::new(
-            colony.plan.center.spawn.resolve()
+            colony.plan.center.spawn.resolve().into_iter()
+                .chain(colony.plan.center.extra_spawns.resolve())
                 .map(|spawn| EnergyStructure::Spawn(spawn.id()))
-                .into_iter()
                 .chain(
                     colony.plan.center.extensions.resolve().into_iter()
-                    .sorted_by_cached_key(|extension| extension.pos().get_range_to(colony.center))
                     .map(|ext| EnergyStructure::Extension(ext.id()))
                 ).collect(),
-            syndrome.any_excavating_excavators && syndrome.any_trucks
+            syndrome.any_excavating_excavators && syndrome.any_trucks,
+            None
         ));
 
         for (source, source_plan) in &colony.plan.sources {
@@ -231,14 +276,16 @@ impl ColonyRoster {
                         source_plan.extensions.resolve().into_iter()
                             .map(|ext| EnergyStructure::Extension(ext.id())))
                     .collect(),
-                !syndrome.excavators.contains_key(&source)
+                !syndrome.excavators.contains_key(&source),
+                Some(source)
             ));
         }
 
         let mut spawns = Vec::new();
 
         spawns.extend(
-            colony.plan.center.spawn.resolve()
+            colony.plan.center.spawn.resolve().into_iter()
+                .chain(colony.plan.center.extra_spawns.resolve())
                 .map(|spawn| {
                     ColonySpawn::new(
                         spawn,
@@ -252,17 +299,21 @@ impl ColonyRoster {
                 .filter_map(|(source, plan)| {
                     Some(ColonySpawn::new(
                         plan.spawn.resolve()?,
-                        ColonySpawnType::Source(source.resolve()?.id(), plan.spawn_direction)
+                        ColonySpawnType::Source(source.resolve()?.id(), plan.spawn_direction()?)
                     ))
                 })
         );
 
+        let mut queue = mem.spawn_queues.get(&colony.name).cloned().unwrap_or_default();
+        queue.drop_expired(colony.name);
+
         Self {
             spawns,
-            energy: ColonyEnergy::new(groups),
+            energy: ColonyEnergy::new(groups, &refill_route(colony, mem)),
             names,
             name: colony.name,
             local_creeps,
+            queue,
             syndrome
         }
     }
@@ -283,34 +334,63 @@ impl ColonyRoster {
         &self.syndrome
     }
 
-    // Not meant to be used by user
-    fn schedule_selected_absolute<S, P>(&mut self, select: S, make_proto: P) -> ColonyScheduleResult
+    pub fn future_energy(&self) -> u32 {
+        self.energy.future_energy(None)
+    }
+
+    // Held back for the excavators of a source
+    pub fn reserved_future_energy(&self, source: ObjectId<Source>) -> u32 {
+        self.energy.future_energy(Some(source)) - self.energy.future_energy(None)
+    }
+
+    pub fn nearest_spawn(&self, pos: Position) -> Option<&StructureSpawn> {
+        self.spawns.iter()
+            .map(|spawn| &spawn.spawn)
+            .min_by_key(|spawn| spawn.pos().get_range_to(pos))
+    }
+
+    pub fn request<P>(&mut self, info: RequestInfo, make_proto: P) -> Result<u32, ColonyScheduleError>
     where
-        S: for<'b> FnOnce(ColonySpawnIterator<'b>) -> Option<usize>,
-        P: FnOnce(SpawnInfo) -> Option<AbsolutePrototype>
+        P: FnOnce(SpawnInfo) -> Option<RelativePrototype>
     {
-        let Some(choice) = select(ColonySpawnIterator { index: 0, spawns: &self.spawns }) else { return Ok(ScheduleDecision::WaitingForSpawn) };
-        let spawn = self.spawns.get_mut(choice).expect("Spawn selection should return a valid index");
-        let spawn_info = SpawnInfo {
-            spawn: spawn.spawn.clone(),
-            energy: self.energy.energy(),
-            future_energy: self.energy.future_energy()
-        };
+        let proto = make_proto(SpawnInfo { future_energy: self.future_energy() }).ok_or(ColonyScheduleError::NoPrototype)?;
+        let request = info.into_request(&proto.clone().with_home(self.name));
+
+        self.local_creeps.queued.push(proto);
+        Ok(self.queue.push(request))
+    }
 
-        let proto = make_proto(spawn_info).ok_or(ColonyScheduleError::NoPrototype)?;
+    pub fn cancel_requests(&mut self, cancel: impl Fn(&SpawnRequest) -> bool) {
+        self.queue.retain(|request| !cancel(request));
+    }
 
+    fn select_spawn<'a>(&self, mut spawns: impl Iterator<Item = (usize, &'a ColonySpawn)>, preference: &SpawnPreference) -> Option<usize> {
+        match preference {
+            SpawnPreference::Source(source) if self.spawns.iter().any(|spawn| spawn.is_source_spawn(source)) =>
+                spawns.find(|(_, spawn)| spawn.is_source_spawn(source)),
+            SpawnPreference::Near(pos) =>
+                spawns.min_by_key(|(_, spawn)| spawn.spawn.pos().get_range_to(*pos)),
+            _ => spawns.max_by_key(|(_, spawn)| spawn.is_central())
+        }.map(|(ix, _)| ix)
+    }
+
+    fn spawn_request(&mut self, choice: usize, request: &SpawnRequest) -> ColonyScheduleResult {
+        let spawn = self.spawns.get_mut(choice).expect("Spawn selection should return a valid index");
         assert!(spawn.is_free());
 
+        let proto = request.proto();
         let cost = proto.body().energy_required();
 
-        if cost > self.energy.future_energy() { return Err(ColonyScheduleError::NotEnoughEnergy) }
-        if cost > self.energy.energy() {
+        let source = if let CreepRole::Excavator(_, source) = proto.role() { Some(*source) } else { None };
+
+        if cost > self.energy.future_energy(source) { return Err(ColonyScheduleError::NotEnoughEnergy) }
+        if cost > self.energy.energy(source) {
             spawn.block();
-            self.energy.reserve_future(cost);
+            self.energy.reserve_future(cost, source);
             return Ok(ScheduleDecision::WaitingForEnergy)
         }
 
-        let structures = self.energy.allocate(cost);
+        let structures = self.energy.allocate(cost, source);
 
         let dirs = if let Some(dir) = spawn.source_direction() {
             if let CreepRole::Excavator(_, source) = proto.role() && spawn.is_source_spawn(source) {
@@ -322,6 +402,7 @@ impl ColonyRoster {
             Direction::iter().copied().collect_vec()
         };
 
+        let predecessor = self.local_creeps.take_predecessor(proto.role());
         let name = self.names.borrow_mut().generate_new(proto.role());
         spawn.spawn.spawn_creep_with_options(
             proto.body().parts(),
@@ -332,41 +413,60 @@ impl ColonyRoster {
         )?;
 
         let id = game::creeps().get(name).unwrap().id();
-        spawn.begin_spawning(id.clone(), CreepData { role: proto.role().clone(), home: proto.home() }, dirs);
+        spawn.begin_spawning(id, CreepData { role: proto.role().clone(), home: proto.home(), travel_time: None, predecessor, boosts: proto.boosts().to_vec() }, dirs);
 
-        Ok(ScheduleDecision::Scheduled(id, proto))
+        Ok(ScheduleDecision::Scheduled)
     }
 
-    pub fn schedule_selected<S, P>(&mut self, select: S, make_proto: P) -> ColonyScheduleResult
-    where
-        S: for<'a> FnOnce(ColonySpawnIterator<'a>) -> Option<usize>,
-        P: FnOnce(SpawnInfo) -> Option<RelativePrototype>
-    {
-        let home = self.name;
-        let result = self.schedule_selected_absolute(select, |spawn| make_proto(spawn).map(|creep| creep.with_home(home)));
+    pub fn spawn_queued(&mut self) {
+        // Queued defenders of the home room pre-empt the economy, so the extensions aren't drained while they wait for energy.
+        // Remotes can be given up for a while, so their defenders don't hold back the colony
+        let defending = self.queue.iter().any(|request| request.role.defended_room() == Some(self.name));
 
-        if let Ok(ScheduleDecision::Scheduled(id, proto)) = &result {
-            self.local_creeps.0.insert(id.clone(), proto.relative().clone());
-        }
+        for request in self.queue.by_priority() {
+            if !self.has_free() { break; }
+            if defending && request.role.defended_room().is_none() && !self.syndrome.is_recovery(&request.role) { continue; }
 
-        result
-    }
+            let free_spawns = self.spawns.iter().enumerate().filter(|(_, spawn)| spawn.is_free());
+            let Some(choice) = self.select_spawn(free_spawns, &request.preference) else { continue; };
 
-    pub fn default_select(iter: ColonySpawnIterator<'_>) -> Option<usize> {
-        iter.max_by_key(|(_, spawn)| spawn.is_central()).map(|(ix, _)| ix)
+            match self.spawn_request(choice, &request) {
+                Ok(ScheduleDecision::Scheduled) => { self.queue.remove(request.id); },
+                Ok(ScheduleDecision::WaitingForEnergy) => {},
+                Err(err) => {
+                    warn!("Dropping spawn request {} for a {} in {}: {err}", request.id, request.role.prefix(), self.name);
+                    self.queue.remove(request.id);
+                }
+            }
+        }
     }
 
-    pub fn schedule<P>(&mut self, make_proto: P) -> ColonyScheduleResult
-    where
-        P: FnOnce(SpawnInfo) -> Option<RelativePrototype>
-    {
-        self.schedule_selected(Self::default_select, make_proto)
+    fn draw_queue(&self) {
+        let visuals = RoomVisual::new(Some(self.name));
+        let style = TextStyle::default().custom_font("0.4 Consolas").align(TextAlign::Left).opacity(0.8);
+
+        let mut lines: HashMap<usize, Vec<String>> = HashMap::new();
+        for request in self.queue.by_priority() {
+            let Some(ix) = self.select_spawn(self.spawns.iter().enumerate(), &request.preference) else { continue; };
+            lines.entry(ix).or_default().push(format!("#{} [{}] {} {}: {}", request.id, request.priority, request.role.prefix(), request.body, request.reason));
+        }
+
+        for (ix, lines) in lines {
+            let pos = self.spawns[ix].spawn.pos();
+            for (i, line) in lines.into_iter().enumerate() {
+                visuals.text(f32::from(pos.x().u8()) + 1.0, f32::from(pos.y().u8()) + 0.5 * i as f32, line, Some(style.clone()));
+            }
+        }
     }
 
-    fn gather_new_creeps(self, mem: &mut Memory) {
+    fn store(self, mem: &mut Memory) {
+        if is_visualized(self.name) { self.draw_queue(); }
+
         for spawn in self.spawns {
             spawn.gather_new_creeps(mem);
         }
+
+        mem.spawn_queues.insert(self.name, self.queue);
     }
 }
 
@@ -377,16 +477,28 @@ pub enum GlobalScheduleError {
     #[error(transparent)] Roster(#[from] ColonyScheduleError),
 }
 
-pub type GlobalScheduleResult = Result<ScheduleDecision, GlobalScheduleError>;
-
-#[derive(Deref)]
-pub struct GlobalCreeps(HashMap<CreepId, AbsolutePrototype>);
+pub struct GlobalCreeps {
+    creeps: HashMap<CreepId, AbsolutePrototype>,
+    queued: Vec<AbsolutePrototype>
+}
 
 impl GlobalCreeps {
     fn new(mem: &Memory) -> Self {
-        Self(mem.creeps.iter()
-            .map(|(id, data)| (id.clone(), AbsolutePrototype::from_creep(id, data)))
-            .collect())
+        Self {
+            creeps: mem.creeps.iter()
+                .map(|(id, data)| (id.clone(), AbsolutePrototype::from_creep(id, data)))
+                .collect(),
+            queued: mem.spawn_queues.values()
+                .flat_map(SpawnQueue::iter)
+                .filter(|request| request.deadline >= game::time())
+                .map(SpawnRequest::proto)
+                .collect()
+        }
+    }
+
+    // Every creep, including those queued to be spawned
+    pub fn all(&self) -> impl Iterator<Item = &AbsolutePrototype> {
+        self.creeps.values().chain(&self.queued)
     }
 }
 
@@ -419,42 +531,41 @@ impl Rosters {
         &self.global_creeps
     }
 
-    pub fn schedule_selected<S, P>(&mut self, select: S, make_proto: P) -> GlobalScheduleResult
+    // Requests are queued in the colony with the most energy, but may be for another home
+    pub fn request<P>(&mut self, info: RequestInfo, make_proto: P) -> Result<u32, GlobalScheduleError>
     where
-        S: for<'a> FnOnce(hash_map::Iter<'a, RoomName, ColonyRoster>) -> Option<RoomName>,
         P: FnOnce(SpawnInfo) -> Option<Prototype>
     {
-        let Some(choice) = select(self.rosters.iter()) else { return Err(GlobalScheduleError::NoRoom) };
-        let roster = self.rosters.get_mut(&choice).expect("Room selection should return a valid room");
-
-        let result = roster.schedule_selected_absolute(
-            ColonyRoster::default_select,
-            |info| make_proto(info).map(|proto| proto.with_default_home(choice))
-        );
+        let choice = self.rosters.iter()
+            .max_by_key(|(_, roster)| roster.future_energy())
+            .map(|(room, _)| *room)
+            .ok_or(GlobalScheduleError::NoRoom)?;
 
-        if let Ok(ScheduleDecision::Scheduled(id, proto)) = &result {
-            self.rosters.get_mut(&proto.home())
-                .ok_or(GlobalScheduleError::InvalidHome(proto.home()))?
-                .local_creeps.0.insert(id.clone(), proto.relative().clone());
-        }
+        let roster = self.rosters.get_mut(&choice).unwrap();
+        let proto = make_proto(SpawnInfo { future_energy: roster.future_energy() })
+            .ok_or(ColonyScheduleError::NoPrototype)?
+            .with_default_home(choice);
+        let id = roster.queue.push(info.into_request(&proto));
 
-        result.map_err(GlobalScheduleError::from)
-    }
+        self.rosters.get_mut(&proto.home())
+            .ok_or(GlobalScheduleError::InvalidHome(proto.home()))?
+            .local_creeps.queued.push(proto.relative().clone());
+        self.global_creeps.queued.push(proto);
 
-    pub fn default_select(iter: hash_map::Iter<'_, RoomName, ColonyRoster>) -> Option<RoomName> {
-        iter.max_by_key(|(_, roster)| roster.energy.future_energy()).map(|(room, _)| *room)
+        Ok(id)
     }
 
-    pub fn schedule<P>(&mut self, make_proto: P) -> GlobalScheduleResult
-    where
-        P: FnOnce(SpawnInfo) -> Option<Prototype>
-    {
-        self.schedule_selected(Self::default_select, make_proto)
+    pub fn spawn_queued(&mut self) {
+        for roster in self.rosters.values_mut() {
+            roster.spawn_queued();
+        }
     }
 
-    pub fn gather_new_creeps(self, mem: &mut Memory) {
-        for colony_spawns in self.rosters.into_values() {
-            colony_spawns.gather_new_creeps(mem);
+    // Colonies without a roster this tick keep their queues, including requests from the console
+    pub fn store(self, mem: &mut Memory) {
+        mem.spawn_queues.retain(|room, _| mem.colonies.rooms().any(|colony| colony == *room));
+        for roster in self.rosters.into_values() {
+            roster.store(mem);
         }
     }
 }
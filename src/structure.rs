@@ -1,7 +1,7 @@
 use std::{marker::PhantomData};
 
 use derive_where::derive_where;
-use screeps::{HasPosition, Position, SharedCreepProperties, Store, Structure, StructureObject};
+use screeps::{HasPosition, Position, SharedCreepProperties, Store, Structure, StructureObject, StructureType};
 use serde::{Deserialize, Serialize};
 
 use crate::{check::{Check, CheckFrom}, domain_traits::{HasId, HasHits, HasStore, IdResolutionError, ObjectId, Repairable, ResolvableId, Transferable, Withdrawable}, ids::{CheckState, Checked, Unchecked}};
@@ -54,7 +54,7 @@ impl<T: StructureKind + RepairableKind> HasHits for KindedStructure<T> {
     }
 
     fn hits_max(&self) -> u32 {
-        self.structure_object().as_repairable().unwrap().hits_max()
+        repair_target_hits(&self.structure_object())
     }
 }
 
@@ -64,6 +64,23 @@ impl<T: StructureKind + RepairableKind> Repairable for KindedStructure<T> {
     }
 }
 
+pub fn repair_target_hits(structure: &StructureObject) -> u32 {
+    let hits_max = structure.as_repairable().map_or(0, screeps::HasHits::hits_max);
+    if !matches!(structure.structure_type(), StructureType::Rampart | StructureType::Wall) { return hits_max }
+
+    let level = structure.as_structure().room().and_then(|room| room.controller()).map_or(0, |controller| controller.level());
+    let target = match level {
+        ..=3 => 10_000,
+        4 => 50_000,
+        5 => 100_000,
+        6 => 300_000,
+        7 => 1_000_000,
+        _ => 3_000_000
+    };
+
+    hits_max.min(target)
+}
+
 pub trait StructureKind {}
 
 macro_rules! def_structure_kind {
@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use log::{info, warn};
+use screeps::{ResourceType, RoomName, StructureTerminal, game};
+
+use crate::{colony::{ColonyView, steps::ColonyStep}, domain_traits::{EnergyStoreAccessors, HasStoreExt}, labs::{LabCoordinator, stored_amount}, memory::Memory, terminals::network::{GameTerminalNetwork, TerminalNetwork}};
+
+pub mod network;
+
+// Offset from the market, so that minerals are balanced between colonies before any are bought
+const BALANCE_INTERVAL: u32 = 10;
+const BALANCE_OFFSET: u32 = 5;
+
+// Transfers further than this lose more than 40% of the amount sent to energy costs
+const MAX_SEND_DISTANCE: u32 = 15;
+const MAX_SEND_AMOUNT: u32 = 10_000;
+
+const EXPORT_STEP: ColonyStep = ColonyStep::EndlesslyUpgrade;
+const EXPORT_ENERGY_THRESHOLD: u32 = 150_000;
+const IMPORT_ENERGY_TARGET: u32 = 200_000;
+const MIN_ENERGY_SEND: u32 = 1_000;
+
+const TERMINAL_ENERGY: u32 = 2_000;
+const EXPORT_TERMINAL_ENERGY: u32 = 30_000;
+
+const MINERAL_RESERVE: u32 = 5_000;
+const MIN_MINERAL_SEND: u32 = 100;
+
+struct NetworkTerminal {
+    name: RoomName,
+    exporting: bool,
+    // Energy in the colony buffer, which the terminal is refilled from
+    buffer_energy: u32,
+    // Energy in the terminal itself, which pays for the transfers
+    energy: u32,
+    free_capacity: u32,
+    ready: bool,
+    surplus: HashMap<ResourceType, u32>,
+    missing: HashMap<ResourceType, u32>
+}
+
+impl NetworkTerminal {
+    fn of_colony(colony: &ColonyView<'_>, terminal: &StructureTerminal) -> Self {
+        Self {
+            name: colony.name,
+            exporting: is_exporting(colony),
+            buffer_energy: colony.buffer.as_ref().map_or(0, EnergyStoreAccessors::used_energy_capacity),
+            energy: terminal.used_energy_capacity(),
+            free_capacity: terminal.free_capacity(None),
+            ready: terminal.cooldown() == 0,
+            surplus: HashMap::new(),
+            missing: HashMap::new()
+        }
+    }
+
+    // Minerals take a lookup per resource, so they are only filled in when the minerals are balanced
+    fn with_minerals(self, colony: &ColonyView<'_>, terminal: &StructureTerminal, labs: Option<&LabCoordinator>) -> Self {
+        let surplus = terminal.store().store_types().into_iter()
+            .filter(|resource| *resource != ResourceType::Energy)
+            .map(|resource| (resource, stored_amount(colony, resource).saturating_sub(MINERAL_RESERVE).min(terminal.used_capacity(Some(resource)))))
+            .filter(|(_, amount)| *amount > 0)
+            .collect();
+
+        Self { surplus, missing: labs.map(|labs| labs.missing_minerals(colony)).unwrap_or_default(), ..self }
+    }
+
+    fn total_energy(&self) -> u32 {
+        self.buffer_energy + self.energy
+    }
+
+    fn has_energy_surplus(&self) -> bool {
+        self.exporting && self.buffer_energy > EXPORT_ENERGY_THRESHOLD
+    }
+
+    // Largest part of the amount which can be sent with the energy in the terminal
+    fn sendable(&self, resource: ResourceType, amount: u32, to: RoomName, network: &impl TerminalNetwork) -> u32 {
+        let cost = network.transaction_cost(amount, self.name, to);
+        let needed = cost + if resource == ResourceType::Energy { amount } else { 0 };
+        if needed <= self.energy { return amount }
+
+        let scale = f64::from(self.energy) / f64::from(needed);
+        (f64::from(amount) * scale) as u32
+    }
+
+    fn send(&self, resource: ResourceType, amount: u32, to: RoomName, network: &mut impl TerminalNetwork) -> bool {
+        let result = network.send(self.name, resource, amount, to);
+        match &result {
+            Ok(()) => info!("Sent {amount} {resource} from {} to {to}", self.name),
+            Err(err) => warn!("{err}")
+        }
+
+        result.is_ok()
+    }
+}
+
+pub fn is_exporting(colony: &ColonyView<'_>) -> bool {
+    colony.progress.has_reached(EXPORT_STEP)
+}
+
+// Energy the terminal is filled with, exporting colonies keep enough around to send in batches
+pub fn terminal_energy_target(colony: &ColonyView<'_>) -> u32 {
+    if is_exporting(colony) { EXPORT_TERMINAL_ENERGY } else { TERMINAL_ENERGY }
+}
+
+// Whether the colony sends its energy surplus to the other through the terminals, which makes hauling it there unnecessary
+pub fn exports_energy_to(from: &ColonyView<'_>, to: &ColonyView<'_>) -> bool {
+    let (Some(from_terminal), Some(to_terminal)) = (from.plan.center.terminal.resolve(), to.plan.center.terminal.resolve()) else { return false };
+    exports_energy(&NetworkTerminal::of_colony(from, &from_terminal), &NetworkTerminal::of_colony(to, &to_terminal), &GameTerminalNetwork::default())
+}
+
+fn exports_energy(from: &NetworkTerminal, to: &NetworkTerminal, network: &impl TerminalNetwork) -> bool {
+    from.name != to.name
+        && from.has_energy_surplus()
+        && !to.exporting
+        && network.distance(from.name, to.name) <= MAX_SEND_DISTANCE
+}
+
+fn balance_energy(terminals: &[NetworkTerminal], busy: &HashSet<RoomName>, network: &mut impl TerminalNetwork) {
+    let mut demands = terminals.iter().enumerate()
+        .filter(|(_, importer)| !importer.exporting)
+        .map(|(i, importer)| (i, IMPORT_ENERGY_TARGET.saturating_sub(importer.total_energy()).min(importer.free_capacity)))
+        .filter(|(_, demand)| *demand >= MIN_ENERGY_SEND)
+        .collect::<HashMap<_, _>>();
+
+    let exporters = terminals.iter()
+        .filter(|exporter| exporter.has_energy_surplus() && exporter.ready && !busy.contains(&exporter.name));
+
+    for exporter in exporters {
+        let importer = demands.keys()
+            .filter(|i| exports_energy(exporter, &terminals[**i], network))
+            .min_by_key(|i| (network.distance(exporter.name, terminals[**i].name), terminals[**i].name.to_string()))
+            .copied();
+        let Some(importer) = importer else { continue; };
+
+        let to = terminals[importer].name;
+        let amount = exporter.sendable(ResourceType::Energy, demands[&importer].min(MAX_SEND_AMOUNT), to, network);
+        if amount < MIN_ENERGY_SEND || !exporter.send(ResourceType::Energy, amount, to, network) { continue; }
+
+        let demand = demands.get_mut(&importer).unwrap();
+        *demand -= amount;
+        if *demand < MIN_ENERGY_SEND { demands.remove(&importer); }
+    }
+}
+
+// Minerals missing from the lab reactions of one colony are taken from the surplus of the others
+fn balance_minerals(terminals: &[NetworkTerminal], busy: &mut HashSet<RoomName>, network: &mut impl TerminalNetwork) {
+    for importer in terminals {
+        for (resource, amount) in importer.missing.iter().sorted_by_key(|(resource, _)| resource.to_string()) {
+            let supplies = terminals.iter()
+                .filter(|donor| donor.name != importer.name && donor.ready && !busy.contains(&donor.name))
+                .filter(|donor| network.distance(importer.name, donor.name) <= MAX_SEND_DISTANCE)
+                .map(|donor| (donor, donor.surplus.get(resource).copied().unwrap_or(0)))
+                .filter(|(_, surplus)| *surplus >= MIN_MINERAL_SEND);
+
+            let Some((donor, surplus)) = supplies.min_by_key(|(donor, _)| network.distance(importer.name, donor.name)) else { continue; };
+
+            let amount = donor.sendable(*resource, (*amount).min(surplus).min(MAX_SEND_AMOUNT), importer.name, network);
+            if amount < MIN_MINERAL_SEND { continue; }
+
+            if donor.send(*resource, amount, importer.name, network) {
+                busy.insert(donor.name);
+            }
+        }
+    }
+}
+
+pub fn do_terminals(mem: &Memory) {
+    if game::time() % BALANCE_INTERVAL != BALANCE_OFFSET { return }
+
+    let mut network = GameTerminalNetwork::default();
+    let terminals = mem.colonies.view_all()
+        .filter_map(|colony| {
+            let terminal = colony.plan.center.terminal.resolve()?;
+            let network_terminal = NetworkTerminal::of_colony(&colony, &terminal).with_minerals(&colony, &terminal, mem.lab_coordinators.get(&colony.name));
+            network.terminals.insert(colony.name, terminal);
+            Some(network_terminal)
+        }).collect_vec();
+    if terminals.len() < 2 { return }
+
+    // A terminal can only send once per tick
+    let mut busy = HashSet::new();
+    balance_minerals(&terminals, &mut busy, &mut network);
+    balance_energy(&terminals, &busy, &mut network);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{market::book::FlatTransactionCost, terminals::network::FakeTerminalNetwork};
+
+    fn terminal(name: &str, exporting: bool, buffer_energy: u32) -> NetworkTerminal {
+        NetworkTerminal {
+            name: RoomName::new(name).unwrap(),
+            exporting,
+            buffer_energy,
+            energy: if exporting { EXPORT_TERMINAL_ENERGY } else { TERMINAL_ENERGY },
+            free_capacity: 200_000,
+            ready: true,
+            surplus: HashMap::new(),
+            missing: HashMap::new()
+        }
+    }
+
+    fn exporter(name: &str) -> NetworkTerminal {
+        terminal(name, true, 200_000)
+    }
+
+    fn importer(name: &str) -> NetworkTerminal {
+        terminal(name, false, 0)
+    }
+
+    fn room(name: &str) -> RoomName {
+        RoomName::new(name).unwrap()
+    }
+
+    #[test]
+    fn energy_is_only_exported_to_nearby_importers() {
+        let network = FakeTerminalNetwork::default();
+
+        assert!(exports_energy(&exporter("W1N1"), &importer("W5N1"), &network));
+        assert!(exports_energy(&exporter("W1N1"), &importer("W16N1"), &network));
+        assert!(!exports_energy(&exporter("W1N1"), &importer("W17N1"), &network));
+        assert!(!exports_energy(&exporter("W1N1"), &exporter("W5N1"), &network));
+        assert!(!exports_energy(&importer("W1N1"), &exporter("W5N1"), &network));
+        assert!(!exports_energy(&exporter("W1N1"), &NetworkTerminal { exporting: false, ..exporter("W1N1") }, &network));
+    }
+
+    #[test]
+    fn exporters_without_a_surplus_leave_the_importers_to_their_trucks() {
+        let network = FakeTerminalNetwork::default();
+
+        assert!(!exports_energy(&terminal("W1N1", true, EXPORT_ENERGY_THRESHOLD), &importer("W3N1"), &network));
+        assert!(exports_energy(&terminal("W1N1", true, EXPORT_ENERGY_THRESHOLD + 1), &importer("W3N1"), &network));
+    }
+
+    #[test]
+    fn energy_goes_to_the_nearest_importer() {
+        let mut network = FakeTerminalNetwork::default();
+        let terminals = [exporter("W1N1"), importer("W6N1"), importer("W3N1"), importer("W1N20")];
+
+        balance_energy(&terminals, &HashSet::new(), &mut network);
+        assert_eq!(network.sends, vec![(room("W1N1"), ResourceType::Energy, MAX_SEND_AMOUNT, room("W3N1"))]);
+    }
+
+    #[test]
+    fn energy_is_sent_up_to_the_import_target() {
+        let mut network = FakeTerminalNetwork::default();
+        let terminals = [exporter("W1N1"), exporter("W2N1"), terminal("W3N1", false, IMPORT_ENERGY_TARGET - TERMINAL_ENERGY - 2_500)];
+
+        balance_energy(&terminals, &HashSet::new(), &mut network);
+        assert_eq!(network.sends, vec![(room("W1N1"), ResourceType::Energy, 2_500, room("W3N1"))]);
+
+        let mut network = FakeTerminalNetwork::default();
+        let terminals = [exporter("W1N1"), terminal("W3N1", false, IMPORT_ENERGY_TARGET - TERMINAL_ENERGY - 500)];
+
+        balance_energy(&terminals, &HashSet::new(), &mut network);
+        assert!(network.sends.is_empty());
+    }
+
+    #[test]
+    fn exporters_keep_their_reserve() {
+        let terminals = [terminal("W1N1", true, EXPORT_ENERGY_THRESHOLD), importer("W3N1")];
+        let mut network = FakeTerminalNetwork::default();
+
+        balance_energy(&terminals, &HashSet::new(), &mut network);
+        assert!(network.sends.is_empty());
+    }
+
+    #[test]
+    fn busy_and_cooling_down_terminals_send_nothing() {
+        let mut network = FakeTerminalNetwork::default();
+
+        balance_energy(&[exporter("W1N1"), importer("W3N1")], &HashSet::from([room("W1N1")]), &mut network);
+        balance_energy(&[NetworkTerminal { ready: false, ..exporter("W1N1") }, importer("W3N1")], &HashSet::new(), &mut network);
+        assert!(network.sends.is_empty());
+    }
+
+    #[test]
+    fn sends_are_scaled_to_the_energy_in_the_terminal() {
+        let mut network = FakeTerminalNetwork { transfer_cost: FlatTransactionCost(0.5), ..FakeTerminalNetwork::default() };
+        let terminals = [NetworkTerminal { energy: 6_000, ..exporter("W1N1") }, importer("W3N1")];
+
+        balance_energy(&terminals, &HashSet::new(), &mut network);
+        assert_eq!(network.sends, vec![(room("W1N1"), ResourceType::Energy, 4_000, room("W3N1"))]);
+    }
+
+    #[test]
+    fn missing_minerals_come_from_the_nearest_donor_with_a_surplus() {
+        let mut network = FakeTerminalNetwork::default();
+        let with_surplus = |name, amount| NetworkTerminal { surplus: HashMap::from([(ResourceType::Hydrogen, amount)]), ..importer(name) };
+        let terminals = [
+            NetworkTerminal { missing: HashMap::from([(ResourceType::Hydrogen, 1_000)]), ..importer("W1N1") },
+            with_surplus("W2N1", MIN_MINERAL_SEND - 1),
+            with_surplus("W6N1", 5_000),
+            with_surplus("W4N1", 300)
+        ];
+
+        let mut busy = HashSet::new();
+        balance_minerals(&terminals, &mut busy, &mut network);
+        assert_eq!(network.sends, vec![(room("W4N1"), ResourceType::Hydrogen, 300, room("W1N1"))]);
+        assert_eq!(busy, HashSet::from([room("W4N1")]));
+    }
+
+    #[test]
+    fn terminals_send_once_per_tick() {
+        let mut network = FakeTerminalNetwork::default();
+        let terminals = [
+            NetworkTerminal { missing: HashMap::from([(ResourceType::Hydrogen, 1_000), (ResourceType::Oxygen, 1_000)]), ..importer("W1N1") },
+            NetworkTerminal { surplus: HashMap::from([(ResourceType::Hydrogen, 5_000), (ResourceType::Oxygen, 5_000)]), ..exporter("W2N1") }
+        ];
+
+        let mut busy = HashSet::new();
+        balance_minerals(&terminals, &mut busy, &mut network);
+        balance_energy(&terminals, &busy, &mut network);
+        assert_eq!(network.sends, vec![(room("W2N1"), ResourceType::Hydrogen, 1_000, room("W1N1"))]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use screeps::{ResourceType, RoomName, StructureTerminal, game};
+
+use crate::market::book::calc_transaction_cost;
+#[cfg(test)]
+use crate::market::book::FlatTransactionCost;
+
+// Game calls made while balancing the terminals
+pub trait TerminalNetwork {
+    fn distance(&self, from: RoomName, to: RoomName) -> u32;
+    fn transaction_cost(&self, amount: u32, from: RoomName, to: RoomName) -> u32;
+    fn send(&mut self, from: RoomName, resource: ResourceType, amount: u32, to: RoomName) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+pub struct GameTerminalNetwork {
+    pub terminals: HashMap<RoomName, StructureTerminal>
+}
+
+impl TerminalNetwork for GameTerminalNetwork {
+    fn distance(&self, from: RoomName, to: RoomName) -> u32 {
+        game::map::get_room_linear_distance(from, to, true)
+    }
+
+    fn transaction_cost(&self, amount: u32, from: RoomName, to: RoomName) -> u32 {
+        calc_transaction_cost(amount, from, to)
+    }
+
+    fn send(&mut self, from: RoomName, resource: ResourceType, amount: u32, to: RoomName) -> anyhow::Result<()> {
+        let terminal = self.terminals.get(&from).ok_or_else(|| anyhow!("No terminal in {from}"))?;
+        terminal.send(resource, amount, to, None).map_err(|err| anyhow!("Unable to send {amount} {resource} from {from} to {to}: {err}"))
+    }
+}
+
+// Records the sends instead of making them
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeTerminalNetwork {
+    pub transfer_cost: FlatTransactionCost,
+    pub sends: Vec<(RoomName, ResourceType, u32, RoomName)>
+}
+
+#[cfg(test)]
+impl TerminalNetwork for FakeTerminalNetwork {
+    fn distance(&self, from: RoomName, to: RoomName) -> u32 {
+        from.x_coord().abs_diff(to.x_coord()).max(from.y_coord().abs_diff(to.y_coord()))
+    }
+
+    fn transaction_cost(&self, amount: u32, _from: RoomName, _to: RoomName) -> u32 {
+        self.transfer_cost.of(amount)
+    }
+
+    fn send(&mut self, from: RoomName, resource: ResourceType, amount: u32, to: RoomName) -> anyhow::Result<()> {
+        self.sends.push((from, resource, amount, to));
+        Ok(())
+    }
+}
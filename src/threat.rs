@@ -0,0 +1,140 @@
+use std::iter;
+
+use itertools::Itertools;
+use log::{info, warn};
+use screeps::{ATTACK_POWER, BodyPart, Boost, CREEP_LIFE_TIME, Creep, DISMANTLE_POWER, HEAL_POWER, Part, RANGED_ATTACK_POWER, ResourceType, Room, StructureObject, TOWER_ENERGY_COST, TOWER_FALLOFF, TOWER_POWER_ATTACK, find, game};
+use serde::{Deserialize, Serialize};
+
+use crate::{domain_traits::EnergyStoreAccessors, memory::Memory, spawn::prototype::Body};
+
+// Hostiles which aren't seen again are forgotten once they would have died of old age
+const THREAT_MEMORY: u32 = CREEP_LIFE_TIME;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Threat {
+    // Damage per tick the hostiles deal to creeps
+    pub damage: u32,
+    // Part of the damage which is dealt in melee range
+    pub melee: u32,
+    // Dismantling only hurts structures, so it is left to the towers rather than the defenders
+    #[serde(default)]
+    pub dismantle: u32,
+    // Healing per tick, scaled up by the damage their boosted tough parts absorb
+    pub heal: u32,
+    // Damage the towers deal anywhere in the room
+    pub towers: u32,
+    pub last_seen: u32
+}
+
+impl Threat {
+    // Damage defenders have to add to the towers to outlast the hostiles and break through their healing
+    pub fn defense_needed(&self) -> u32 {
+        (self.damage + self.heal).saturating_sub(self.towers)
+    }
+
+    // Ranged defenders keep their distance from melee attackers, while ranged attackers are run down in melee
+    pub fn is_melee(&self) -> bool {
+        2 * self.melee > self.damage
+    }
+}
+
+// Damage and healing per tick the body adds to the defense, measured like the threat
+pub fn defense_strength(body: &Body) -> u32 {
+    body.part_count(Part::Attack) as u32 * ATTACK_POWER
+        + body.part_count(Part::RangedAttack) as u32 * RANGED_ATTACK_POWER
+        + body.part_count(Part::Heal) as u32 * HEAL_POWER
+}
+
+fn boost_multiplier(part: &BodyPart) -> u32 {
+    match part.boost().and_then(ResourceType::boost) {
+        Some(Boost::Attack(multiplier) | Boost::RangedAttack(multiplier) | Boost::Heal(multiplier) | Boost::Dismantle(multiplier)) => multiplier,
+        _ => 1
+    }
+}
+
+// Fraction of the damage taken which gets through the best boosted tough part
+fn damage_ratio(body: &[BodyPart]) -> f32 {
+    body.iter()
+        .filter(|part| part.part() == Part::Tough)
+        .filter_map(|part| match part.boost()?.boost()? {
+            Boost::Tough(ratio) => Some(ratio),
+            _ => None
+        })
+        .fold(1.0, f32::min)
+}
+
+fn assess(hostiles: &[Creep], towers: u32) -> Threat {
+    let mut threat = Threat { damage: 0, melee: 0, dismantle: 0, heal: 0, towers, last_seen: game::time() };
+
+    for hostile in hostiles {
+        let body = hostile.body().into_iter().filter(|part| part.hits() > 0).collect_vec();
+
+        let mut heal = 0;
+        for part in &body {
+            let power = |base: u32| base * boost_multiplier(part);
+            match part.part() {
+                Part::Attack => { threat.damage += power(ATTACK_POWER); threat.melee += power(ATTACK_POWER); },
+                Part::Work => threat.dismantle += power(DISMANTLE_POWER),
+                Part::RangedAttack => threat.damage += power(RANGED_ATTACK_POWER),
+                Part::Heal => heal += power(HEAL_POWER),
+                _ => {}
+            }
+        }
+
+        threat.heal += (heal as f32 / damage_ratio(&body)) as u32;
+    }
+
+    threat
+}
+
+// Towers are counted at their weakest, as if the hostiles kept to the edges of the room
+fn tower_damage(room: &Room) -> u32 {
+    let towers = room.find(find::MY_STRUCTURES, None).into_iter()
+        .filter_map(|structure| match structure {
+            StructureObject::StructureTower(tower) => Some(tower),
+            _ => None
+        })
+        .filter(|tower| tower.used_energy_capacity() >= TOWER_ENERGY_COST)
+        .count();
+
+    towers as u32 * (f64::from(TOWER_POWER_ATTACK) * (1.0 - TOWER_FALLOFF)) as u32
+}
+
+pub fn assess_threats(mem: &mut Memory) {
+    let rooms = mem.colonies.view_all()
+        .flat_map(|colony| iter::once(colony.name).chain(colony.remotes.active().map(|remote| remote.room)).collect_vec())
+        .collect_vec();
+
+    mem.threats.retain(|room, threat| rooms.contains(room) && game::time() < threat.last_seen + THREAT_MEMORY);
+
+    for name in rooms {
+        let Some(room) = game::rooms().get(name) else { continue; };
+
+        let threat = assess(&room.find(find::HOSTILE_CREEPS, None), tower_damage(&room));
+        if threat.damage + threat.dismantle + threat.heal == 0 {
+            if mem.threats.remove(&name).is_some() { info!("{name} is clear of hostiles"); }
+            continue;
+        }
+
+        if !mem.threats.contains_key(&name) {
+            warn!("Hostiles in {name} dealing {} damage, {} dismantling and {} healing per tick", threat.damage, threat.dismantle, threat.heal);
+        }
+
+        mem.threats.insert(name, threat);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dismantling_needs_no_defenders() {
+        let threat = Threat { damage: 0, melee: 0, dismantle: 5 * DISMANTLE_POWER, heal: 0, towers: 0, last_seen: 0 };
+        assert_eq!(threat.defense_needed(), 0);
+
+        let threat = Threat { damage: ATTACK_POWER, melee: ATTACK_POWER, ..threat };
+        assert_eq!(threat.defense_needed(), ATTACK_POWER);
+        assert!(threat.is_melee());
+    }
+}
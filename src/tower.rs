@@ -1,6 +1,8 @@
 use log::error;
 use screeps::{StructureObject, StructureTower, find, game, prelude::*};
 
+use crate::structure::repair_target_hits;
+
 const FIX_THRESHOLD: f32 = 0.35;
 
 pub fn do_towers() {
@@ -38,7 +40,7 @@ fn do_tower(tower: &StructureTower) -> Option<()> {
     let structures = room.find(find::STRUCTURES, None);
     let repairable = structures.iter()
         .filter_map(|structure| structure.as_repairable().map(|repairable| (repairable, structure)))
-        .filter(|(repairable, _)| repairable.hits() < (repairable.hits_max() as f32 * FIX_THRESHOLD) as u32)
+        .filter(|(repairable, structure)| repairable.hits() < (repair_target_hits(structure) as f32 * FIX_THRESHOLD) as u32)
         .min_by_key(|(_, structure)| tower.pos().get_range_to(structure.pos()))
         .map(|(repairable, _)| repairable);
     if let Some(repairable) = repairable {
@@ -5,7 +5,8 @@ use screeps::{RoomName, RoomVisual};
 #[derive(Hash, PartialEq, Eq, Clone, Copy)]
 pub enum RoomDrawerType {
     Plan,
-    Diff
+    Diff,
+    Traffic
 }
 
 pub type Drawer = Box<dyn FnMut(&RoomVisual)>;